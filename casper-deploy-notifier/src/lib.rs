@@ -18,6 +18,7 @@ type BoxedEventStream = BoxStream<'static, Result<Event, EventStreamError<reqwes
 pub struct DeployNotifier {
     url: String,
     event_stream: Option<BoxedEventStream>,
+    block_heights: Option<mpsc::Sender<u64>>,
 }
 
 impl Default for DeployNotifier {
@@ -26,6 +27,7 @@ impl Default for DeployNotifier {
         Self {
             url,
             event_stream: None,
+            block_heights: None,
         }
     }
 }
@@ -35,9 +37,17 @@ impl DeployNotifier {
         DeployNotifier {
             url: url.to_string(),
             event_stream: None,
+            block_heights: None,
         }
     }
 
+    /// Also send the height of every block added to the chain to `tx`.
+    /// Heights are dropped while `tx` is full, they are only meant as a wake-up signal.
+    pub fn with_block_heights(mut self, tx: mpsc::Sender<u64>) -> Self {
+        self.block_heights = Some(tx);
+        self
+    }
+
     pub async fn connect(&mut self) -> Result<(), SseError> {
         // Connect to SSE endpoint.
         let client = reqwest::Client::new();
@@ -81,6 +91,11 @@ impl DeployNotifier {
             match data {
                 SseData::ApiVersion(_) => Err(SseError::UnexpectedHandshake)?,
                 SseData::Other(_) => {}
+                SseData::BlockAdded(block_added) => {
+                    if let Some(block_heights) = &self.block_heights {
+                        let _ = block_heights.try_send(block_added.block.header.height);
+                    }
+                }
                 SseData::DeployProcessed(event_details) => {
                     let notification = event_details.into();
                     if let Err(_e) = tx.send(notification).await {
@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 pub enum SseData {
     /// The version of node's API. First event to receive, used for handshake.
     ApiVersion(casper_types::ProtocolVersion),
+    /// A block has been added to the linear chain.
+    BlockAdded(BlockAdded),
     /// The given deploy has been executed, committed and forms part of the given block.
     DeployProcessed(DeployProcessed),
     /// The node is about to shut down.
@@ -23,3 +25,20 @@ pub struct DeployProcessed {
     pub account: Box<casper_types::PublicKey>,
     pub execution_result: Box<casper_types::ExecutionResult>,
 }
+
+/// Only the fields we need are parsed, the rest of the block is ignored.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct BlockAdded {
+    pub block_hash: String,
+    pub block: AddedBlock,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct AddedBlock {
+    pub header: AddedBlockHeader,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct AddedBlockHeader {
+    pub height: u64,
+}
@@ -1,3 +1,5 @@
+use std::collections::BTreeSet;
+
 use casper_types::AsymmetricType;
 
 use crate::sse_types::DeployProcessed;
@@ -7,21 +9,45 @@ pub struct Notification {
     pub deploy_hash: String,
     pub public_key: String,
     pub success: bool,
+    /// Why the deploy failed, `None` if it succeeded.
+    pub error_message: Option<String>,
+    /// Formatted keys read or written by the deploy, e.g. `hash-<contract hash>`.
+    pub touched_keys: BTreeSet<String>,
+}
+
+impl Notification {
+    /// Returns true if the deploy read or wrote `key`, given in its formatted form.
+    /// Calling a stored contract reads `hash-<contract hash>`.
+    pub fn touches(&self, key: &str) -> bool {
+        self.touched_keys.contains(key)
+    }
 }
 
 impl From<DeployProcessed> for Notification {
     fn from(event_details: DeployProcessed) -> Self {
-        let success = match *event_details.execution_result {
-            casper_types::ExecutionResult::Failure { .. } => false,
-            casper_types::ExecutionResult::Success { .. } => true,
+        let (error_message, effect) = match *event_details.execution_result {
+            casper_types::ExecutionResult::Failure {
+                effect,
+                error_message,
+                ..
+            } => (Some(error_message), effect),
+            casper_types::ExecutionResult::Success { effect, .. } => (None, effect),
         };
+        let success = error_message.is_none();
         let deploy_hash = base16::encode_lower(event_details.deploy_hash.as_bytes());
         let public_key = event_details.account.to_hex();
+        let touched_keys = effect
+            .transforms
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
 
         Notification {
             deploy_hash,
             public_key,
             success,
+            error_message,
+            touched_keys,
         }
     }
 }
@@ -160,6 +160,11 @@ mod tests {
                 "016acb4cfa2ec31ea67ca53c1f93c77dba6740c463968ac550466723dc2cbaa421"
             );
             assert!(notification.success);
+            assert_eq!(notification.error_message, None);
+            assert!(notification
+                .touches("hash-d2469afeb99130f0be7c9ce230a84149e6d756e306ef8cf5b8a49d5182e41676"));
+            assert!(!notification
+                .touches("hash-0000000000000000000000000000000000000000000000000000000000000000"));
         } else {
             panic!("Expected a notification, but none was received");
         }
@@ -203,4 +208,31 @@ mod tests {
         let maybe_notification = rx.recv().await;
         assert!(maybe_notification.is_none());
     }
+
+    #[tokio::test]
+    async fn test_block_heights() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/")
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "data: {\"ApiVersion\": \"1.5.6\"}\n\n",
+                "data: {\"BlockAdded\": {\"block_hash\":\"90145cba9e25adf02f7acbab7e85b8a46e15ad86f28c5717dd4d548c5f14c908\",\"block\": {\"hash\":\"90145cba9e25adf02f7acbab7e85b8a46e15ad86f28c5717dd4d548c5f14c908\",\"header\": {\"parent_hash\":\"926f7c831d6313b1359a64ae01845e678da337381f228f1be7230476e594899e\",\"era_id\":42,\"height\":1234,\"protocol_version\":\"1.5.6\"},\"body\": {\"proposer\":\"016acb4cfa2ec31ea67ca53c1f93c77dba6740c463968ac550466723dc2cbaa421\",\"deploy_hashes\": [],\"transfer_hashes\": []},\"proofs\": []}}}\n\n",
+                "data: \"Shutdown\"\n\n"
+            ))
+            .create_async()
+            .await;
+
+        let (block_tx, mut block_rx) = mpsc::channel(1);
+        let mut notifier = DeployNotifier::new(&server.url()).with_block_heights(block_tx);
+        notifier.connect().await.unwrap();
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let result = notifier.run(tx).await;
+        assert!(matches!(result, Err(SseError::NodeShutdown)));
+
+        assert_eq!(block_rx.recv().await, Some(1234));
+        // Blocks are not deploy notifications.
+        assert!(rx.recv().await.is_none());
+    }
 }
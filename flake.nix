@@ -149,6 +149,7 @@
                 ./kairos-test-utils
                 ./kairos-tx
                 ./kairos-prover/kairos-circuit-logic
+                ./kairos-prover/kairos-light-client
                 ./kairos-prover/kairos-verifier-risc0-lib
                 ./kairos-contracts/demo-contract/contract-utils
                 ./testdata
@@ -7,9 +7,11 @@ use kairos_crypto::error::CryptoError;
 use kairos_crypto::implementations::Signer;
 use kairos_crypto::SignerCore;
 use kairos_crypto::SignerFsExtension;
+use kairos_crypto::SignerTxExtension;
 
 use clap::Parser;
 use kairos_server::routes::{transfer::TransferPath, PayloadBody};
+use kairos_server::state::transaction_status::TransactionReceipt;
 use kairos_tx::asn::{SigningPayload, Transfer};
 use reqwest::Url;
 
@@ -36,23 +38,26 @@ pub fn run(args: Args, kairos_server_address: Url) -> Result<String, CliError> {
         Some(nonce) => nonce,
     };
 
-    // TODO: Create transaction and sign it with `signer`.
+    let transaction =
+        signer.sign_tx_payload(SigningPayload::new(nonce, Transfer::new(recipient, amount)))?;
 
     // TODO: Send transaction to the network, using Rust SDK.
     let res = reqwest::blocking::Client::new()
         .post(kairos_server_address.join(TransferPath::PATH).unwrap())
         .json(&PayloadBody {
             public_key: signer_public_key,
-            payload: SigningPayload::new(nonce, Transfer::new(recipient, amount))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
+            payload: transaction.payload.der_encode()?,
+            signature: transaction.signature.into(),
         })
         .send()
         .map_err(KairosClientError::from)?;
 
     if res.status().is_success() {
-        Ok("Transfer successfully sent to L2".to_string())
+        let receipt: TransactionReceipt = res.json().map_err(KairosClientError::from)?;
+        Ok(format!(
+            "Transfer successfully sent to L2, transaction hash: {}",
+            receipt.hash
+        ))
     } else {
         Err(KairosClientError::ResponseErrorWithCode(
             res.status().as_u16(),
@@ -5,11 +5,12 @@ use crate::error::CliError;
 use axum_extra::routing::TypedPath;
 use kairos_crypto::error::CryptoError;
 use kairos_crypto::implementations::Signer;
-use kairos_crypto::{SignerCore, SignerFsExtension};
+use kairos_crypto::{SignerCore, SignerFsExtension, SignerTxExtension};
 
 use clap::Parser;
 use kairos_server::routes::withdraw::WithdrawPath;
 use kairos_server::routes::PayloadBody;
+use kairos_server::state::transaction_status::TransactionReceipt;
 use kairos_tx::asn::{SigningPayload, Withdrawal};
 use reqwest::Url;
 
@@ -33,23 +34,26 @@ pub fn run(args: Args, kairos_server_address: Url) -> Result<String, CliError> {
         Some(nonce) => nonce,
     };
 
-    // TODO: Create transaction and sign it with `signer`.
+    let transaction =
+        signer.sign_tx_payload(SigningPayload::new(nonce, Withdrawal::new(amount)))?;
 
     // TODO: Send transaction to the network, using Rust SDK.
     let res = reqwest::blocking::Client::new()
         .post(kairos_server_address.join(WithdrawPath::PATH).unwrap())
         .json(&PayloadBody {
             public_key: signer_public_key,
-            payload: SigningPayload::new(nonce, Withdrawal::new(amount))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
+            payload: transaction.payload.der_encode()?,
+            signature: transaction.signature.into(),
         })
         .send()
         .map_err(KairosClientError::from)?;
 
     if res.status().is_success() {
-        Ok("Withdrawal successfully sent to L2".to_string())
+        let receipt: TransactionReceipt = res.json().map_err(KairosClientError::from)?;
+        Ok(format!(
+            "Withdrawal successfully sent to L2, transaction hash: {}",
+            receipt.hash
+        ))
     } else {
         Err(KairosClientError::ResponseErrorWithCode(
             res.status().as_u16(),
@@ -1,5 +1,6 @@
 use crate::client::KairosClientError;
 use kairos_crypto::error::CryptoError;
+use kairos_tx::error::TxError;
 
 use hex::FromHexError;
 use thiserror::Error;
@@ -18,6 +19,12 @@ pub enum CliError {
         #[from]
         error: CryptoError,
     },
+    /// Transaction encoding error.
+    #[error("transaction error: {error}")]
+    TransactionError {
+        #[from]
+        error: TxError,
+    },
     /// Failed to parse hex string.
     #[error("failed to parse hex string: {error}")]
     ParseError {
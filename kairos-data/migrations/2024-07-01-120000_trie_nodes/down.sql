@@ -0,0 +1,2 @@
+DROP TABLE trie_roots;
+DROP TABLE trie_nodes;
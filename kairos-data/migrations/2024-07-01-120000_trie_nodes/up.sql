@@ -0,0 +1,10 @@
+CREATE TABLE trie_nodes (
+    hash bytea PRIMARY KEY,
+    node bytea NOT NULL
+);
+
+CREATE TABLE trie_roots (
+    id bigserial PRIMARY KEY,
+    root bytea,
+    committed_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,2 @@
+DROP TABLE batches;
+DROP TYPE batch_status;
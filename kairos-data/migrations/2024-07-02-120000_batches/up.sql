@@ -0,0 +1,14 @@
+CREATE TYPE batch_status AS ENUM ('pending', 'proving', 'proved', 'submitted', 'finalized', 'failed');
+CREATE TABLE batches (
+    id bigserial PRIMARY KEY,
+    status batch_status NOT NULL DEFAULT 'pending',
+    old_root varchar,
+    new_root varchar,
+    transactions jsonb NOT NULL,
+    deploy_hash varchar,
+    error varchar,
+    created_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at timestamp NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    proving_started_at timestamp,
+    proving_finished_at timestamp
+);
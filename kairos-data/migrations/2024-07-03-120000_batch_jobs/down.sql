@@ -0,0 +1,4 @@
+ALTER TABLE batches
+    DROP COLUMN attempts,
+    DROP COLUMN receipt,
+    DROP COLUMN proof_inputs;
@@ -0,0 +1,4 @@
+ALTER TABLE batches
+    ADD COLUMN proof_inputs jsonb,
+    ADD COLUMN receipt jsonb,
+    ADD COLUMN attempts integer NOT NULL DEFAULT 0;
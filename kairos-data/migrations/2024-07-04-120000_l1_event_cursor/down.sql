@@ -0,0 +1,4 @@
+ALTER TABLE trie_roots
+    DROP COLUMN next_event_index;
+ALTER TABLE transactions
+    DROP COLUMN event_index;
@@ -0,0 +1,4 @@
+ALTER TABLE transactions
+    ADD COLUMN event_index bigint UNIQUE;
+ALTER TABLE trie_roots
+    ADD COLUMN next_event_index bigint;
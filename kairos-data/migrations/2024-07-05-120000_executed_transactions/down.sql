@@ -0,0 +1,5 @@
+DROP INDEX transactions_batch_id;
+ALTER TABLE transactions
+    DROP COLUMN batch_id,
+    DROP COLUMN seq;
+DROP SEQUENCE transactions_seq;
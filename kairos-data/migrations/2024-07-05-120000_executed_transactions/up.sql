@@ -0,0 +1,10 @@
+-- Transactions are recorded by the trie thread once it executed them,
+-- `seq` is the order they were executed in and `batch_id` the batch they were committed in.
+-- Rows stored before this migration keep a NULL `seq` and are not replayed.
+CREATE SEQUENCE transactions_seq;
+ALTER TABLE transactions
+    ADD COLUMN seq bigint UNIQUE,
+    ADD COLUMN batch_id bigint REFERENCES batches (id);
+ALTER TABLE transactions
+    ALTER COLUMN seq SET DEFAULT nextval('transactions_seq');
+CREATE INDEX transactions_batch_id ON transactions (batch_id);
@@ -0,0 +1,221 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use kairos_circuit_logic::ProofInputs;
+
+use crate::errors::DBError;
+use crate::schema::batches;
+
+/// The stage of the proving pipeline a batch is in.
+///
+/// A batch moves through `Pending -> Proving -> Proved -> Submitted -> Finalized`.
+/// `Failed` means the last attempt failed, the batch is retried from its stored progress.
+#[derive(diesel_derive_enum::DbEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[ExistingTypePath = "crate::schema::sql_types::BatchStatus"]
+#[serde(rename_all = "lowercase")]
+pub enum BatchStatus {
+    Pending,
+    Proving,
+    Proved,
+    Submitted,
+    Finalized,
+    Failed,
+}
+
+/// A committed batch, `id` is the sequence number of the batch.
+///
+/// The batches table doubles as the durable queue of the batch output handler,
+/// `proof_inputs` and `receipt` allow it to resume a batch after a restart.
+/// They are large, so they are left out of the JSON representation.
+#[derive(Queryable, Debug, Identifiable, Serialize, Selectable, Deserialize)]
+#[diesel(table_name = batches)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Batch {
+    pub id: i64,
+    pub status: BatchStatus,
+    /// Hex encoded trie root before the batch, `None` if the trie was empty.
+    pub old_root: Option<String>,
+    /// Hex encoded trie root after the batch, `None` if the trie is empty.
+    pub new_root: Option<String>,
+    pub transactions: serde_json::Value,
+    /// Hex encoded hash of the deploy that submitted the proof to L1.
+    pub deploy_hash: Option<String>,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub proving_started_at: Option<NaiveDateTime>,
+    pub proving_finished_at: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub proof_inputs: Option<serde_json::Value>,
+    #[serde(skip)]
+    pub receipt: Option<serde_json::Value>,
+    /// The number of failed attempts to prove and submit the batch.
+    pub attempts: i32,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = batches)]
+pub struct NewBatch {
+    pub old_root: Option<String>,
+    pub new_root: Option<String>,
+    pub transactions: serde_json::Value,
+    pub proof_inputs: serde_json::Value,
+}
+
+impl NewBatch {
+    pub fn new(
+        old_root: Option<[u8; 32]>,
+        new_root: Option<[u8; 32]>,
+        proof_inputs: &ProofInputs,
+    ) -> Result<Self, DBError> {
+        Ok(Self {
+            old_root: old_root.map(hex::encode),
+            new_root: new_root.map(hex::encode),
+            transactions: serde_json::to_value(&proof_inputs.transactions)?,
+            proof_inputs: serde_json::to_value(proof_inputs)?,
+        })
+    }
+}
+
+/// A change to a stored batch, fields that are `None` are left untouched.
+#[derive(AsChangeset, Debug)]
+#[diesel(table_name = batches)]
+pub struct BatchUpdate {
+    pub status: BatchStatus,
+    pub updated_at: NaiveDateTime,
+    pub deploy_hash: Option<String>,
+    pub proving_started_at: Option<NaiveDateTime>,
+    pub proving_finished_at: Option<NaiveDateTime>,
+    pub receipt: Option<serde_json::Value>,
+}
+
+impl BatchUpdate {
+    pub fn status(status: BatchStatus) -> Self {
+        Self {
+            status,
+            updated_at: Utc::now().naive_utc(),
+            deploy_hash: None,
+            proving_started_at: None,
+            proving_finished_at: None,
+            receipt: None,
+        }
+    }
+
+    pub fn proving() -> Self {
+        Self {
+            proving_started_at: Some(Utc::now().naive_utc()),
+            ..Self::status(BatchStatus::Proving)
+        }
+    }
+
+    pub fn proved(receipt: serde_json::Value) -> Self {
+        Self {
+            proving_finished_at: Some(Utc::now().naive_utc()),
+            receipt: Some(receipt),
+            ..Self::status(BatchStatus::Proved)
+        }
+    }
+
+    pub fn submitted(deploy_hash: String) -> Self {
+        Self {
+            deploy_hash: Some(deploy_hash),
+            ..Self::status(BatchStatus::Submitted)
+        }
+    }
+}
+
+/// Inserts a batch with the status `pending` and returns its sequence number.
+/// This is synchronous so it can share a transaction with the trie root, see `TrieDb`.
+pub fn insert(conn: &mut PgConnection, batch: NewBatch) -> QueryResult<i64> {
+    diesel::insert_into(batches::table)
+        .values(batch)
+        .returning(batches::id)
+        .get_result(conn)
+}
+
+pub async fn update(pool: &crate::Pool, id: i64, update: BatchUpdate) -> Result<(), DBError> {
+    let conn = pool.get().await?;
+    conn.interact(move |conn| {
+        diesel::update(batches::table.find(id))
+            .set(update)
+            .execute(conn)
+    })
+    .await??;
+    Ok(())
+}
+
+/// Records a failed attempt, the batch keeps its stored progress and will be retried.
+pub async fn record_failure(pool: &crate::Pool, id: i64, error: String) -> Result<(), DBError> {
+    let conn = pool.get().await?;
+    conn.interact(move |conn| {
+        diesel::update(batches::table.find(id))
+            .set((
+                batches::status.eq(BatchStatus::Failed),
+                batches::error.eq(error),
+                batches::attempts.eq(batches::attempts + 1),
+                batches::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+    })
+    .await??;
+    Ok(())
+}
+
+pub async fn get(pool: &crate::Pool, id: i64) -> Result<Option<Batch>, DBError> {
+    let conn = pool.get().await?;
+    let res = conn
+        .interact(move |conn| {
+            batches::table
+                .find(id)
+                .select(Batch::as_select())
+                .first::<Batch>(conn)
+                .optional()
+        })
+        .await??;
+    Ok(res)
+}
+
+/// Returns the most recent batches, newest first.
+pub async fn get_latest(pool: &crate::Pool, limit: i64) -> Result<Vec<Batch>, DBError> {
+    let conn = pool.get().await?;
+    let res = conn
+        .interact(move |conn| {
+            batches::table
+                .select(Batch::as_select())
+                .order(batches::id.desc())
+                .limit(limit)
+                .load::<Batch>(conn)
+        })
+        .await??;
+    Ok(res)
+}
+
+/// Returns the batches that have not been finalized yet, in the order they were committed.
+pub async fn get_unfinished(pool: &crate::Pool) -> Result<Vec<Batch>, DBError> {
+    let conn = pool.get().await?;
+    let res = conn
+        .interact(|conn| {
+            batches::table
+                .select(Batch::as_select())
+                .filter(batches::status.ne(BatchStatus::Finalized))
+                .order(batches::id.asc())
+                .load::<Batch>(conn)
+        })
+        .await??;
+    Ok(res)
+}
+
+/// Returns the sequence number and the hex encoded new trie root of every batch, in the order they were committed.
+pub async fn get_roots(pool: &crate::Pool) -> Result<Vec<(i64, Option<String>)>, DBError> {
+    let conn = pool.get().await?;
+    let res = conn
+        .interact(|conn| {
+            batches::table
+                .select((batches::id, batches::new_root))
+                .order(batches::id.asc())
+                .load::<(i64, Option<String>)>(conn)
+        })
+        .await??;
+    Ok(res)
+}
@@ -1,6 +1,7 @@
 use deadpool_diesel::Error as DDError;
 use deadpool_diesel::InteractError as DDIError;
 use diesel::result::Error as DieselError;
+use diesel::ConnectionError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,4 +14,10 @@ pub enum DBError {
     PoolError(#[from] deadpool::managed::PoolError<deadpool_diesel::Error>),
     #[error("Diesel error: {0}")]
     DieselError(#[from] DieselError),
+    #[error("Failed to establish a database connection: {0}")]
+    EstablishError(#[from] ConnectionError),
+    #[error("Failed to (de)serialize stored data: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error("Stored data is corrupted: {0}")]
+    CorruptedData(String),
 }
@@ -8,9 +8,11 @@ use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 pub use deadpool_diesel::postgres::Pool;
 pub use diesel::{insert_into, prelude};
 
+pub mod batch;
 pub mod errors;
 pub mod schema;
 pub mod transaction;
+pub mod trie;
 
 #[cfg(feature = "migrations")]
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
@@ -1,11 +1,37 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "batch_status"))]
+    pub struct BatchStatus;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "transaction"))]
     pub struct Transaction;
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::BatchStatus;
+
+    batches (id) {
+        id -> Int8,
+        status -> BatchStatus,
+        old_root -> Nullable<Varchar>,
+        new_root -> Nullable<Varchar>,
+        transactions -> Jsonb,
+        deploy_hash -> Nullable<Varchar>,
+        error -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        proving_started_at -> Nullable<Timestamp>,
+        proving_finished_at -> Nullable<Timestamp>,
+        proof_inputs -> Nullable<Jsonb>,
+        receipt -> Nullable<Jsonb>,
+        attempts -> Int4,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::Transaction;
@@ -17,5 +43,28 @@ diesel::table! {
         trx -> Transaction,
         amount -> Numeric,
         recipient -> Nullable<Varchar>,
+        event_index -> Nullable<Int8>,
+        seq -> Nullable<Int8>,
+        batch_id -> Nullable<Int8>,
+    }
+}
+
+diesel::table! {
+    trie_nodes (hash) {
+        hash -> Bytea,
+        node -> Bytea,
     }
 }
+
+diesel::table! {
+    trie_roots (id) {
+        id -> Int8,
+        root -> Nullable<Bytea>,
+        committed_at -> Timestamp,
+        next_event_index -> Nullable<Int8>,
+    }
+}
+
+diesel::joinable!(transactions -> batches (batch_id));
+
+diesel::allow_tables_to_appear_in_same_query!(batches, transactions, trie_nodes, trie_roots,);
@@ -1,5 +1,7 @@
+use std::ops::RangeInclusive;
+
 use crate::schema::transactions;
-use bigdecimal::BigDecimal;
+use bigdecimal::{BigDecimal, ToPrimitive};
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
 use hex;
@@ -9,6 +11,8 @@ use kairos_circuit_logic::transactions::{
     KairosTransaction, L1Deposit, Signed, Transfer, Withdraw,
 };
 
+use crate::errors::DBError;
+
 #[derive(diesel_derive_enum::DbEnum, Debug, Serialize, Deserialize)]
 #[ExistingTypePath = "crate::schema::sql_types::Transaction"]
 pub enum Transaction {
@@ -28,6 +32,13 @@ pub struct Transactions {
     pub trx: Transaction,
     pub amount: BigDecimal,
     pub recipient: Option<String>,
+    /// The index of the contract event that emitted a deposit, `None` for L2 transactions.
+    pub event_index: Option<i64>,
+    /// The order the trie thread executed the transaction in, assigned by the database.
+    /// `None` for rows stored before execution was recorded, they are not replayed.
+    pub seq: Option<i64>,
+    /// The batch the transaction was committed in, `None` while it's in the current batch.
+    pub batch_id: Option<i64>,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -81,22 +92,92 @@ pub async fn get(
     Ok(res)
 }
 
-pub async fn insert(
-    pool: &crate::Pool,
-    kairos_trx: KairosTransaction,
-) -> Result<Transactions, crate::errors::DBError> {
-    let trx = Transactions::from(kairos_trx);
+/// Returns every executed transaction in the order the trie thread executed it.
+pub async fn get_executed(pool: &crate::Pool) -> Result<Vec<Transactions>, DBError> {
     let conn = pool.get().await?;
     let res = conn
         .interact(|conn| {
-            diesel::insert_into(transactions::table)
-                .values(trx)
-                .get_result::<Transactions>(conn)
+            transactions::table
+                .select(Transactions::as_select())
+                .filter(transactions::seq.is_not_null())
+                .order(transactions::seq.asc())
+                .load::<Transactions>(conn)
         })
         .await??;
     Ok(res)
 }
 
+/// Returns the executed transactions that are not in a batch yet, in the order they were executed.
+/// This is synchronous so it can use the connection of the trie thread, see `TrieDb`.
+pub fn get_unbatched(conn: &mut PgConnection) -> QueryResult<Vec<Transactions>> {
+    transactions::table
+        .select(Transactions::as_select())
+        .filter(transactions::seq.is_not_null())
+        .filter(transactions::batch_id.is_null())
+        .order(transactions::seq.asc())
+        .load::<Transactions>(conn)
+}
+
+/// Records a transaction the trie thread executed, `event_index` is the contract event of a deposit.
+/// Returns the order it was executed in, `None` if its deposit is already in a batch.
+///
+/// A deposit that is not in a batch yet may be credited again after a restart,
+/// its stale row is replaced so it's replayed in the order it was executed last.
+/// This is synchronous so it can use the connection of the trie thread, see `TrieDb`.
+pub fn insert_executed(
+    conn: &mut PgConnection,
+    kairos_trx: KairosTransaction,
+    event_index: Option<u32>,
+) -> QueryResult<Option<i64>> {
+    let trx = Transactions {
+        event_index: event_index.map(i64::from),
+        ..Transactions::from(kairos_trx)
+    };
+    conn.transaction(|conn| {
+        if let Some(event_index) = trx.event_index {
+            diesel::delete(transactions::table)
+                .filter(transactions::event_index.eq(event_index))
+                .filter(transactions::batch_id.is_null())
+                .execute(conn)?;
+        }
+
+        let seq = diesel::insert_into(transactions::table)
+            .values(trx)
+            .on_conflict(transactions::event_index)
+            .do_nothing()
+            .returning(transactions::seq)
+            .get_result::<Option<i64>>(conn)
+            .optional()?;
+        Ok(seq.flatten())
+    })
+}
+
+/// Assigns the executed transactions with a sequence number in `seqs` to the batch `batch_id`.
+pub fn assign_batch(
+    conn: &mut PgConnection,
+    batch_id: i64,
+    seqs: RangeInclusive<i64>,
+) -> QueryResult<usize> {
+    diesel::update(transactions::table)
+        .filter(transactions::seq.between(*seqs.start(), *seqs.end()))
+        .filter(transactions::batch_id.is_null())
+        .set(transactions::batch_id.eq(batch_id))
+        .execute(conn)
+}
+
+impl Transactions {
+    /// The index of the contract event that emitted a deposit, `None` for L2 transactions.
+    pub fn event_index(&self) -> Result<Option<u32>, DBError> {
+        self.event_index
+            .map(|index| {
+                u32::try_from(index).map_err(|_| {
+                    DBError::CorruptedData(format!("event index out of range: {index}"))
+                })
+            })
+            .transpose()
+    }
+}
+
 impl From<KairosTransaction> for Transactions {
     fn from(tx: KairosTransaction) -> Self {
         match tx {
@@ -116,6 +197,9 @@ impl From<Signed<Transfer>> for Transactions {
             trx: Transaction::Transfer,
             amount: BigDecimal::from(signed_transfer.transaction.amount),
             recipient: Some(hex::encode(&signed_transfer.transaction.recipient)),
+            event_index: None,
+            seq: None,
+            batch_id: None,
         }
     }
 }
@@ -129,6 +213,9 @@ impl From<Signed<Withdraw>> for Transactions {
             trx: Transaction::Withdrawal,
             amount: BigDecimal::from(signed_withdraw.transaction.amount),
             recipient: None,
+            event_index: None,
+            seq: None,
+            batch_id: None,
         }
     }
 }
@@ -142,6 +229,54 @@ impl From<L1Deposit> for Transactions {
             trx: Transaction::Deposit,
             amount: BigDecimal::from(deposit.amount),
             recipient: Some(hex::encode(&deposit.recipient)),
+            event_index: None,
+            seq: None,
+            batch_id: None,
         }
     }
 }
+
+impl TryFrom<Transactions> for KairosTransaction {
+    type Error = DBError;
+
+    fn try_from(trx: Transactions) -> Result<Self, Self::Error> {
+        let public_key = decode_hex(&trx.public_key)?;
+        let amount = trx.amount.to_u64().ok_or_else(|| {
+            DBError::CorruptedData(format!("amount out of range: {}", trx.amount))
+        })?;
+        let nonce = || {
+            trx.nonce
+                .as_ref()
+                .and_then(ToPrimitive::to_u64)
+                .ok_or_else(|| DBError::CorruptedData(format!("invalid nonce: {:?}", trx.nonce)))
+        };
+
+        Ok(match trx.trx {
+            Transaction::Deposit => KairosTransaction::Deposit(L1Deposit {
+                recipient: public_key,
+                amount,
+            }),
+            Transaction::Transfer => {
+                let recipient = trx
+                    .recipient
+                    .as_deref()
+                    .ok_or_else(|| DBError::CorruptedData("transfer without recipient".into()))
+                    .and_then(decode_hex)?;
+                KairosTransaction::Transfer(Signed {
+                    public_key,
+                    nonce: nonce()?,
+                    transaction: Transfer { recipient, amount },
+                })
+            }
+            Transaction::Withdrawal => KairosTransaction::Withdraw(Signed {
+                public_key,
+                nonce: nonce()?,
+                transaction: Withdraw { amount },
+            }),
+        })
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>, DBError> {
+    hex::decode(value).map_err(|err| DBError::CorruptedData(format!("invalid hex {value}: {err}")))
+}
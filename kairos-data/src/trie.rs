@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+
+use diesel::prelude::*;
+use diesel::PgConnection;
+use serde::{de::DeserializeOwned, Serialize};
+
+use kairos_circuit_logic::transactions::KairosTransaction;
+use kairos_trie::{
+    stored::{DatabaseGet, DatabaseSet},
+    Branch, Leaf, Node, NodeHash,
+};
+
+use crate::batch::{self, NewBatch};
+use crate::errors::DBError;
+use crate::schema::{trie_nodes, trie_roots};
+use crate::transaction::{self, Transactions};
+
+/// A persistent store for the nodes of a `kairos_trie` merkle trie.
+///
+/// The trie is accessed synchronously from the trie state thread,
+/// so unlike the rest of this crate it owns a plain `PgConnection` instead of using the pool.
+/// Nodes are content addressed by their hash, so writes are idempotent.
+pub struct TrieDb<V> {
+    conn: RefCell<PgConnection>,
+    _value: PhantomData<V>,
+}
+
+impl<V> TrieDb<V> {
+    pub fn connect(conn_str: &str) -> Result<Self, DBError> {
+        let conn = PgConnection::establish(conn_str)?;
+
+        Ok(Self {
+            conn: RefCell::new(conn),
+            _value: PhantomData,
+        })
+    }
+
+    /// Returns the root of the most recently committed trie.
+    /// `None` is returned if the trie is empty or nothing has been committed yet.
+    pub fn last_committed_root(&self) -> Result<Option<[u8; 32]>, DBError> {
+        let root = trie_roots::table
+            .select(trie_roots::root)
+            .order(trie_roots::id.desc())
+            .first::<Option<Vec<u8>>>(&mut *self.conn.borrow_mut())
+            .optional()?
+            .flatten();
+
+        root.map(|root| {
+            <[u8; 32]>::try_from(root).map_err(|root| {
+                DBError::CorruptedData(format!("invalid trie root: {}", hex::encode(root)))
+            })
+        })
+        .transpose()
+    }
+
+    /// Returns the index of the next contract event to credit, as of the most recently committed trie.
+    /// `None` is returned if nothing has been committed yet, or the root was committed without it.
+    pub fn next_event_index(&self) -> Result<Option<u32>, DBError> {
+        let next_event_index = trie_roots::table
+            .select(trie_roots::next_event_index)
+            .order(trie_roots::id.desc())
+            .first::<Option<i64>>(&mut *self.conn.borrow_mut())
+            .optional()?
+            .flatten();
+
+        next_event_index
+            .map(|index| {
+                u32::try_from(index).map_err(|_| {
+                    DBError::CorruptedData(format!("invalid next event index: {index}"))
+                })
+            })
+            .transpose()
+    }
+
+    /// Records `root` as the latest committed trie root,
+    /// along with the index of the next contract event it has not credited.
+    /// All nodes reachable from `root` must already be stored.
+    pub fn insert_committed_root(
+        &self,
+        root: Option<[u8; 32]>,
+        next_event_index: Option<u32>,
+    ) -> Result<(), DBError> {
+        diesel::insert_into(trie_roots::table)
+            .values((
+                trie_roots::root.eq(root.map(Vec::from)),
+                trie_roots::next_event_index.eq(next_event_index.map(i64::from)),
+            ))
+            .execute(&mut *self.conn.borrow_mut())?;
+
+        Ok(())
+    }
+
+    /// Returns the executed transactions that are not in a batch yet, in the order they were executed.
+    pub fn unbatched_transactions(&self) -> Result<Vec<Transactions>, DBError> {
+        Ok(transaction::get_unbatched(&mut self.conn.borrow_mut())?)
+    }
+
+    /// Records `transaction` as executed after every transaction recorded before it,
+    /// `event_index` is the contract event of a deposit.
+    /// Returns its sequence number, `None` if its deposit is already in a batch.
+    pub fn insert_executed_transaction(
+        &self,
+        transaction: KairosTransaction,
+        event_index: Option<u32>,
+    ) -> Result<Option<i64>, DBError> {
+        Ok(transaction::insert_executed(
+            &mut self.conn.borrow_mut(),
+            transaction,
+            event_index,
+        )?)
+    }
+
+    /// Records `root` as the latest committed trie root along with the `batch` that produced it.
+    /// The executed transactions with a sequence number in `seqs` are assigned to it.
+    /// Everything is written in one transaction, so a committed batch can never be lost.
+    /// Returns the sequence number of the batch.
+    pub fn insert_committed_batch(
+        &self,
+        root: Option<[u8; 32]>,
+        next_event_index: Option<u32>,
+        batch: NewBatch,
+        seqs: Option<RangeInclusive<i64>>,
+    ) -> Result<i64, DBError> {
+        self.conn
+            .borrow_mut()
+            .transaction::<_, diesel::result::Error, _>(|conn| {
+                diesel::insert_into(trie_roots::table)
+                    .values((
+                        trie_roots::root.eq(root.map(Vec::from)),
+                        trie_roots::next_event_index.eq(next_event_index.map(i64::from)),
+                    ))
+                    .execute(conn)?;
+
+                let batch_id = batch::insert(conn, batch)?;
+                if let Some(seqs) = seqs {
+                    transaction::assign_batch(conn, batch_id, seqs)?;
+                }
+                Ok(batch_id)
+            })
+            .map_err(Into::into)
+    }
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> DatabaseGet<V> for TrieDb<V> {
+    type GetError = DBError;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<V>>, Self::GetError> {
+        let node = trie_nodes::table
+            .find(hash.bytes.to_vec())
+            .select(trie_nodes::node)
+            .first::<Vec<u8>>(&mut *self.conn.borrow_mut())?;
+
+        serde_json::from_slice(&node).map_err(Into::into)
+    }
+}
+
+impl<V: Clone + Serialize + DeserializeOwned> DatabaseSet<V> for TrieDb<V> {
+    type SetError = DBError;
+
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<V>>,
+    ) -> Result<(), Self::GetError> {
+        let node = serde_json::to_vec(&node)?;
+
+        diesel::insert_into(trie_nodes::table)
+            .values((
+                trie_nodes::hash.eq(hash.bytes.to_vec()),
+                trie_nodes::node.eq(node),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut *self.conn.borrow_mut())?;
+
+        Ok(())
+    }
+}
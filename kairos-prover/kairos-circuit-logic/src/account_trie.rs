@@ -320,6 +320,31 @@ impl<Db: DatabaseGet<Account>> AccountTrie<SnapshotBuilder<Db, Account>> {
 
         Ok(account.nonce)
     }
+
+    /// Returns the account for a public key, `None` if the account is unknown.
+    pub fn get_account(&self, account: &PublicKey) -> Result<Option<Account>, TxnErr> {
+        let [account_hash] = hash_buffers([account]);
+
+        let account = self.txn.get_exclude_from_txn(&account_hash)?;
+
+        Ok(account.map(|account| Account::new(account.balance, account.nonce)))
+    }
+
+    /// Looks up an account and builds a snapshot of the trie nodes on its path.
+    /// The snapshot is a Merkle proof of the account, or of its absence, against the trie root.
+    ///
+    /// This must be called on a fresh `AccountTrie`,
+    /// otherwise the snapshot contains every node touched by the transaction.
+    pub fn prove_account(
+        &self,
+        account: &PublicKey,
+    ) -> Result<(Option<Account>, Snapshot<Account>), TxnErr> {
+        let [account_hash] = hash_buffers([account]);
+
+        let account = self.txn.get(&account_hash)?.cloned();
+
+        Ok((account, self.txn.build_initial_snapshot()))
+    }
 }
 
 /// An account in the trie.
@@ -369,6 +394,12 @@ impl PortableHash for Account {
     }
 }
 
+/// Returns the key under which the account of `public_key` is stored in the trie.
+pub fn hash_account_key(public_key: &[u8]) -> KeyHash {
+    let [account_hash] = hash_buffers([public_key]);
+    account_hash
+}
+
 /// A utility function to hash multiple buffers reusing the same hasher.
 /// Note this function returns an array of hashes, one for each input item.
 /// `out[i] = hash(item[i])`
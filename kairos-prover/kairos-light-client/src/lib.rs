@@ -0,0 +1,205 @@
+//! Verifies Merkle proofs of Kairos accounts against the trie root stored by the L1 contract.
+//!
+//! A proof is a trie `Snapshot` holding the nodes on the path to an account,
+//! as returned by the Kairos server's `/api/v1/accounts/{public_key}` endpoint.
+//! The trie root is read from the contract's `kairos_trie_root` named key,
+//! which is `None` while the trie is empty.
+#![no_std]
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use kairos_circuit_logic::account_trie::{hash_account_key, AccountTrie};
+use kairos_trie::DigestHasher;
+use sha2::Sha256;
+
+pub use kairos_circuit_logic::account_trie::Account;
+pub use kairos_trie::stored::merkle::Snapshot;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The proof is malformed, or does not contain the path to the account.
+    InvalidProof(String),
+    /// The proof does not hash to the trusted trie root.
+    RootMismatch {
+        expected: Option<[u8; 32]>,
+        actual: Option<[u8; 32]>,
+    },
+    /// The proof is valid, but the account stored in the trie differs from the claimed one.
+    AccountMismatch {
+        expected: Option<Account>,
+        actual: Option<Account>,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidProof(err) => write!(f, "invalid proof: {err}"),
+            Self::RootMismatch { expected, actual } => write!(
+                f,
+                "proof root {actual:?} does not match the trie root {expected:?}"
+            ),
+            Self::AccountMismatch { expected, actual } => write!(
+                f,
+                "proven account {actual:?} does not match the claimed account {expected:?}"
+            ),
+        }
+    }
+}
+
+/// Verifies that `account` is stored under `public_key` in the trie with root `trie_root`.
+pub fn verify_inclusion(
+    public_key: &[u8],
+    account: &Account,
+    proof: &Snapshot<Account>,
+    trie_root: [u8; 32],
+) -> Result<(), VerifyError> {
+    verify_account(public_key, Some(account), proof, Some(trie_root))
+}
+
+/// Verifies that no account is stored under `public_key` in the trie with root `trie_root`.
+pub fn verify_non_inclusion(
+    public_key: &[u8],
+    proof: &Snapshot<Account>,
+    trie_root: Option<[u8; 32]>,
+) -> Result<(), VerifyError> {
+    verify_account(public_key, None, proof, trie_root)
+}
+
+/// Verifies that the account stored under `public_key` in the trie with root `trie_root` is `account`.
+/// `None` means the account must be absent.
+pub fn verify_account(
+    public_key: &[u8],
+    account: Option<&Account>,
+    proof: &Snapshot<Account>,
+    trie_root: Option<[u8; 32]>,
+) -> Result<(), VerifyError> {
+    let trie = AccountTrie::new_try_from_snapshot(proof).map_err(VerifyError::InvalidProof)?;
+
+    let proof_root: Option<[u8; 32]> = trie
+        .txn
+        .calc_root_hash(&mut DigestHasher::<Sha256>::default())
+        .map_err(|err| VerifyError::InvalidProof(err.to_string()))?
+        .into();
+    if proof_root != trie_root {
+        return Err(VerifyError::RootMismatch {
+            expected: trie_root,
+            actual: proof_root,
+        });
+    }
+
+    let proven = trie
+        .txn
+        .get(&hash_account_key(public_key))
+        .map_err(|err| VerifyError::InvalidProof(err.to_string()))?;
+    if proven != account {
+        return Err(VerifyError::AccountMismatch {
+            expected: account.cloned(),
+            actual: proven.cloned(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use alloc::{rc::Rc, vec::Vec};
+
+    use kairos_circuit_logic::transactions::L1Deposit;
+    use kairos_trie::{stored::memory_db::MemoryDb, NodeHash, TrieRoot};
+
+    /// Commits deposits for `accounts` and returns the database with the new root.
+    fn setup(accounts: &[(&[u8], u64)]) -> (Rc<MemoryDb<Account>>, TrieRoot<NodeHash>) {
+        let db = Rc::new(MemoryDb::empty());
+        let mut trie = AccountTrie::new_try_from_db(db.clone(), TrieRoot::Empty);
+        for (public_key, amount) in accounts {
+            trie.deposit(&L1Deposit {
+                recipient: public_key.to_vec(),
+                amount: *amount,
+            })
+            .unwrap();
+        }
+        let root = trie
+            .txn
+            .commit(&mut DigestHasher::<Sha256>::default())
+            .unwrap();
+
+        (db, root)
+    }
+
+    fn prove(
+        db: &Rc<MemoryDb<Account>>,
+        root: TrieRoot<NodeHash>,
+        public_key: &[u8],
+    ) -> (Option<Account>, Snapshot<Account>) {
+        AccountTrie::new_try_from_db(db.clone(), root)
+            .prove_account(&public_key.to_vec())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify_inclusion() {
+        let (db, root) = setup(&[(b"alice", 10), (b"bob", 20), (b"carol", 30)]);
+        let trie_root = Option::<[u8; 32]>::from(root).unwrap();
+
+        let (account, proof) = prove(&db, root, b"bob");
+        assert_eq!(account, Some(Account::new(20, 0)));
+        verify_inclusion(b"bob", &Account::new(20, 0), &proof, trie_root).unwrap();
+
+        assert!(matches!(
+            verify_inclusion(b"bob", &Account::new(21, 0), &proof, trie_root),
+            Err(VerifyError::AccountMismatch { .. })
+        ));
+        assert!(matches!(
+            verify_inclusion(b"bob", &Account::new(20, 0), &proof, [0; 32]),
+            Err(VerifyError::RootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_non_inclusion() {
+        let (db, root) = setup(&[(b"alice", 10), (b"bob", 20)]);
+
+        let (account, proof) = prove(&db, root, b"mallory");
+        assert_eq!(account, None);
+        verify_non_inclusion(b"mallory", &proof, root.into()).unwrap();
+
+        // A proof for one account can't be used to claim another is absent.
+        let (_, alice_proof) = prove(&db, root, b"alice");
+        assert!(verify_non_inclusion(b"alice", &alice_proof, root.into()).is_err());
+    }
+
+    #[test]
+    fn test_verify_empty_trie() {
+        let (db, root) = setup(&[]);
+        assert_eq!(root, TrieRoot::Empty);
+
+        let (_, proof) = prove(&db, root, b"alice");
+        verify_non_inclusion(b"alice", &proof, None).unwrap();
+        verify_account(b"alice", None, &proof, None).unwrap();
+        assert!(verify_non_inclusion(b"alice", &proof, Some([0; 32])).is_err());
+    }
+
+    #[test]
+    fn test_proof_without_the_path_is_rejected() {
+        let accounts: Vec<_> = (0u8..16).map(|i| ([i; 4], 10)).collect();
+        let accounts: Vec<(&[u8], u64)> = accounts.iter().map(|(k, a)| (&k[..], *a)).collect();
+        let (db, root) = setup(&accounts);
+
+        // The proof of one account does not contain the path to other accounts.
+        let (_, proof) = prove(&db, root, &[0; 4]);
+        let result = verify_inclusion(
+            &[15; 4],
+            &Account::new(10, 0),
+            &proof,
+            Option::<[u8; 32]>::from(root).unwrap(),
+        );
+        assert!(result.is_err());
+    }
+}
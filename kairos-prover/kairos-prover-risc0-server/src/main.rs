@@ -1,8 +1,17 @@
 use std::time::Instant;
 
-use axum::{http::StatusCode, Json};
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use axum_extra::routing::{RouterExt, TypedPath};
 use kairos_circuit_logic::{ProofInputs, ProofOutputs};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry,
+    TextEncoder,
+};
 
 // These constants represent the RISC-V ELF and the image ID generated by risc0-build.
 // The ELF is used for proving and the ID is used for verification.
@@ -31,7 +40,8 @@ async fn main() {
 
     let app = axum::Router::new()
         .typed_post(prove_batch_route)
-        .with_state(());
+        .typed_get(metrics_route)
+        .with_state(Metrics::new());
 
     tracing::info!("starting http server on `{}`", socket_addr);
     let listener = tokio::net::TcpListener::bind(socket_addr).await.unwrap();
@@ -45,25 +55,105 @@ pub struct ProveBatch;
 
 pub async fn prove_batch_route(
     _: ProveBatch,
+    State(metrics): State<Metrics>,
     proof_inputs: Json<ProofInputs>,
 ) -> Result<Json<(ProofOutputs, Receipt)>, (StatusCode, String)> {
-    let proof = tokio::task::spawn_blocking(move || prove_execution(proof_inputs.0))
-        .await
+    let timestamp = Instant::now();
+    metrics.proving_in_progress.inc();
+    let proof = tokio::task::spawn_blocking(move || prove_execution(proof_inputs.0)).await;
+    metrics.proving_in_progress.dec();
+    metrics
+        .proving_duration
+        .observe(timestamp.elapsed().as_secs_f64());
+
+    let proof = proof
+        .map_err(|e| format!("Error while joining proving task: {e}"))
+        .and_then(|proof| proof.map_err(|e| format!("Error while proving batch: {e}")))
         .map_err(|e| {
-            let e = format!("Error while joining proving task: {e}");
             tracing::error!(e);
+            metrics.proving_failures.inc();
             (StatusCode::INTERNAL_SERVER_ERROR, e)
         })?;
 
-    let proof = proof.map_err(|e| {
-        let e = format!("Error while proving batch: {e}");
-        tracing::error!(e);
-        (StatusCode::INTERNAL_SERVER_ERROR, e)
-    })?;
-
     Ok(Json(proof))
 }
 
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/metrics")]
+pub struct MetricsPath;
+
+/// Serves the proving metrics in the Prometheus text format.
+pub async fn metrics_route(
+    _: MetricsPath,
+    State(metrics): State<Metrics>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    Ok((
+        [(
+            CONTENT_TYPE,
+            HeaderValue::from_static(prometheus::TEXT_FORMAT),
+        )],
+        buffer,
+    ))
+}
+
+/// Proving metrics, named like the matching metrics of `kairos-server`.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub proving_duration: Histogram,
+    pub proving_failures: IntCounter,
+    pub proving_in_progress: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("kairos_prover".to_string()), None)
+            .expect("Invalid metrics prefix");
+        let proving_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "proving_duration_seconds",
+                "Time taken to prove and verify a batch, including failed attempts",
+            )
+            .buckets(exponential_buckets(1.0, 2.0, 12).expect("Invalid histogram buckets")),
+        )
+        .expect("Invalid metric");
+        let proving_failures =
+            IntCounter::new("proving_failures_total", "Batches that failed to be proven")
+                .expect("Invalid metric");
+        let proving_in_progress =
+            IntGauge::new("proving_in_progress", "Batches currently being proven")
+                .expect("Invalid metric");
+
+        registry
+            .register(Box::new(proving_duration.clone()))
+            .expect("Metric registered twice");
+        registry
+            .register(Box::new(proving_failures.clone()))
+            .expect("Metric registered twice");
+        registry
+            .register(Box::new(proving_in_progress.clone()))
+            .expect("Metric registered twice");
+
+        Self {
+            registry,
+            proving_duration,
+            proving_failures,
+            proving_in_progress,
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 fn prove_execution(
     proof_inputs: kairos_circuit_logic::ProofInputs,
 ) -> Result<(ProofOutputs, Receipt), String> {
@@ -1,12 +1,15 @@
 use casper_client_types::{ContractHash, SecretKey};
 use hex::FromHex;
 use reqwest::Url;
+use serde::Deserialize;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{fmt, str::FromStr};
 
-#[derive(Clone, Debug)]
+/// Loaded by `ServerConfig::load`, see `PartialServerConfig` for the TOML keys,
+/// environment variables and CLI flags.
+#[derive(Clone)]
 pub struct ServerConfig {
     /// Set by the environment variable `KAIROS_SERVER_SECRET_KEY_FILE`.
     /// This is checked at startup to ensure SecretKey::from_file is successful.
@@ -15,67 +18,59 @@ pub struct ServerConfig {
     pub casper_rpc: Url,
     pub casper_sse: Url,
     pub casper_sync_interval: Duration,
+    /// Set by the environment variable `KAIROS_SERVER_CASPER_CONFIRMATIONS`,
+    /// defaults to `DEFAULT_CASPER_CONFIRMATIONS`.
+    /// Deposits are only credited once this many blocks were added on top of their block,
+    /// so L2 funds are never backed by a deposit that could still be orphaned.
+    pub casper_confirmations: u64,
     pub kairos_demo_contract_hash: ContractHash,
     pub batch_config: BatchConfig,
+    /// The admin API is only served if `KAIROS_SERVER_ADMIN_SOCKET_ADDR` is set.
+    pub admin_config: Option<AdminConfig>,
     #[cfg(feature = "database")]
     pub db_addr: String,
+    /// Set by the environment variable `KAIROS_SERVER_RECOVER_L2_STATE`, defaults to `false`.
+    /// When set, the trie is rebuilt at startup by replaying the stored transactions.
+    #[cfg(feature = "database")]
+    pub recover_l2_state: bool,
 }
 
 impl ServerConfig {
-    pub fn from_env() -> Result<Self, String> {
-        let socket_addr = parse_env_as::<SocketAddr>("KAIROS_SERVER_SOCKET_ADDR")?;
-
-        let casper_rpc = parse_env_as::<Url>("KAIROS_SERVER_CASPER_RPC")?;
-        let casper_sse = parse_env_as::<Url>("KAIROS_SERVER_CASPER_SSE")?;
-        let casper_sync_interval =
-            parse_env_as::<u64>("KAIROS_SERVER_CASPER_SYNC_INTERVAL").map(Duration::from_secs)?;
-
-        if casper_sync_interval.as_secs() == 0 {
-            return Err("Casper sync interval must be greater than 0".to_string());
-        }
-
-        let batch_config = BatchConfig::from_env()?;
-
-        let secret_key_file = parse_env_as_opt::<String>("KAIROS_SERVER_SECRET_KEY_FILE")?
-            .map(PathBuf::from)
-            .map(|p| find_relative_path_up(&p, 2))
-            .transpose()?
-            .map(|p| {
-                if SecretKey::from_file(&p).is_ok() {
-                    Ok(p)
-                } else {
-                    Err(format!("Failed to read secret key file: {:?}", p))
-                }
-            })
-            .transpose()?;
-
-        let kairos_demo_contract_hash = parse_env_as::<String>("KAIROS_SERVER_DEMO_CONTRACT_HASH")
-            .and_then(|contract_hash_string| {
-                <[u8; 32]>::from_hex(&contract_hash_string).map_err(|err| {
-                    panic!(
-                        "Failed to decode kairos-demo-contract-hash {}: {}",
-                        contract_hash_string, err
-                    )
-                })
-            })
-            .map(ContractHash::new)?;
-        #[cfg(feature = "database")]
-        let db_addr = parse_env_as::<String>("KAIROS_SERVER_DB_ADDR")?;
+    /// Loads the configuration from the environment only, see `ServerConfig::load`.
+    pub fn from_env() -> Result<Self, ConfigErrors> {
+        Self::load(None, PartialServerConfig::default())
+    }
 
-        Ok(Self {
-            secret_key_file,
-            socket_addr,
-            casper_rpc,
-            casper_sse,
-            casper_sync_interval,
-            kairos_demo_contract_hash,
-            batch_config,
-            #[cfg(feature = "database")]
-            db_addr,
-        })
+    /// Loads the configuration in layers, each overriding the previous one:
+    /// the TOML file at `config_file`, the `KAIROS_SERVER_*` environment variables and `cli`.
+    /// Every problem found in any layer is reported at once.
+    pub fn load(
+        config_file: Option<&Path>,
+        cli: PartialServerConfig,
+    ) -> Result<Self, ConfigErrors> {
+        let mut errors = Vec::new();
+        let file = match config_file {
+            Some(path) => PartialServerConfig::from_file(path).unwrap_or_else(|err| {
+                errors.push(err);
+                PartialServerConfig::default()
+            }),
+            None => PartialServerConfig::default(),
+        };
+        let env = PartialServerConfig::from_vars(|name| std::env::var(name).ok(), &mut errors);
+
+        file.merge(env).merge(cli).validate(errors)
     }
 }
 
+pub const DEFAULT_CASPER_CONFIRMATIONS: u64 = 3;
+pub const DEFAULT_MEMPOOL_EXPIRY: Duration = Duration::from_secs(60);
+pub const DEFAULT_PROVING_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+/// In motes, the most a proof submission may spend on gas.
+pub const DEFAULT_SUBMISSION_PAYMENT: u64 = 10_000_000_000_000;
+pub const DEFAULT_SUBMISSION_TTL: Duration = Duration::from_secs(10 * 60);
+const MIN_TICK_INTERVAL: Duration = Duration::from_millis(10);
+const MAX_TICK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Configuration for the trie state thread.
 /// Currently only configures when a batch is committed and sent to the proving server.
 #[derive(Debug, Clone)]
@@ -84,49 +79,656 @@ pub struct BatchConfig {
     pub max_batch_size: Option<u64>,
     /// Set by the environment variable `KAIROS_SERVER_MAX_BATCH_SECONDS`.
     pub max_batch_duration: Option<Duration>,
-    pub proving_server: Url,
+    /// Set by the environment variable `KAIROS_SERVER_MAX_BATCH_CYCLES`.
+    /// The batch is committed once its estimated guest cycles reach this, see `BatchCost`.
+    pub max_batch_cycles: Option<u64>,
+    /// Set by the environment variable `KAIROS_SERVER_MAX_BATCH_SNAPSHOT_BYTES`.
+    /// The batch is committed once the trie snapshot sent to the prover reaches this size.
+    pub max_batch_snapshot_bytes: Option<u64>,
+    /// Set by the environment variable `KAIROS_SERVER_PROVER`, defaults to `ProverKind::Http`.
+    pub prover: ProverKind,
+    /// Set by the environment variable `KAIROS_SERVER_PROVING_SERVERS`,
+    /// or `KAIROS_PROVER_SERVER_URL` for a single server.
+    /// Never empty with the `http` prover, unused by the others.
+    /// Each batch is proven by an idle, healthy server, see `ProverPool`.
+    pub proving_servers: Vec<ProvingServer>,
+    /// Set by the environment variable `KAIROS_SERVER_PROVING_TIMEOUT_SECONDS`, defaults to 1 hour.
+    /// A batch that is not proven in time is retried on another server.
+    pub proving_timeout: Duration,
+    /// Set by the environment variable `KAIROS_SERVER_MEMPOOL_EXPIRY_SECONDS`, defaults to 60 seconds.
+    /// How long a transaction with a future nonce is held, see `Mempool`. Zero disables holding.
+    pub mempool_expiry: Duration,
+    /// Set by the environment variable `KAIROS_SERVER_SUBMISSION_PAYMENT`,
+    /// defaults to `DEFAULT_SUBMISSION_PAYMENT`.
+    /// The payment in motes of each deploy submitting a proof to the contract.
+    pub submission_payment: u64,
+    /// Set by the environment variable `KAIROS_SERVER_SUBMISSION_TTL_SECONDS`, defaults to 10 minutes.
+    /// A proof deploy that is not executed within its TTL is submitted again.
+    pub submission_ttl: Duration,
 }
 
 impl BatchConfig {
-    pub fn from_env() -> Result<Self, String> {
-        let max_batch_size = parse_env_as_opt("KAIROS_SERVER_MAX_BATCH_SIZE")?;
-        let max_batch_duration =
-            parse_env_as_opt::<u64>("KAIROS_SERVER_MAX_BATCH_SECONDS")?.map(Duration::from_secs);
-        let proving_server = parse_env_as::<Url>("KAIROS_PROVER_SERVER_URL")?;
-
-        Ok(Self {
-            max_batch_size,
-            max_batch_duration,
-            proving_server,
+    /// Whether batches are limited by their estimated proving cost, which the trie thread then tracks.
+    pub fn has_cost_budget(&self) -> bool {
+        self.max_batch_cycles.is_some() || self.max_batch_snapshot_bytes.is_some()
+    }
+
+    /// How often the trie thread wakes up to commit batches older than `max_batch_duration`
+    /// and to expire held transactions, `None` if neither is enabled.
+    pub fn tick_interval(&self) -> Option<Duration> {
+        [
+            self.max_batch_duration,
+            Some(self.mempool_expiry).filter(|expiry| !expiry.is_zero()),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|duration| (duration / 2).clamp(MIN_TICK_INTERVAL, MAX_TICK_INTERVAL))
+        .min()
+    }
+}
+
+/// How committed batches are proven, see `BatchProver`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProverKind {
+    /// The kairos-prover-risc0-server instances in `proving_servers`.
+    #[default]
+    Http,
+    /// risc0 in the server process, needs the `risc0-prover` feature.
+    Risc0,
+    /// Runs the batch logic natively and returns dev mode receipts, for development and tests.
+    Native,
+}
+
+impl FromStr for ProverKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http" => Ok(Self::Http),
+            "risc0" => Ok(Self::Risc0),
+            "native" => Ok(Self::Native),
+            _ => Err(format!(
+                "unknown prover {:?}, expected http, risc0 or native",
+                s
+            )),
+        }
+    }
+}
+
+/// A proving server, batches are spread over the servers in proportion to their `weight`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProvingServer {
+    pub url: Url,
+    pub weight: u32,
+}
+
+impl ProvingServer {
+    /// A server with the default weight of 1.
+    pub fn new(url: Url) -> Self {
+        Self { url, weight: 1 }
+    }
+}
+
+/// `db_addr` may hold the database password, so it's redacted.
+impl fmt::Debug for ServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ServerConfig");
+        debug
+            .field("secret_key_file", &self.secret_key_file)
+            .field("socket_addr", &self.socket_addr)
+            .field("casper_rpc", &self.casper_rpc)
+            .field("casper_sse", &self.casper_sse)
+            .field("casper_sync_interval", &self.casper_sync_interval)
+            .field("casper_confirmations", &self.casper_confirmations)
+            .field("kairos_demo_contract_hash", &self.kairos_demo_contract_hash)
+            .field("batch_config", &self.batch_config)
+            .field("admin_config", &self.admin_config);
+        #[cfg(feature = "database")]
+        debug
+            .field("db_addr", &"<redacted>")
+            .field("recover_l2_state", &self.recover_l2_state);
+        debug.finish()
+    }
+}
+
+/// Configuration for the admin API, which is served on its own socket address
+/// so it can be kept off the public network.
+#[derive(Clone)]
+pub struct AdminConfig {
+    /// Set by the environment variable `KAIROS_SERVER_ADMIN_SOCKET_ADDR`.
+    pub socket_addr: SocketAddr,
+    /// Set by the environment variable `KAIROS_SERVER_ADMIN_TOKEN`.
+    /// Requests must carry it as a bearer token.
+    pub token: String,
+}
+
+impl fmt::Debug for AdminConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminConfig")
+            .field("socket_addr", &self.socket_addr)
+            .field("token", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Every problem found while loading and validating the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration error(s):", self.0.len())?;
+        for error in &self.0 {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// One layer of the configuration, unset values are filled in by the layers below it.
+///
+/// In the TOML file, the keys are the field names, e.g. `casper_rpc = "http://..."`,
+/// with the `batch` and `admin` settings in their own tables.
+/// Durations are in seconds. The environment variable of each key is listed on its field,
+/// the CLI flag is the key in kebab case, e.g. `--casper-rpc`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct PartialServerConfig {
+    /// `KAIROS_SERVER_SOCKET_ADDR`
+    #[arg(long)]
+    pub socket_addr: Option<SocketAddr>,
+    /// `KAIROS_SERVER_CASPER_RPC`
+    #[arg(long)]
+    pub casper_rpc: Option<String>,
+    /// `KAIROS_SERVER_CASPER_SSE`
+    #[arg(long)]
+    pub casper_sse: Option<String>,
+    /// `KAIROS_SERVER_CASPER_SYNC_INTERVAL`, in seconds.
+    #[arg(long)]
+    pub casper_sync_interval: Option<u64>,
+    /// `KAIROS_SERVER_CASPER_CONFIRMATIONS`, in blocks.
+    #[arg(long)]
+    pub casper_confirmations: Option<u64>,
+    /// `KAIROS_SERVER_DEMO_CONTRACT_HASH`, hex encoded.
+    #[arg(long)]
+    pub demo_contract_hash: Option<String>,
+    /// `KAIROS_SERVER_SECRET_KEY_FILE`
+    #[arg(long)]
+    pub secret_key_file: Option<PathBuf>,
+    /// `KAIROS_SERVER_DB_ADDR`, only used with the `database` feature.
+    #[arg(long)]
+    pub db_addr: Option<String>,
+    /// `KAIROS_SERVER_RECOVER_L2_STATE`, only used with the `database` feature.
+    #[arg(long)]
+    pub recover_l2_state: Option<bool>,
+    #[serde(default)]
+    #[command(flatten)]
+    pub batch: PartialBatchConfig,
+    #[serde(default)]
+    #[command(flatten)]
+    pub admin: PartialAdminConfig,
+}
+
+/// The `[batch]` table, see `BatchConfig`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct PartialBatchConfig {
+    /// `KAIROS_SERVER_MAX_BATCH_SIZE`
+    #[arg(long)]
+    pub max_batch_size: Option<u64>,
+    /// `KAIROS_SERVER_MAX_BATCH_SECONDS`
+    #[arg(long)]
+    pub max_batch_seconds: Option<u64>,
+    /// `KAIROS_SERVER_MAX_BATCH_CYCLES`
+    #[arg(long)]
+    pub max_batch_cycles: Option<u64>,
+    /// `KAIROS_SERVER_MAX_BATCH_SNAPSHOT_BYTES`
+    #[arg(long)]
+    pub max_batch_snapshot_bytes: Option<u64>,
+    /// `KAIROS_SERVER_PROVER`, one of `http`, `risc0` or `native`.
+    #[arg(long, value_enum)]
+    pub prover: Option<ProverKind>,
+    /// `KAIROS_PROVER_SERVER_URL`, a single server, see `proving_servers`.
+    #[arg(long)]
+    pub proving_server: Option<String>,
+    /// `KAIROS_SERVER_PROVING_SERVERS`, comma separated `URL` or `URL;weight=N` entries.
+    /// In the TOML file a list of `{ url = "...", weight = N }` tables.
+    #[arg(long, value_delimiter = ',')]
+    pub proving_servers: Option<Vec<PartialProvingServer>>,
+    /// `KAIROS_SERVER_PROVING_TIMEOUT_SECONDS`
+    #[arg(long)]
+    pub proving_timeout_seconds: Option<u64>,
+    /// `KAIROS_SERVER_MEMPOOL_EXPIRY_SECONDS`
+    #[arg(long)]
+    pub mempool_expiry_seconds: Option<u64>,
+    /// `KAIROS_SERVER_SUBMISSION_PAYMENT`, in motes.
+    #[arg(long)]
+    pub submission_payment: Option<u64>,
+    /// `KAIROS_SERVER_SUBMISSION_TTL_SECONDS`
+    #[arg(long)]
+    pub submission_ttl_seconds: Option<u64>,
+}
+
+/// An entry of `batch.proving_servers`, see `ProvingServer`. The weight defaults to 1.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartialProvingServer {
+    pub url: String,
+    pub weight: Option<u32>,
+}
+
+impl FromStr for PartialProvingServer {
+    type Err = String;
+
+    /// Parses `URL` or `URL;weight=N`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once(";weight=") {
+            Some((url, weight)) => Ok(Self {
+                url: url.to_string(),
+                weight: Some(
+                    weight
+                        .parse()
+                        .map_err(|e| format!("invalid weight {:?}: {}", weight, e))?,
+                ),
+            }),
+            None => Ok(Self {
+                url: s.to_string(),
+                weight: None,
+            }),
+        }
+    }
+}
+
+/// The `[admin]` table, see `AdminConfig`.
+/// The token has no CLI flag, so it does not show up in the process list.
+#[derive(Default, Clone, PartialEq, Eq, Deserialize, clap::Args)]
+#[serde(deny_unknown_fields)]
+pub struct PartialAdminConfig {
+    /// `KAIROS_SERVER_ADMIN_SOCKET_ADDR`
+    #[arg(long = "admin-socket-addr", id = "admin_socket_addr")]
+    pub socket_addr: Option<SocketAddr>,
+    /// `KAIROS_SERVER_ADMIN_TOKEN`
+    #[arg(skip)]
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for PartialAdminConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartialAdminConfig")
+            .field("socket_addr", &self.socket_addr)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl PartialServerConfig {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config file {:?}: {}", path, e))
+    }
+
+    /// Reads the environment variables through `var`, pushing parse failures to `errors`.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>, errors: &mut Vec<String>) -> Self {
+        macro_rules! parse {
+            ($name:literal) => {
+                parse_var($name, var($name), errors)
+            };
+        }
+
+        Self {
+            socket_addr: parse!("KAIROS_SERVER_SOCKET_ADDR"),
+            casper_rpc: parse!("KAIROS_SERVER_CASPER_RPC"),
+            casper_sse: parse!("KAIROS_SERVER_CASPER_SSE"),
+            casper_sync_interval: parse!("KAIROS_SERVER_CASPER_SYNC_INTERVAL"),
+            casper_confirmations: parse!("KAIROS_SERVER_CASPER_CONFIRMATIONS"),
+            demo_contract_hash: parse!("KAIROS_SERVER_DEMO_CONTRACT_HASH"),
+            secret_key_file: parse!("KAIROS_SERVER_SECRET_KEY_FILE"),
+            db_addr: parse!("KAIROS_SERVER_DB_ADDR"),
+            recover_l2_state: parse!("KAIROS_SERVER_RECOVER_L2_STATE"),
+            batch: PartialBatchConfig {
+                max_batch_size: parse!("KAIROS_SERVER_MAX_BATCH_SIZE"),
+                max_batch_seconds: parse!("KAIROS_SERVER_MAX_BATCH_SECONDS"),
+                max_batch_cycles: parse!("KAIROS_SERVER_MAX_BATCH_CYCLES"),
+                max_batch_snapshot_bytes: parse!("KAIROS_SERVER_MAX_BATCH_SNAPSHOT_BYTES"),
+                prover: parse!("KAIROS_SERVER_PROVER"),
+                proving_server: parse!("KAIROS_PROVER_SERVER_URL"),
+                proving_servers: parse_list_var(
+                    "KAIROS_SERVER_PROVING_SERVERS",
+                    var("KAIROS_SERVER_PROVING_SERVERS"),
+                    errors,
+                ),
+                proving_timeout_seconds: parse!("KAIROS_SERVER_PROVING_TIMEOUT_SECONDS"),
+                mempool_expiry_seconds: parse!("KAIROS_SERVER_MEMPOOL_EXPIRY_SECONDS"),
+                submission_payment: parse!("KAIROS_SERVER_SUBMISSION_PAYMENT"),
+                submission_ttl_seconds: parse!("KAIROS_SERVER_SUBMISSION_TTL_SECONDS"),
+            },
+            admin: PartialAdminConfig {
+                socket_addr: parse!("KAIROS_SERVER_ADMIN_SOCKET_ADDR"),
+                token: parse!("KAIROS_SERVER_ADMIN_TOKEN"),
+            },
+        }
+    }
+
+    /// Returns `self` with the values set in `overrides` replaced.
+    pub fn merge(self, overrides: Self) -> Self {
+        // The single server and the list are one setting, a layer that sets either replaces both.
+        let (proving_server, proving_servers) = if overrides.batch.proving_server.is_some()
+            || overrides.batch.proving_servers.is_some()
+        {
+            (
+                overrides.batch.proving_server,
+                overrides.batch.proving_servers,
+            )
+        } else {
+            (self.batch.proving_server, self.batch.proving_servers)
+        };
+
+        Self {
+            socket_addr: overrides.socket_addr.or(self.socket_addr),
+            casper_rpc: overrides.casper_rpc.or(self.casper_rpc),
+            casper_sse: overrides.casper_sse.or(self.casper_sse),
+            casper_sync_interval: overrides.casper_sync_interval.or(self.casper_sync_interval),
+            casper_confirmations: overrides.casper_confirmations.or(self.casper_confirmations),
+            demo_contract_hash: overrides.demo_contract_hash.or(self.demo_contract_hash),
+            secret_key_file: overrides.secret_key_file.or(self.secret_key_file),
+            db_addr: overrides.db_addr.or(self.db_addr),
+            recover_l2_state: overrides.recover_l2_state.or(self.recover_l2_state),
+            batch: PartialBatchConfig {
+                max_batch_size: overrides.batch.max_batch_size.or(self.batch.max_batch_size),
+                max_batch_seconds: overrides
+                    .batch
+                    .max_batch_seconds
+                    .or(self.batch.max_batch_seconds),
+                max_batch_cycles: overrides
+                    .batch
+                    .max_batch_cycles
+                    .or(self.batch.max_batch_cycles),
+                max_batch_snapshot_bytes: overrides
+                    .batch
+                    .max_batch_snapshot_bytes
+                    .or(self.batch.max_batch_snapshot_bytes),
+                prover: overrides.batch.prover.or(self.batch.prover),
+                proving_server,
+                proving_servers,
+                proving_timeout_seconds: overrides
+                    .batch
+                    .proving_timeout_seconds
+                    .or(self.batch.proving_timeout_seconds),
+                mempool_expiry_seconds: overrides
+                    .batch
+                    .mempool_expiry_seconds
+                    .or(self.batch.mempool_expiry_seconds),
+                submission_payment: overrides
+                    .batch
+                    .submission_payment
+                    .or(self.batch.submission_payment),
+                submission_ttl_seconds: overrides
+                    .batch
+                    .submission_ttl_seconds
+                    .or(self.batch.submission_ttl_seconds),
+            },
+            admin: PartialAdminConfig {
+                socket_addr: overrides.admin.socket_addr.or(self.admin.socket_addr),
+                token: overrides.admin.token.or(self.admin.token),
+            },
+        }
+    }
+
+    /// Checks every value and builds the `ServerConfig`,
+    /// `errors` holds the problems found while loading the layers.
+    pub fn validate(self, mut errors: Vec<String>) -> Result<ServerConfig, ConfigErrors> {
+        let socket_addr = required(self.socket_addr, "socket_addr", &mut errors);
+        let casper_rpc = required_url(self.casper_rpc, "casper_rpc", &mut errors);
+        let casper_sse = required_url(self.casper_sse, "casper_sse", &mut errors);
+        let casper_sync_interval = required(
+            self.casper_sync_interval,
+            "casper_sync_interval",
+            &mut errors,
+        )
+        .filter(|&seconds| {
+            let valid = seconds > 0;
+            if !valid {
+                errors.push("casper_sync_interval must be greater than 0".to_string());
+            }
+            valid
         })
+        .map(Duration::from_secs);
+        let casper_confirmations = self
+            .casper_confirmations
+            .unwrap_or(DEFAULT_CASPER_CONFIRMATIONS);
+
+        let kairos_demo_contract_hash =
+            required(self.demo_contract_hash, "demo_contract_hash", &mut errors).and_then(
+                |contract_hash| match <[u8; 32]>::from_hex(&contract_hash) {
+                    Ok(bytes) => Some(ContractHash::new(bytes)),
+                    Err(err) => {
+                        errors.push(format!(
+                            "demo_contract_hash {:?} is not a hex encoded 32 byte hash: {}",
+                            contract_hash, err
+                        ));
+                        None
+                    }
+                },
+            );
+
+        // We check that the secret key can be read, so the batch output handler won't fail later.
+        let secret_key_file = self.secret_key_file.and_then(|path| {
+            let checked = find_relative_path_up(&path, 2).and_then(|path| {
+                SecretKey::from_file(&path)
+                    .map(|_| path.clone())
+                    .map_err(|err| format!("Failed to read secret key file {:?}: {}", path, err))
+            });
+            checked.map_err(|err| errors.push(err)).ok()
+        });
+
+        let prover = self.batch.prover.unwrap_or_default();
+        if prover == ProverKind::Risc0 && !cfg!(feature = "risc0-prover") {
+            errors.push("batch.prover risc0 needs the risc0-prover feature".to_string());
+        }
+        // The in-process provers don't need a proving server, e.g. in CI.
+        let proving_servers = match prover {
+            ProverKind::Http => proving_servers(
+                self.batch.proving_server,
+                self.batch.proving_servers,
+                &mut errors,
+            ),
+            ProverKind::Risc0 | ProverKind::Native => Some(Vec::new()),
+        };
+        let proving_timeout = self
+            .batch
+            .proving_timeout_seconds
+            .map_or(DEFAULT_PROVING_TIMEOUT, Duration::from_secs);
+        if proving_timeout.is_zero() {
+            errors.push("batch.proving_timeout_seconds must be greater than 0".to_string());
+        }
+        let max_batch_size = self.batch.max_batch_size;
+        if max_batch_size == Some(0) {
+            errors.push("batch.max_batch_size must be greater than 0".to_string());
+        }
+        let max_batch_duration = self.batch.max_batch_seconds.map(Duration::from_secs);
+        let max_batch_cycles = self.batch.max_batch_cycles;
+        if max_batch_cycles == Some(0) {
+            errors.push("batch.max_batch_cycles must be greater than 0".to_string());
+        }
+        let max_batch_snapshot_bytes = self.batch.max_batch_snapshot_bytes;
+        if max_batch_snapshot_bytes == Some(0) {
+            errors.push("batch.max_batch_snapshot_bytes must be greater than 0".to_string());
+        }
+        let mempool_expiry = self
+            .batch
+            .mempool_expiry_seconds
+            .map_or(DEFAULT_MEMPOOL_EXPIRY, Duration::from_secs);
+        let submission_payment = self
+            .batch
+            .submission_payment
+            .unwrap_or(DEFAULT_SUBMISSION_PAYMENT);
+        if submission_payment == 0 {
+            errors.push("batch.submission_payment must be greater than 0".to_string());
+        }
+        let submission_ttl = self
+            .batch
+            .submission_ttl_seconds
+            .map_or(DEFAULT_SUBMISSION_TTL, Duration::from_secs);
+        if submission_ttl.is_zero() {
+            errors.push("batch.submission_ttl_seconds must be greater than 0".to_string());
+        }
+
+        let admin_config = match (self.admin.socket_addr, self.admin.token) {
+            (None, None) => None,
+            (None, Some(_)) => {
+                errors.push("admin.token is set but admin.socket_addr is not".to_string());
+                None
+            }
+            (Some(_), None) => {
+                errors.push("admin.token is required when admin.socket_addr is set".to_string());
+                None
+            }
+            (Some(_), Some(token)) if token.is_empty() => {
+                errors.push("admin.token must not be empty".to_string());
+                None
+            }
+            (Some(socket_addr), Some(token)) => Some(AdminConfig { socket_addr, token }),
+        };
+
+        #[cfg(feature = "database")]
+        let db_addr = required(self.db_addr, "db_addr", &mut errors);
+        #[cfg(feature = "database")]
+        let recover_l2_state = self.recover_l2_state.unwrap_or(false);
+
+        match (
+            socket_addr,
+            casper_rpc,
+            casper_sse,
+            casper_sync_interval,
+            kairos_demo_contract_hash,
+            proving_servers,
+        ) {
+            (
+                Some(socket_addr),
+                Some(casper_rpc),
+                Some(casper_sse),
+                Some(casper_sync_interval),
+                Some(kairos_demo_contract_hash),
+                Some(proving_servers),
+            ) if errors.is_empty() => Ok(ServerConfig {
+                secret_key_file,
+                socket_addr,
+                casper_rpc,
+                casper_sse,
+                casper_sync_interval,
+                casper_confirmations,
+                kairos_demo_contract_hash,
+                batch_config: BatchConfig {
+                    max_batch_size,
+                    max_batch_duration,
+                    max_batch_cycles,
+                    max_batch_snapshot_bytes,
+                    prover,
+                    proving_servers,
+                    proving_timeout,
+                    mempool_expiry,
+                    submission_payment,
+                    submission_ttl,
+                },
+                admin_config,
+                #[cfg(feature = "database")]
+                db_addr: db_addr.expect("a missing db_addr is an error"),
+                #[cfg(feature = "database")]
+                recover_l2_state,
+            }),
+            _ => Err(ConfigErrors(errors)),
+        }
     }
 }
 
-fn parse_env_as<T>(env: &str) -> Result<T, String>
+fn parse_var<T>(name: &str, value: Option<String>, errors: &mut Vec<String>) -> Option<T>
 where
     T: FromStr,
     <T as FromStr>::Err: fmt::Display,
 {
-    std::env::var(env)
-        .map_err(|e| format!("Failed to fetch environment variable {}: {}", env, e))
-        .and_then(|val| {
-            val.parse::<T>()
-                .map_err(|e| format!("Failed to parse {}: {}", env, e))
-        })
+    value?
+        .parse::<T>()
+        .map_err(|e| errors.push(format!("Failed to parse {}: {}", name, e)))
+        .ok()
 }
 
-fn parse_env_as_opt<T>(env: &str) -> Result<Option<T>, String>
+/// Parses a comma separated list, see `parse_var`.
+fn parse_list_var<T>(name: &str, value: Option<String>, errors: &mut Vec<String>) -> Option<Vec<T>>
 where
     T: FromStr,
     <T as FromStr>::Err: fmt::Display,
 {
-    match std::env::var(env) {
-        Ok(val) => val
-            .parse::<T>()
-            .map(Some)
-            .map_err(|e| format!("Failed to parse {}: {}", env, e)),
-        Err(_) => Ok(None),
+    value?
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| parse_var(name, Some(item.to_string()), errors))
+        .collect()
+}
+
+fn required<T>(value: Option<T>, key: &str, errors: &mut Vec<String>) -> Option<T> {
+    if value.is_none() {
+        errors.push(format!("{} is not set", key));
     }
+    value
+}
+
+fn required_url(value: Option<String>, key: &str, errors: &mut Vec<String>) -> Option<Url> {
+    let value = required(value, key, errors)?;
+    Url::parse(&value)
+        .map_err(|e| errors.push(format!("{} {:?} is not a valid URL: {}", key, value, e)))
+        .ok()
+}
+
+/// Either the single `batch.proving_server` or the `batch.proving_servers` list must be set.
+fn proving_servers(
+    proving_server: Option<String>,
+    proving_servers: Option<Vec<PartialProvingServer>>,
+    errors: &mut Vec<String>,
+) -> Option<Vec<ProvingServer>> {
+    let servers = match (proving_server, proving_servers) {
+        (Some(_), Some(_)) => {
+            errors.push(
+                "batch.proving_server and batch.proving_servers are both set, use only one"
+                    .to_string(),
+            );
+            return None;
+        }
+        (None, None) => {
+            errors.push("batch.proving_server is not set, nor batch.proving_servers".to_string());
+            return None;
+        }
+        (Some(url), None) => vec![PartialProvingServer { url, weight: None }],
+        (None, Some(servers)) if servers.is_empty() => {
+            errors.push("batch.proving_servers must not be empty".to_string());
+            return None;
+        }
+        (None, Some(servers)) => servers,
+    };
+
+    let count = servers.len();
+    let valid: Vec<_> = servers
+        .into_iter()
+        .filter_map(|server| {
+            let url = required_url(Some(server.url), "batch.proving_servers url", errors)?;
+            let weight = server.weight.unwrap_or(1);
+            if weight == 0 {
+                errors.push(format!(
+                    "the weight of proving server {} must be greater than 0",
+                    url
+                ));
+                return None;
+            }
+            Some(ProvingServer { url, weight })
+        })
+        .collect();
+
+    (valid.len() == count).then_some(valid)
 }
 
 fn find_relative_path_up(path: &Path, max_levels: usize) -> Result<PathBuf, String> {
@@ -149,3 +751,190 @@ fn find_relative_path_up(path: &Path, max_levels: usize) -> Result<PathBuf, Stri
         path, max_levels
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn example_config() -> PartialServerConfig {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("kairos-server.example.toml");
+        PartialServerConfig::from_file(&path).expect("Failed to load the example config")
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let config = example_config().validate(Vec::new()).unwrap();
+
+        assert_eq!(config.socket_addr, "0.0.0.0:9999".parse().unwrap());
+        assert_eq!(config.casper_sync_interval, Duration::from_secs(10));
+        assert_eq!(config.casper_confirmations, 3);
+        assert_eq!(config.batch_config.max_batch_size, Some(2));
+        assert_eq!(
+            config.batch_config.max_batch_duration,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            config.batch_config.proving_servers,
+            vec![ProvingServer::new(
+                Url::parse("http://127.0.0.1:7894").unwrap()
+            )]
+        );
+        assert_eq!(config.batch_config.proving_timeout, DEFAULT_PROVING_TIMEOUT);
+        assert_eq!(config.batch_config.prover, ProverKind::Http);
+        assert_eq!(
+            config.batch_config.submission_payment,
+            DEFAULT_SUBMISSION_PAYMENT
+        );
+        assert_eq!(config.batch_config.submission_ttl, DEFAULT_SUBMISSION_TTL);
+        assert!(config.admin_config.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "database")]
+    fn test_database_address_is_redacted() {
+        let config = example_config().validate(Vec::new()).unwrap();
+
+        let debug = format!("{:#?}", config);
+        assert!(!debug.contains(&config.db_addr));
+        assert!(debug.contains("<redacted>"));
+    }
+
+    #[test]
+    fn test_proving_servers_replace_the_single_server() {
+        let vars = HashMap::from([(
+            "KAIROS_SERVER_PROVING_SERVERS",
+            "http://prover-1:7894;weight=3, http://prover-2:7894",
+        )]);
+        let mut errors = Vec::new();
+        let env = PartialServerConfig::from_vars(
+            |name| vars.get(name).map(|value| value.to_string()),
+            &mut errors,
+        );
+
+        let config = example_config().merge(env).validate(errors).unwrap();
+
+        assert_eq!(
+            config.batch_config.proving_servers,
+            vec![
+                ProvingServer {
+                    url: Url::parse("http://prover-1:7894").unwrap(),
+                    weight: 3,
+                },
+                ProvingServer::new(Url::parse("http://prover-2:7894").unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_in_process_provers_need_no_proving_server() {
+        let vars = HashMap::from([("KAIROS_SERVER_PROVER", "native")]);
+        let mut errors = Vec::new();
+        let env = PartialServerConfig::from_vars(
+            |name| vars.get(name).map(|value| value.to_string()),
+            &mut errors,
+        );
+        let mut file = example_config();
+        file.batch.proving_server = None;
+
+        let config = file.merge(env).validate(errors).unwrap();
+
+        assert_eq!(config.batch_config.prover, ProverKind::Native);
+        assert!(config.batch_config.proving_servers.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_proving_servers_are_reported() {
+        let layer = |proving_server: Option<&str>, proving_servers: Vec<PartialProvingServer>| {
+            PartialServerConfig {
+                batch: PartialBatchConfig {
+                    proving_server: proving_server.map(str::to_string),
+                    proving_servers: Some(proving_servers),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        };
+
+        for (config, expected) in [
+            (layer(Some("http://prover-1:7894"), vec![]), "are both set"),
+            (layer(None, vec![]), "must not be empty"),
+            (
+                layer(None, vec!["http://prover-1:7894;weight=0".parse().unwrap()]),
+                "must be greater than 0",
+            ),
+            (
+                layer(None, vec!["not a url".parse().unwrap()]),
+                "is not a valid URL",
+            ),
+        ] {
+            let ConfigErrors(errors) = config.validate(Vec::new()).unwrap_err();
+            assert!(
+                errors.iter().any(|error| error.contains(expected)),
+                "missing error `{expected}` in {errors:#?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_later_layers_override_earlier_ones() {
+        let vars = HashMap::from([
+            ("KAIROS_SERVER_CASPER_SYNC_INTERVAL", "20"),
+            ("KAIROS_SERVER_MAX_BATCH_SIZE", "5"),
+        ]);
+        let mut errors = Vec::new();
+        let env = PartialServerConfig::from_vars(
+            |name| vars.get(name).map(|value| value.to_string()),
+            &mut errors,
+        );
+        let cli = PartialServerConfig {
+            casper_sync_interval: Some(30),
+            ..Default::default()
+        };
+
+        let config = example_config()
+            .merge(env)
+            .merge(cli)
+            .validate(errors)
+            .unwrap();
+
+        assert_eq!(config.casper_sync_interval, Duration::from_secs(30));
+        assert_eq!(config.batch_config.max_batch_size, Some(5));
+        assert_eq!(config.batch_config.mempool_expiry, DEFAULT_MEMPOOL_EXPIRY);
+    }
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let vars = HashMap::from([
+            ("KAIROS_SERVER_SOCKET_ADDR", "not an address"),
+            ("KAIROS_SERVER_CASPER_RPC", "not a url"),
+            ("KAIROS_SERVER_CASPER_SYNC_INTERVAL", "0"),
+            ("KAIROS_SERVER_DEMO_CONTRACT_HASH", "abcd"),
+            ("KAIROS_SERVER_ADMIN_SOCKET_ADDR", "127.0.0.1:9998"),
+        ]);
+        let mut errors = Vec::new();
+        let env = PartialServerConfig::from_vars(
+            |name| vars.get(name).map(|value| value.to_string()),
+            &mut errors,
+        );
+
+        let ConfigErrors(errors) = env.validate(errors).unwrap_err();
+
+        for expected in [
+            "KAIROS_SERVER_SOCKET_ADDR",
+            "socket_addr is not set",
+            "casper_rpc \"not a url\" is not a valid URL",
+            "casper_sse is not set",
+            "casper_sync_interval must be greater than 0",
+            "demo_contract_hash \"abcd\"",
+            "batch.proving_server is not set",
+            "admin.token is required",
+        ] {
+            assert!(
+                errors.iter().any(|error| error.contains(expected)),
+                "missing error `{expected}` in {errors:#?}"
+            );
+        }
+    }
+}
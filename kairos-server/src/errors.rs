@@ -26,6 +26,10 @@ impl AppErr {
         self.status = Some(status);
         self
     }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        self.status
+    }
 }
 
 impl IntoResponse for AppErr {
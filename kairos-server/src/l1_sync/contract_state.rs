@@ -0,0 +1,135 @@
+use casper_client::{
+    rpcs::{BlockIdentifier, GlobalStateIdentifier},
+    types::{Block, StoredValue},
+    JsonRpcId, Verbosity,
+};
+use casper_client_types::{bytesrepr::FromBytes, CLTyped, ContractHash, Key};
+use contract_utils::constants::KAIROS_TRIE_ROOT;
+use rand::random;
+use reqwest::Url;
+
+use super::error::L1SyncError;
+
+/// The named key under which the Casper Event Standard stores the number of emitted events.
+const EVENTS_LENGTH: &str = "__events_length";
+
+/// A block deep enough below the tip of the chain that it can no longer be orphaned.
+#[derive(Debug, Clone)]
+pub struct FinalBlock {
+    pub height: u64,
+    pub state: GlobalStateIdentifier,
+}
+
+/// Returns the block `confirmations` blocks below the tip of the chain,
+/// `None` if the chain is not that long yet.
+pub async fn get_final_block(
+    casper_rpc: &Url,
+    confirmations: u64,
+) -> Result<Option<FinalBlock>, L1SyncError> {
+    let tip = get_block(casper_rpc, None).await?;
+    let Some(height) = tip.header().height().checked_sub(confirmations) else {
+        return Ok(None);
+    };
+
+    let block = if confirmations == 0 {
+        tip
+    } else {
+        get_block(casper_rpc, Some(BlockIdentifier::Height(height))).await?
+    };
+
+    Ok(Some(FinalBlock {
+        height,
+        state: GlobalStateIdentifier::StateRootHash(*block.header().state_root_hash()),
+    }))
+}
+
+/// Reads the number of events the contract had emitted as of `block`.
+pub async fn get_events_count(
+    casper_rpc: &Url,
+    contract_hash: ContractHash,
+    block: &FinalBlock,
+) -> Result<u32, L1SyncError> {
+    query_named_key_at(
+        casper_rpc,
+        block.state.clone(),
+        contract_hash,
+        EVENTS_LENGTH,
+    )
+    .await
+}
+
+async fn get_block(casper_rpc: &Url, block: Option<BlockIdentifier>) -> Result<Block, L1SyncError> {
+    casper_client::get_block(
+        JsonRpcId::Number(random()),
+        casper_rpc.as_str(),
+        Verbosity::Low,
+        block,
+    )
+    .await?
+    .result
+    .block
+    .ok_or_else(|| L1SyncError::Unavailable("node returned no block".into()))
+}
+
+/// Reads the trie root of the last batch accepted by the contract.
+pub async fn get_trie_root(
+    casper_rpc: &Url,
+    contract_hash: ContractHash,
+) -> Result<Option<[u8; 32]>, L1SyncError> {
+    query_named_key(casper_rpc, contract_hash, KAIROS_TRIE_ROOT).await
+}
+
+/// Reads the value stored under the contract's named key `name` at the latest state root.
+pub async fn query_named_key<T: CLTyped + FromBytes>(
+    casper_rpc: &Url,
+    contract_hash: ContractHash,
+    name: &str,
+) -> Result<T, L1SyncError> {
+    let state_root_hash = casper_client::get_state_root_hash(
+        JsonRpcId::Number(random()),
+        casper_rpc.as_str(),
+        Verbosity::Low,
+        None,
+    )
+    .await?
+    .result
+    .state_root_hash
+    .ok_or_else(|| L1SyncError::Unavailable("node returned no state root hash".into()))?;
+
+    query_named_key_at(
+        casper_rpc,
+        GlobalStateIdentifier::StateRootHash(state_root_hash),
+        contract_hash,
+        name,
+    )
+    .await
+}
+
+/// Reads the value stored under the contract's named key `name` at `state`.
+pub async fn query_named_key_at<T: CLTyped + FromBytes>(
+    casper_rpc: &Url,
+    state: GlobalStateIdentifier,
+    contract_hash: ContractHash,
+    name: &str,
+) -> Result<T, L1SyncError> {
+    let stored_value = casper_client::query_global_state(
+        JsonRpcId::Number(random()),
+        casper_rpc.as_str(),
+        Verbosity::Low,
+        state,
+        Key::Hash(contract_hash.value()),
+        vec![name.to_string()],
+    )
+    .await?
+    .result
+    .stored_value;
+
+    match stored_value {
+        StoredValue::CLValue(cl_value) => cl_value.into_t().map_err(|err| {
+            L1SyncError::UnexpectedError(format!("failed to parse named key {name}: {err}"))
+        }),
+        other => Err(L1SyncError::UnexpectedError(format!(
+            "named key {name} is not a CLValue: {other:?}"
+        ))),
+    }
+}
@@ -9,6 +9,31 @@ pub enum L1SyncError {
         error: casper_event_toolkit::error::ToolkitError,
     },
 
+    /// Casper RPC error.
+    #[error("casper client error: {error}")]
+    CasperClientError {
+        #[from]
+        error: casper_client::Error,
+    },
+
+    /// The event does not decode into the type its handler expects.
+    #[error("malformed {name} event {index}: {reason}")]
+    MalformedEvent {
+        index: u32,
+        name: String,
+        reason: String,
+    },
+
+    /// The trie thread does not accept deposits right now, e.g. while intake is paused.
+    /// The event is retried on the next sync.
+    #[error("deposit of event {index} deferred: {reason}")]
+    DepositDeferred { index: u32, reason: String },
+
+    /// A request to the node or the database failed in a way that may not happen again,
+    /// e.g. while the node is catching up. It's retried on the next sync.
+    #[error("unavailable: {0}")]
+    Unavailable(String),
+
     /// Communication error.
     #[error("channel error: {0}")]
     BrokenChannel(String),
@@ -17,3 +42,13 @@ pub enum L1SyncError {
     #[error("Unexpected error: {0}")]
     UnexpectedError(String),
 }
+
+impl L1SyncError {
+    /// Whether the failed request may succeed when it's retried.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::ToolkitError { .. } | Self::CasperClientError { .. } | Self::Unavailable(_)
+        )
+    }
+}
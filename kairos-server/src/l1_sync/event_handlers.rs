@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use axum::http::StatusCode;
+use casper_event_toolkit::casper_types::bytesrepr::FromBytes;
+
+use crate::state::{events::ServerEvent, ServerStateInner};
+use kairos_circuit_logic::transactions::L1Deposit;
+
+use super::error::L1SyncError;
+
+pub type HandlerFuture<'a> = Pin<Box<dyn Future<Output = Result<(), L1SyncError>> + Send + 'a>>;
+
+/// Applies the contract events of one CES schema to `C`, the state they drive.
+///
+/// Events are decoded into `Self::Event` before they reach the handler,
+/// see `EventHandlers::dispatch`.
+pub trait EventHandler<C>: Send + Sync {
+    /// The name of the CES schema, e.g. `L1Deposit`.
+    const NAME: &'static str;
+    type Event: FromBytes + Send;
+
+    /// Handles the event at `event_index`, it's called again for the same event
+    /// if processing fails or the server restarts, so it must be idempotent.
+    fn handle<'a>(&'a self, ctx: &'a C, event_index: u32, event: Self::Event) -> HandlerFuture<'a>;
+}
+
+/// `EventHandler` with the event type erased, so handlers can share the registry.
+trait DecodingHandler<C>: Send + Sync {
+    fn decode_and_handle<'a>(
+        &'a self,
+        ctx: &'a C,
+        event_index: u32,
+        bytes: &[u8],
+    ) -> Result<HandlerFuture<'a>, L1SyncError>;
+}
+
+impl<C, H: EventHandler<C>> DecodingHandler<C> for H {
+    fn decode_and_handle<'a>(
+        &'a self,
+        ctx: &'a C,
+        event_index: u32,
+        bytes: &[u8],
+    ) -> Result<HandlerFuture<'a>, L1SyncError> {
+        let malformed = |reason: String| L1SyncError::MalformedEvent {
+            index: event_index,
+            name: H::NAME.to_string(),
+            reason,
+        };
+
+        let (event, remainder) =
+            H::Event::from_bytes(bytes).map_err(|e| malformed(e.to_string()))?;
+        if !remainder.is_empty() {
+            return Err(malformed(format!("{} trailing bytes", remainder.len())));
+        }
+
+        Ok(self.handle(ctx, event_index, event))
+    }
+}
+
+/// The handlers of the contract events, keyed by CES schema name.
+pub struct EventHandlers<C> {
+    handlers: HashMap<&'static str, Box<dyn DecodingHandler<C>>>,
+}
+
+impl<C> Default for EventHandlers<C> {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<C> EventHandlers<C> {
+    /// Registers `handler` for the events named `H::NAME`.
+    ///
+    /// Panics if a handler is already registered for that name.
+    pub fn with<H: EventHandler<C> + 'static>(mut self, handler: H) -> Self {
+        if self.handlers.insert(H::NAME, Box::new(handler)).is_some() {
+            panic!("Event handler for {} registered twice", H::NAME);
+        }
+        self
+    }
+
+    /// Decodes the CES encoded `bytes` of the event at `event_index` and hands it to its handler.
+    /// Events no handler is registered for don't affect L2, they are skipped.
+    ///
+    /// Errors if the bytes don't decode,
+    /// the event is then retried on the next sync instead of being skipped.
+    pub async fn dispatch(
+        &self,
+        ctx: &C,
+        event_index: u32,
+        name: &str,
+        bytes: &[u8],
+    ) -> Result<(), L1SyncError> {
+        let Some(handler) = self.handlers.get(name) else {
+            tracing::warn!(
+                "Skipping event {} named {}, it has no handler",
+                event_index,
+                name
+            );
+            return Ok(());
+        };
+
+        handler.decode_and_handle(ctx, event_index, bytes)?.await
+    }
+}
+
+impl EventHandlers<ServerStateInner> {
+    /// The handlers of every event the Kairos contract emits.
+    pub fn kairos() -> Self {
+        Self::default().with(L1DepositHandler)
+    }
+}
+
+/// Credits deposits to L2.
+pub struct L1DepositHandler;
+
+impl EventHandler<ServerStateInner> for L1DepositHandler {
+    const NAME: &'static str = "L1Deposit";
+    type Event = L1Deposit;
+
+    fn handle<'a>(
+        &'a self,
+        server_state: &'a ServerStateInner,
+        event_index: u32,
+        deposit: L1Deposit,
+    ) -> HandlerFuture<'a> {
+        Box::pin(async move {
+            // The trie ignores events it already credited and records the deposits it credits,
+            // so events are safe to process again after a restart.
+            let credited = server_state
+                .batch_state_manager
+                .enqueue_deposit(event_index, deposit.clone())
+                .await
+                .map_err(|e| match e.status() {
+                    // Intake is paused, the event cursor stays put so the deposit is retried.
+                    Some(StatusCode::SERVICE_UNAVAILABLE) => L1SyncError::DepositDeferred {
+                        index: event_index,
+                        reason: e.to_string(),
+                    },
+                    _ => L1SyncError::UnexpectedError(format!("unable to batch tx: {}", e)),
+                })?;
+            if credited {
+                server_state
+                    .deposit_statuses
+                    .credited(event_index, &deposit);
+                server_state
+                    .batch_state_manager
+                    .events
+                    .publish(ServerEvent::DepositCredited { deposit });
+            } else {
+                tracing::debug!("Event {} was already credited, skipping it", event_index);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use casper_event_toolkit::casper_types::bytesrepr::ToBytes;
+
+    use super::*;
+
+    /// Records the handled deposits.
+    type Credited = Mutex<Vec<(u32, L1Deposit)>>;
+
+    struct RecordingHandler;
+
+    impl EventHandler<Credited> for RecordingHandler {
+        const NAME: &'static str = "L1Deposit";
+        type Event = L1Deposit;
+
+        fn handle<'a>(
+            &'a self,
+            credited: &'a Credited,
+            event_index: u32,
+            deposit: L1Deposit,
+        ) -> HandlerFuture<'a> {
+            Box::pin(async move {
+                credited.lock().unwrap().push((event_index, deposit));
+                Ok(())
+            })
+        }
+    }
+
+    fn deposit_bytes() -> (L1Deposit, Vec<u8>) {
+        let deposit = L1Deposit {
+            recipient: vec![1, 2, 3],
+            amount: 100,
+        };
+        let bytes = deposit.to_bytes().unwrap();
+        (deposit, bytes)
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_decodes_events_for_their_handler() {
+        let handlers = EventHandlers::default().with(RecordingHandler);
+        let credited = Credited::default();
+        let (deposit, bytes) = deposit_bytes();
+
+        handlers
+            .dispatch(&credited, 7, "L1Deposit", &bytes)
+            .await
+            .unwrap();
+
+        assert_eq!(*credited.lock().unwrap(), vec![(7, deposit)]);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_skips_unknown_and_rejects_malformed_events() {
+        let handlers = EventHandlers::default().with(RecordingHandler);
+        let credited = Credited::default();
+        let (_, mut bytes) = deposit_bytes();
+
+        // The cursor moves past events without a handler, so they must not fail.
+        handlers
+            .dispatch(&credited, 0, "Upgrade", &bytes)
+            .await
+            .unwrap();
+
+        let truncated = handlers
+            .dispatch(&credited, 1, "L1Deposit", &bytes[..bytes.len() - 1])
+            .await;
+        assert!(matches!(
+            truncated,
+            Err(L1SyncError::MalformedEvent { index: 1, .. })
+        ));
+
+        bytes.push(0);
+        let trailing = handlers.dispatch(&credited, 2, "L1Deposit", &bytes).await;
+        assert!(matches!(
+            trailing,
+            Err(L1SyncError::MalformedEvent { index: 2, .. })
+        ));
+
+        assert!(credited.lock().unwrap().is_empty());
+    }
+}
@@ -1,21 +1,21 @@
 use std::sync::Arc;
 
-use casper_event_toolkit::casper_types::bytesrepr::FromBytes;
 use casper_event_toolkit::fetcher::{Fetcher, Schemas};
 use casper_event_toolkit::metadata::CesMetadataRef;
 use casper_event_toolkit::rpc::client::CasperClient;
+use contract_utils::constants::KAIROS_UNPROCESSED_DEPOSIT_INDEX;
 
 use crate::state::ServerStateInner;
-use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
-#[cfg(feature = "database")]
-use kairos_data::transaction as db;
 
+use super::contract_state::{get_events_count, get_final_block, get_trie_root, query_named_key};
 use super::error::L1SyncError;
+use super::event_handlers::EventHandlers;
 
 pub struct EventManager {
     next_event_id: u32,
     fetcher: Fetcher,
     schemas: Schemas,
+    handlers: EventHandlers<ServerStateInner>,
     server_state: Arc<ServerStateInner>,
 }
 
@@ -39,20 +39,48 @@ impl EventManager {
         let schemas = fetcher.fetch_schema().await?;
         tracing::debug!("Schemas fetched successfully");
 
+        // Resume where the trie left off, events before the cursor were already credited.
+        let next_event_id = match server_state
+            .batch_state_manager
+            .next_event_index()
+            .await
+            .map_err(|e| {
+                L1SyncError::Unavailable(format!("unable to read the event cursor: {}", e))
+            })? {
+            Some(next_event_id) => next_event_id,
+            // Without L2 state, start from the first deposit that is not in a batch on L1 yet.
+            None => {
+                query_named_key(
+                    &server_state.server_config.casper_rpc,
+                    server_state.server_config.kairos_demo_contract_hash,
+                    KAIROS_UNPROCESSED_DEPOSIT_INDEX,
+                )
+                .await?
+            }
+        };
+        tracing::info!("Processing events from event {}", next_event_id);
+
         Ok(EventManager {
-            next_event_id: 0,
+            next_event_id,
             fetcher,
             schemas,
+            handlers: EventHandlers::kairos(),
             server_state,
         })
     }
 
     /// Processes new events starting from the last known event ID.
+    /// Only events emitted in final blocks are processed, see `ServerConfig::casper_confirmations`.
     pub async fn process_new_events(&mut self) -> Result<(), L1SyncError> {
         tracing::info!("Looking for new events");
 
         let num_events = self.fetcher.fetch_events_count().await?;
-        for i in self.next_event_id..num_events {
+        let num_final_events = self.fetch_final_events_count().await?.min(num_events);
+        let l1_sync_status = &self.server_state.l1_sync_status;
+        l1_sync_status.set_unconfirmed_events(num_events - num_final_events);
+        let l1_sync_lag = &self.server_state.batch_state_manager.metrics.l1_sync_lag;
+        l1_sync_lag.set(i64::from(num_events.saturating_sub(self.next_event_id)));
+        for i in self.next_event_id..num_final_events {
             let event = self.fetcher.fetch_event(i, &self.schemas).await?;
             tracing::debug!("Event {} fetched: {:?}.", i, event);
 
@@ -61,43 +89,38 @@ impl EventManager {
             // (koxu1996) NOTE: I think we should rather use full transaction data (ASN) for events,
             // parse them here with `kairos-tx` and then push to Data Availability layer.
 
-            match event.name.as_str() {
-                "L1Deposit" => {
-                    // Parse simplified deposit data.
-                    let (deposit, _) = L1Deposit::from_bytes(&event_bytes)
-                        .expect("Failed to parse deposit event from bytes");
-
-                    let amount = deposit.amount;
-                    let recipient: Vec<u8> = deposit.recipient;
-                    let txn = KairosTransaction::Deposit(L1Deposit { amount, recipient });
-
-                    #[cfg(feature = "database")]
-                    db::insert(&self.server_state.pool, txn.clone())
-                        .await
-                        .map_err(|e| {
-                            L1SyncError::UnexpectedError(format!(
-                                "Failed to add to database: {}",
-                                e
-                            ))
-                        })?;
-
-                    // Push deposit to trie.
-                    self.server_state
-                        .batch_state_manager
-                        .enqueue_transaction(txn)
-                        .await
-                        .map_err(|e| {
-                            L1SyncError::UnexpectedError(format!("unable to batch tx: {}", e))
-                        })?;
-                }
-                name => {
-                    tracing::error!("Unrecognized event {}", name);
-                }
-            }
+            self.handlers
+                .dispatch(&self.server_state, i, &event.name, &event_bytes)
+                .await?;
 
             self.next_event_id = i + 1;
+            l1_sync_lag.set(i64::from(num_events - self.next_event_id));
         }
 
+        // Batches are accepted independently of events, so the root is read on every sync.
+        let config = &self.server_state.server_config;
+        let l1_root = get_trie_root(&config.casper_rpc, config.kairos_demo_contract_hash).await?;
+        l1_sync_status.set_l1_root(l1_root.into());
+
+        l1_sync_status.record_sync();
         Ok(())
     }
+
+    /// The number of events the contract had emitted as of the last final block.
+    async fn fetch_final_events_count(&self) -> Result<u32, L1SyncError> {
+        let config = &self.server_state.server_config;
+        let Some(final_block) =
+            get_final_block(&config.casper_rpc, config.casper_confirmations).await?
+        else {
+            return Ok(0);
+        };
+        tracing::debug!("Last final block: {}", final_block.height);
+
+        get_events_count(
+            &config.casper_rpc,
+            config.kairos_demo_contract_hash,
+            &final_block,
+        )
+        .await
+    }
 }
@@ -1,3 +1,5 @@
+pub mod contract_state;
 pub mod error;
+pub mod event_handlers;
 pub mod event_manager;
 pub mod service;
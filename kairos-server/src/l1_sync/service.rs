@@ -1,21 +1,29 @@
-use crate::state::ServerStateInner;
+use crate::state::{deposit_status::DeployHashBytes, ServerStateInner};
 
 use super::error::L1SyncError;
 use super::event_manager::EventManager;
 
+use casper_client_types::Key;
+use casper_deploy_notifier::{DeployNotifier, Notification};
+use reqwest::Url;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 use tokio::time;
 
 use std::{sync::Arc, time::Duration};
 
+/// How long we wait before reconnecting to the node's event stream.
+const SSE_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 pub enum SyncCommand {
     TriggerSync(oneshot::Sender<()>),
     // NOTE: More commands can be here.
 }
 
+#[derive(Clone)]
 pub struct L1SyncService {
     command_sender: mpsc::Sender<SyncCommand>,
+    server_state: Arc<ServerStateInner>,
     //event_manager_handle: tokio::task::JoinHandle<()>,
 }
 
@@ -30,6 +38,7 @@ impl L1SyncService {
 
         Ok(L1SyncService {
             command_sender: tx,
+            server_state,
             //event_manager_handle: _handle,
         })
     }
@@ -59,6 +68,95 @@ impl L1SyncService {
             });
         }
     }
+
+    /// Triggers a sync as soon as the node reports a processed deploy touching the Kairos contract,
+    /// so deposits are picked up without waiting for the next periodic sync.
+    /// While contract events wait for their block to become final, each new block triggers a sync too.
+    /// The connection to `sse_url` is reestablished whenever it is lost.
+    pub async fn run_sse_triggered_sync(&self, sse_url: Url) {
+        let (tx, mut rx) = mpsc::channel(100);
+        let (block_tx, mut block_rx) = mpsc::channel(1);
+        tokio::spawn(run_deploy_notifier(sse_url, tx, block_tx));
+
+        let contract_key = Key::from(self.server_state.server_config.kairos_demo_contract_hash)
+            .to_formatted_string();
+        loop {
+            let trigger = tokio::select! {
+                Some(notification) = rx.recv() => self
+                    .is_kairos_deploy(&notification, &contract_key)
+                    .then(|| format!("deploy {}", notification.deploy_hash)),
+                Some(height) = block_rx.recv() => {
+                    let unconfirmed_events = self.server_state.l1_sync_status.unconfirmed_events();
+                    (unconfirmed_events > 0).then(|| {
+                        format!("block {}, {} events awaiting finality", height, unconfirmed_events)
+                    })
+                }
+                else => break,
+            };
+            let Some(trigger) = trigger else {
+                continue;
+            };
+
+            // One sync processes every new contract event, so the notifications
+            // that queued up in the meantime don't need a sync of their own.
+            // Their deposit statuses are still recorded.
+            while let Ok(notification) = rx.try_recv() {
+                self.is_kairos_deploy(&notification, &contract_key);
+            }
+
+            tracing::debug!("Triggering L1 sync for {}", trigger);
+            let _ = self.trigger_sync().await.map_err(|e| {
+                tracing::error!("Unable to trigger sync: {}", e);
+            });
+        }
+    }
+
+    /// Returns true if the deploy should trigger a sync.
+    /// Records the outcome of the deposit deploys forwarded by the deposit endpoint along the way,
+    /// and wakes up the batch output handler if it waits for the deploy.
+    fn is_kairos_deploy(&self, notification: &Notification, contract_key: &str) -> bool {
+        let deploy_hash = hex::decode(&notification.deploy_hash)
+            .ok()
+            .and_then(|bytes| DeployHashBytes::try_from(bytes).ok());
+        if let Some(deploy_hash) = &deploy_hash {
+            let deploy_watcher = &self.server_state.batch_state_manager.deploy_watcher;
+            deploy_watcher.processed(deploy_hash);
+        }
+
+        let deposit_statuses = &self.server_state.deposit_statuses;
+        let forwarded_deposit =
+            deploy_hash.filter(|deploy_hash| deposit_statuses.is_tracked(deploy_hash));
+        if let Some(deploy_hash) = &forwarded_deposit {
+            deposit_statuses.executed(deploy_hash, notification.error_message.clone());
+        }
+
+        notification.success && (forwarded_deposit.is_some() || notification.touches(contract_key))
+    }
+}
+
+/// Forwards the deploy notifications of the node at `sse_url` to `tx`
+/// and the heights of added blocks to `block_tx`, until `tx` is closed.
+async fn run_deploy_notifier(
+    sse_url: Url,
+    tx: mpsc::Sender<Notification>,
+    block_tx: mpsc::Sender<u64>,
+) {
+    let mut deploy_notifier = DeployNotifier::new(sse_url.as_str()).with_block_heights(block_tx);
+    while !tx.is_closed() {
+        match deploy_notifier.connect().await {
+            Ok(()) => {
+                tracing::info!("Listening to deploy notifications from {}", sse_url);
+                if let Err(e) = deploy_notifier.run(tx.clone()).await {
+                    tracing::warn!("Deploy notifications interrupted: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Unable to connect to {}: {}", sse_url, e),
+        }
+
+        // The connection can be lost, e.g. when the node restarts, so we retry after a delay.
+        // Periodic sync keeps running in the meantime.
+        time::sleep(SSE_RECONNECT_DELAY).await;
+    }
 }
 
 /// Handles incoming commands and delegates tasks to EventManager.
@@ -69,6 +167,12 @@ async fn run_event_manager(mut rx: mpsc::Receiver<SyncCommand>, mut event_manage
             .await
             .map_err(|e| match e {
                 L1SyncError::UnexpectedError(e) => panic!("Unrecoverable error: {}", e),
+                L1SyncError::DepositDeferred { .. } => {
+                    tracing::info!("{}, retrying on the next sync", e)
+                }
+                L1SyncError::Unavailable(_) => {
+                    tracing::warn!("{}, retrying on the next sync", e)
+                }
                 _ => tracing::error!("Transient error: {}", e),
             });
     }
@@ -1,22 +1,25 @@
 pub mod config;
 pub mod errors;
+pub mod metrics;
 pub mod routes;
 pub mod state;
 
 mod l1_sync;
 mod utils;
 
-use axum::Router;
+use axum::{middleware, Router};
 use axum_extra::routing::RouterExt;
-use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::RwLock;
 
 use casper_client_types::ContractHash;
 
 use crate::config::ServerConfig;
 use crate::l1_sync::service::L1SyncService;
-use crate::state::{BatchStateManager, ServerState, ServerStateInner};
+use crate::state::{
+    deposit_status::DepositStatuses, BatchStateManager, L1SyncStatus, ServerState, ServerStateInner,
+};
+#[cfg(feature = "database")]
+use crate::state::{ReplayedTransaction, StoredBatch};
 pub use errors::AppErr;
 
 #[cfg(feature = "database")]
@@ -29,10 +32,17 @@ type Signature = Vec<u8>;
 pub fn app_router(state: ServerState) -> Router {
     let mut router = Router::new()
         .typed_post(routes::deposit_handler)
+        .typed_get(routes::get_deposit_status_handler)
         .typed_post(routes::withdraw_handler)
         .typed_post(routes::transfer_handler)
         .typed_get(routes::get_chain_name_handler)
         .typed_post(routes::get_nonce_handler)
+        .typed_get(routes::get_account_handler)
+        .typed_get(routes::events_handler)
+        .typed_get(routes::get_transaction_status_handler)
+        .typed_get(routes::metrics_handler)
+        .typed_get(routes::health_handler)
+        .typed_get(routes::ready_handler)
         .typed_get(routes::contract_hash_handler);
     #[cfg(feature = "deposit-mock")]
     {
@@ -40,11 +50,29 @@ pub fn app_router(state: ServerState) -> Router {
     }
     #[cfg(feature = "database")]
     {
-        router = router.typed_post(routes::query_transactions_handler);
+        router = router
+            .typed_post(routes::query_transactions_handler)
+            .typed_get(routes::get_batches_handler)
+            .typed_get(routes::get_batch_handler);
     }
     router.with_state(state)
 }
 
+/// The admin API, it must only be served on the admin socket address, see `AdminConfig`.
+pub fn admin_router(state: ServerState) -> Router {
+    Router::new()
+        .typed_post(routes::admin::commit_handler)
+        .typed_post(routes::admin::pause_intake_handler)
+        .typed_post(routes::admin::resume_intake_handler)
+        .typed_post(routes::admin::drain_handler)
+        .typed_get(routes::admin::stats_handler)
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            routes::admin::require_admin_token,
+        ))
+        .with_state(state)
+}
+
 pub async fn run_l1_sync(server_state: Arc<ServerStateInner>) {
     // Extra check: make sure the default dummy value of contract hash was changed.
     let sync_interval = server_state.server_config.casper_sync_interval;
@@ -56,36 +84,192 @@ pub async fn run_l1_sync(server_state: Arc<ServerStateInner>) {
         return;
     }
 
-    // Initialize L1 synchronizer.
-    let l1_sync_service = L1SyncService::new(server_state).await.unwrap_or_else(|e| {
-        panic!("Event manager failed to initialize: {}", e);
+    // Initialize L1 synchronizer, the node may not be reachable yet.
+    let l1_sync_service = loop {
+        match L1SyncService::new(server_state.clone()).await {
+            Ok(l1_sync_service) => break l1_sync_service,
+            Err(e) if e.is_retryable() => {
+                tracing::warn!(
+                    "Event manager failed to initialize, retrying in {:?}: {}",
+                    sync_interval,
+                    e
+                );
+                tokio::time::sleep(sync_interval).await;
+            }
+            Err(e) => panic!("Event manager failed to initialize: {}", e),
+        }
+    };
+
+    // Sync as soon as the node reports a deploy touching the contract.
+    tokio::spawn({
+        let l1_sync_service = l1_sync_service.clone();
+        let casper_sse = server_state.server_config.casper_sse.clone();
+        async move {
+            l1_sync_service.run_sse_triggered_sync(casper_sse).await;
+        }
     });
 
-    // Run periodic synchronization.
-    // TODO: Add additional SSE trigger.
+    // Run periodic synchronization, in case a notification was missed.
     tokio::spawn(async move {
         l1_sync_service.run_periodic_sync(sync_interval).await;
     });
 }
 
+/// Rebuild the trie by replaying the committed batches and the transactions executed since,
+/// in the order the trie thread executed them,
+/// then check that one of the batches committed the trie root stored in the contract.
+///
+/// Panics if the stored transactions can't be loaded or disagree with the batches or L1,
+/// in that case the server must not start accepting transactions.
+#[cfg(feature = "database")]
+pub async fn recover_l2_state(server_state: &ServerStateInner) {
+    use kairos_data::errors::DBError;
+
+    let mut batches = kairos_data::batch::get_roots(&server_state.pool)
+        .await
+        .expect("Failed to load committed batches")
+        .into_iter()
+        .map(|(id, new_root)| {
+            let new_root = new_root
+                .map(|root| {
+                    hex::decode(&root)
+                        .ok()
+                        .and_then(|root| <[u8; 32]>::try_from(root).ok())
+                        .ok_or_else(|| {
+                            DBError::CorruptedData(format!("invalid trie root of batch {id}"))
+                        })
+                })
+                .transpose()?;
+            Ok::<_, DBError>(StoredBatch {
+                id,
+                new_root: new_root.into(),
+                transactions: Vec::new(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .expect("Failed to decode committed batches");
+
+    let mut pending = Vec::new();
+    let rows = kairos_data::transaction::get_executed(&server_state.pool)
+        .await
+        .expect("Failed to load executed transactions");
+    for row in rows {
+        let (batch_id, seq) = (row.batch_id, row.seq);
+        let event_index = row
+            .event_index()
+            .expect("Failed to decode executed transactions");
+        let txn = kairos_circuit_logic::transactions::KairosTransaction::try_from(row)
+            .expect("Failed to decode executed transactions");
+        let txn = ReplayedTransaction {
+            txn,
+            event_index,
+            seq,
+        };
+
+        match batch_id {
+            Some(batch_id) => batches
+                .iter_mut()
+                .find(|batch| batch.id == batch_id)
+                .unwrap_or_else(|| {
+                    panic!("Executed transaction refers to unknown batch {batch_id}")
+                })
+                .transactions
+                .push(txn),
+            None => pending.push(txn),
+        }
+    }
+
+    let contract_hash = server_state.server_config.kairos_demo_contract_hash;
+    let l1_root = if contract_hash == ContractHash::default() {
+        tracing::warn!(
+            "Casper contract hash not configured, the recovered trie root will NOT be checked against L1."
+        );
+        None
+    } else {
+        crate::l1_sync::contract_state::get_trie_root(
+            &server_state.server_config.casper_rpc,
+            contract_hash,
+        )
+        .await
+        .expect("Failed to read the trie root from the contract")
+    };
+
+    tracing::info!(
+        "Replaying {} committed batches and {} pending transactions",
+        batches.len(),
+        pending.len()
+    );
+    let report = server_state
+        .batch_state_manager
+        .recover(batches, pending, l1_root.into())
+        .await
+        .unwrap_or_else(|err| panic!("Failed to recover L2 state: {}", err));
+    tracing::info!(
+        "Recovered L2 state: {} batches with {} transactions replayed, {} pending for the next batch",
+        report.batches,
+        report.replayed,
+        report.pending
+    );
+}
+
 pub async fn run(config: ServerConfig) {
     let listener = tokio::net::TcpListener::bind(config.socket_addr)
         .await
         .unwrap_or_else(|err| panic!("Failed to bind to address {}: {}", config.socket_addr, err));
     tracing::info!("listening on `{}`", listener.local_addr().unwrap());
 
+    // The pool runs the migrations, so it has to be created before the trie store is opened.
+    #[cfg(feature = "database")]
+    let pool = new_pool(&config.db_addr)
+        .await
+        .expect("Failed to connect to database");
+
+    #[cfg(feature = "database")]
+    let batch_state_manager = BatchStateManager::new_persistent(&config, pool.clone())
+        .expect("Failed to set up the batch state");
+    #[cfg(not(feature = "database"))]
+    let batch_state_manager =
+        BatchStateManager::new_empty(&config).expect("Failed to set up the batch state");
+
     let state = Arc::new(ServerStateInner {
-        batch_state_manager: BatchStateManager::new_empty(&config),
+        batch_state_manager,
         server_config: config.clone(),
-        known_deposit_deploys: RwLock::new(HashSet::new()),
+        deposit_statuses: DepositStatuses::default(),
+        l1_sync_status: L1SyncStatus::default(),
         #[cfg(feature = "database")]
-        pool: new_pool(&config.db_addr)
-            .await
-            .expect("Failed to connect to database"),
+        pool,
     });
 
+    #[cfg(feature = "database")]
+    if state.server_config.recover_l2_state {
+        recover_l2_state(&state).await;
+    }
+
     run_l1_sync(state.clone()).await;
 
+    if let Some(admin_config) = &state.server_config.admin_config {
+        let admin_listener = tokio::net::TcpListener::bind(admin_config.socket_addr)
+            .await
+            .unwrap_or_else(|err| {
+                panic!(
+                    "Failed to bind to admin address {}: {}",
+                    admin_config.socket_addr, err
+                )
+            });
+        tracing::info!(
+            "admin API listening on `{}`",
+            admin_listener.local_addr().unwrap()
+        );
+
+        let admin_app = admin_router(state.clone());
+        tokio::spawn(async move {
+            axum::serve(admin_listener, admin_app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await
+                .unwrap();
+        });
+    }
+
     let app = app_router(state);
 
     axum::serve(listener, app)
@@ -1,9 +1,26 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
 use dotenvy::dotenv;
-use kairos_server::config::ServerConfig;
+use kairos_server::config::{PartialServerConfig, ServerConfig};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// TOML configuration file, overridden by the environment and the flags below.
+    #[arg(long, env = "KAIROS_SERVER_CONFIG_FILE")]
+    config: Option<PathBuf>,
+    /// Validate the configuration, print it and exit.
+    #[arg(long)]
+    check_config: bool,
+    #[command(flatten)]
+    overrides: PartialServerConfig,
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     tracing_subscriber::registry()
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()))
         .with(tracing_subscriber::fmt::layer())
@@ -13,7 +30,20 @@ async fn main() {
     // if the .env does not exist in the current directory,
     // we still go ahead and try to obtain a server config from the environment
     let _ = dotenv();
-    let config = ServerConfig::from_env()
-        .unwrap_or_else(|e| panic!("Failed to parse server config from environment: {}", e));
-    kairos_server::run(config).await
+    let cli = Cli::parse();
+    let config = match ServerConfig::load(cli.config.as_deref(), cli.overrides) {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("{}", errors);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.check_config {
+        println!("Configuration is valid:\n{:#?}", config);
+        return ExitCode::SUCCESS;
+    }
+
+    kairos_server::run(config).await;
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,190 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::state::events::ServerEvent;
+use kairos_circuit_logic::transactions::KairosTransaction;
+
+/// Prometheus metrics of the sequencer pipeline, served by `routes::metrics`.
+///
+/// Transaction and batch metrics are derived from the published `ServerEvent`s, see `Metrics::apply`.
+/// Gauges of queues are sampled when the metrics are scraped.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// Messages waiting in the `queued_transactions` channel of the trie thread.
+    pub queued_transactions: IntGauge,
+    /// Labeled by `type`, one of `deposit`, `transfer` or `withdraw`.
+    pub transactions_accepted: IntCounterVec,
+    /// Labeled by `type`, one of `deposit`, `transfer` or `withdraw`.
+    pub transactions_rejected: IntCounterVec,
+    pub batch_size: Histogram,
+    pub batch_commit_interval: Histogram,
+    pub proving_duration: Histogram,
+    pub proving_failures: IntCounter,
+    /// From sending the proof to the contract until the deploy is executed.
+    pub l1_submission_duration: Histogram,
+    pub l1_submission_failures: IntCounter,
+    pub l1_submission_gas_used: Histogram,
+    /// Contract events that were emitted but not processed yet.
+    pub l1_sync_lag: IntGauge,
+    /// Deposit deploys forwarded by the deposit endpoint that were not credited or failed yet.
+    pub pending_deposit_deploys: IntGauge,
+    /// When the last batch was committed, for `batch_commit_interval`.
+    last_commit: Arc<Mutex<Instant>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("kairos_server".to_string()), None)
+            .expect("Invalid metrics prefix");
+
+        let metrics = Self {
+            queued_transactions: IntGauge::new(
+                "queued_transactions",
+                "Messages waiting to be processed by the trie thread",
+            )
+            .expect("Invalid metric"),
+            transactions_accepted: IntCounterVec::new(
+                Opts::new(
+                    "transactions_accepted_total",
+                    "Transactions applied to a batch",
+                ),
+                &["type"],
+            )
+            .expect("Invalid metric"),
+            transactions_rejected: IntCounterVec::new(
+                Opts::new(
+                    "transactions_rejected_total",
+                    "Transactions rejected by the trie thread",
+                ),
+                &["type"],
+            )
+            .expect("Invalid metric"),
+            batch_size: histogram(
+                "batch_size",
+                "Transactions in each committed batch",
+                exponential_buckets(1.0, 2.0, 12),
+            ),
+            batch_commit_interval: histogram(
+                "batch_commit_interval_seconds",
+                "Time between two batch commits",
+                exponential_buckets(0.5, 2.0, 12),
+            ),
+            proving_duration: histogram(
+                "proving_duration_seconds",
+                "Time the proving server took to prove a batch, including failed attempts",
+                exponential_buckets(1.0, 2.0, 12),
+            ),
+            proving_failures: IntCounter::new(
+                "proving_failures_total",
+                "Failed requests to the proving server",
+            )
+            .expect("Invalid metric"),
+            l1_submission_duration: histogram(
+                "l1_submission_duration_seconds",
+                "Time from submitting a proof to the contract until the deploy is executed",
+                exponential_buckets(1.0, 2.0, 10),
+            ),
+            l1_submission_failures: IntCounter::new(
+                "l1_submission_failures_total",
+                "Proof submissions that failed or were rejected by the contract",
+            )
+            .expect("Invalid metric"),
+            l1_submission_gas_used: histogram(
+                "l1_submission_gas_used",
+                "Gas cost of the executed proof submission deploys, in motes",
+                exponential_buckets(1e8, 4.0, 12),
+            ),
+            l1_sync_lag: IntGauge::new(
+                "l1_sync_lag_events",
+                "Contract events that are not processed yet",
+            )
+            .expect("Invalid metric"),
+            pending_deposit_deploys: IntGauge::new(
+                "pending_deposit_deploys",
+                "Deposit deploys sent through the deposit endpoint that are not credited or failed yet",
+            )
+            .expect("Invalid metric"),
+            last_commit: Arc::new(Mutex::new(Instant::now())),
+            registry,
+        };
+
+        metrics.register_all();
+        metrics
+    }
+
+    fn register_all(&self) {
+        let collectors: [Box<dyn prometheus::core::Collector>; 12] = [
+            Box::new(self.queued_transactions.clone()),
+            Box::new(self.transactions_accepted.clone()),
+            Box::new(self.transactions_rejected.clone()),
+            Box::new(self.batch_size.clone()),
+            Box::new(self.batch_commit_interval.clone()),
+            Box::new(self.proving_duration.clone()),
+            Box::new(self.proving_failures.clone()),
+            Box::new(self.l1_submission_duration.clone()),
+            Box::new(self.l1_submission_failures.clone()),
+            Box::new(self.l1_submission_gas_used.clone()),
+            Box::new(self.l1_sync_lag.clone()),
+            Box::new(self.pending_deposit_deploys.clone()),
+        ];
+        for collector in collectors {
+            self.registry
+                .register(collector)
+                .expect("Metric registered twice");
+        }
+    }
+
+    /// Encodes every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|err| prometheus::Error::Msg(err.to_string()))
+    }
+
+    /// Updates the transaction and batch metrics, it's called by `EventBus::publish` for every event.
+    pub fn apply(&self, event: &ServerEvent) {
+        match event {
+            ServerEvent::TransactionAccepted { transaction } => self
+                .transactions_accepted
+                .with_label_values(&[transaction_type(transaction)])
+                .inc(),
+            ServerEvent::TransactionRejected { transaction, .. } => self
+                .transactions_rejected
+                .with_label_values(&[transaction_type(transaction)])
+                .inc(),
+            ServerEvent::BatchCommitted { transactions, .. } => {
+                self.batch_size.observe(transactions.len() as f64);
+                let mut last_commit = self.last_commit.lock().expect("poisoned lock");
+                self.batch_commit_interval
+                    .observe(last_commit.elapsed().as_secs_f64());
+                *last_commit = Instant::now();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn histogram(name: &str, help: &str, buckets: Result<Vec<f64>, prometheus::Error>) -> Histogram {
+    let buckets = buckets.expect("Invalid histogram buckets");
+    Histogram::with_opts(HistogramOpts::new(name, help).buckets(buckets)).expect("Invalid metric")
+}
+
+fn transaction_type(txn: &KairosTransaction) -> &'static str {
+    match txn {
+        KairosTransaction::Deposit(_) => "deposit",
+        KairosTransaction::Transfer(_) => "transfer",
+        KairosTransaction::Withdraw(_) => "withdraw",
+    }
+}
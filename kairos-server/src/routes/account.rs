@@ -0,0 +1,42 @@
+use anyhow::anyhow;
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::routing::TypedPath;
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{
+    state::{AccountState, ServerState},
+    AppErr,
+};
+
+#[derive(TypedPath, Deserialize, Debug, Clone)]
+#[typed_path("/api/v1/accounts/:public_key")]
+pub struct AccountPath {
+    /// Hex encoded public key of the account.
+    pub public_key: String,
+}
+
+/// Returns the balance and nonce of an account as of the last committed batch,
+/// along with Merkle proofs against its trie root and the trie root accepted on L1.
+/// Transactions that are not committed yet are reported separately as the pending change.
+/// Unknown accounts have a zero balance and nonce, the proofs then show the account is absent.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn get_account_handler(
+    AccountPath { public_key }: AccountPath,
+    State(state): State<ServerState>,
+) -> Result<Json<AccountState>, AppErr> {
+    let public_key = hex::decode(&public_key).map_err(|err| {
+        AppErr::new(anyhow!("Invalid public key {}: {}", public_key, err))
+            .set_status(StatusCode::BAD_REQUEST)
+    })?;
+
+    // Read from the L1 sync's cache, `None` until the first sync.
+    let l1_root = state.l1_sync_status.l1_root();
+
+    let account = state
+        .batch_state_manager
+        .get_account(public_key, l1_root)
+        .await?;
+
+    Ok(Json(account))
+}
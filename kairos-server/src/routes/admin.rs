@@ -0,0 +1,148 @@
+//! Operator controls for incidents and upgrades.
+//!
+//! These routes are served by `admin_router` on the admin socket address only,
+//! every request must carry the configured admin token as a bearer token.
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+    Json,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    routing::TypedPath,
+    TypedHeader,
+};
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::{
+    state::{batch_tracker::BatchId, ServerState, TrieThreadStats},
+    AppErr,
+};
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/admin/v1/commit")]
+pub struct AdminCommitPath;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/admin/v1/intake/pause")]
+pub struct AdminPauseIntakePath;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/admin/v1/intake/resume")]
+pub struct AdminResumeIntakePath;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/admin/v1/drain")]
+pub struct AdminDrainPath;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/admin/v1/stats")]
+pub struct AdminStatsPath;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitResponse {
+    pub batch_id: BatchId,
+    /// Hex encoded trie root after the batch, `None` if the trie is empty.
+    pub new_root: Option<String>,
+    pub transactions: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainResponse {
+    /// Held transactions that were rejected.
+    pub drained: usize,
+}
+
+/// Rejects requests that don't carry the admin token.
+pub async fn require_admin_token(
+    State(state): State<ServerState>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppErr> {
+    let Some(admin_config) = &state.server_config.admin_config else {
+        return Err(
+            AppErr::new(anyhow!("the admin API is disabled")).set_status(StatusCode::NOT_FOUND)
+        );
+    };
+
+    match authorization {
+        Some(TypedHeader(Authorization(bearer)))
+            if tokens_match(&admin_config.token, bearer.token()) =>
+        {
+            Ok(next.run(request).await)
+        }
+        _ => {
+            warn!("Rejected unauthorized admin request to {}", request.uri());
+            Err(AppErr::new(anyhow!("invalid admin token")).set_status(StatusCode::UNAUTHORIZED))
+        }
+    }
+}
+
+/// Compares in constant time, so the token can't be guessed byte by byte.
+fn tokens_match(expected: &str, provided: &str) -> bool {
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Commits the current batch, even if it's not full yet, and queues it for proving.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn commit_handler(
+    _: AdminCommitPath,
+    State(state): State<ServerState>,
+) -> Result<Json<CommitResponse>, AppErr> {
+    let batch_output = state.batch_state_manager.commit().await?;
+    let new_root: Option<[u8; 32]> = batch_output.new_root.into();
+    info!("Batch {:?} committed by an operator", batch_output.id);
+
+    Ok(Json(CommitResponse {
+        batch_id: batch_output.id,
+        new_root: new_root.map(hex::encode),
+        transactions: batch_output.proof_inputs.transactions.len(),
+    }))
+}
+
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn pause_intake_handler(
+    _: AdminPauseIntakePath,
+    State(state): State<ServerState>,
+) -> Result<(), AppErr> {
+    state.batch_state_manager.set_intake_paused(true).await
+}
+
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn resume_intake_handler(
+    _: AdminResumeIntakePath,
+    State(state): State<ServerState>,
+) -> Result<(), AppErr> {
+    state.batch_state_manager.set_intake_paused(false).await
+}
+
+/// Waits for the queued transactions to be processed and rejects the held ones.
+/// Pause intake first, otherwise new transactions keep arriving.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn drain_handler(
+    _: AdminDrainPath,
+    State(state): State<ServerState>,
+) -> Result<Json<DrainResponse>, AppErr> {
+    let drained = state.batch_state_manager.drain().await?;
+    Ok(Json(DrainResponse { drained }))
+}
+
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn stats_handler(
+    _: AdminStatsPath,
+    State(state): State<ServerState>,
+) -> Result<Json<TrieThreadStats>, AppErr> {
+    let stats = state.batch_state_manager.stats().await?;
+    Ok(Json(stats))
+}
@@ -0,0 +1,43 @@
+use anyhow::anyhow;
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::routing::TypedPath;
+use kairos_data::batch::{self, Batch};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{state::ServerState, AppErr};
+
+/// The number of batches returned by `/api/v1/batches`.
+const LATEST_BATCHES_LIMIT: i64 = 100;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/api/v1/batches")]
+pub struct BatchesPath;
+
+#[derive(TypedPath, Deserialize, Debug, Clone, Copy)]
+#[typed_path("/api/v1/batches/:id")]
+pub struct BatchPath {
+    pub id: i64,
+}
+
+/// Returns the most recent batches, newest first.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn get_batches_handler(
+    _: BatchesPath,
+    State(state): State<ServerState>,
+) -> Result<Json<Vec<Batch>>, AppErr> {
+    batch::get_latest(&state.pool, LATEST_BATCHES_LIMIT)
+        .await
+        .map(Json)
+        .map_err(Into::into)
+}
+
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn get_batch_handler(
+    BatchPath { id }: BatchPath,
+    State(state): State<ServerState>,
+) -> Result<Json<Batch>, AppErr> {
+    batch::get(&state.pool, id).await?.map(Json).ok_or_else(|| {
+        AppErr::new(anyhow!("Batch {} not found", id)).set_status(StatusCode::NOT_FOUND)
+    })
+}
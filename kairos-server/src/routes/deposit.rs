@@ -1,20 +1,36 @@
 use anyhow::anyhow;
-use axum::{extract::State, Json};
+use axum::{extract::State, http::StatusCode, Json};
 use axum_extra::routing::TypedPath;
+use casper_client_types::{bytesrepr::ToBytes, PublicKey, U512};
 use rand::Rng;
+use serde::Deserialize;
 use tracing::*;
 
-use crate::{state::ServerState, AppErr};
+use crate::{
+    state::{
+        deposit_status::{DeployHashBytes, DepositStatus},
+        ServerState,
+    },
+    AppErr,
+};
 use casper_client::{
     put_deploy,
     types::{Deploy, DeployHash},
     JsonRpcId,
 };
+use kairos_circuit_logic::transactions::L1Deposit;
 
 #[derive(TypedPath, Debug, Clone, Copy)]
 #[typed_path("/api/v1/deposit")]
 pub struct DepositPath;
 
+#[derive(TypedPath, Deserialize, Debug, Clone)]
+#[typed_path("/api/v1/deposits/:deploy_hash")]
+pub struct DepositStatusPath {
+    /// Hex encoded hash of the deposit deploy, as returned by the deposit endpoint.
+    pub deploy_hash: String,
+}
+
 #[instrument(level = "trace", skip(state), ret)]
 pub async fn deposit_handler(
     _: DepositPath,
@@ -30,6 +46,7 @@ pub async fn deposit_handler(
     {
         None => return Err(anyhow!("Deploy not signed by depositor").into()),
         Some(_) => {
+            let expected_deposit = expected_deposit(&body);
             let response = put_deploy(
                 expected_rpc_id.clone(),
                 state.server_config.casper_rpc.as_str(),
@@ -39,15 +56,52 @@ pub async fn deposit_handler(
             .await
             .map_err(Into::<AppErr>::into)?;
             if response.id == expected_rpc_id {
-                assert!(state
-                    .known_deposit_deploys
-                    .write()
-                    .await
-                    .insert(response.result.deploy_hash));
-                Ok(Json(response.result.deploy_hash))
+                let deploy_hash = response.result.deploy_hash;
+                state
+                    .deposit_statuses
+                    .forwarded(deploy_hash_bytes(&deploy_hash), expected_deposit);
+                Ok(Json(deploy_hash))
             } else {
                 Err(anyhow!("JSON RPC Id missmatch").into())
             }
         }
     }
 }
+
+/// Returns whether the deposit deploy is pending on L1, failed, or credited on L2.
+/// Only deploys forwarded by the deposit endpoint since the server started are known.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn get_deposit_status_handler(
+    DepositStatusPath { deploy_hash }: DepositStatusPath,
+    State(state): State<ServerState>,
+) -> Result<Json<DepositStatus>, AppErr> {
+    let hash = hex::decode(&deploy_hash)
+        .ok()
+        .and_then(|bytes| DeployHashBytes::try_from(bytes).ok())
+        .ok_or_else(|| {
+            AppErr::new(anyhow!("Invalid deploy hash {}", deploy_hash))
+                .set_status(StatusCode::BAD_REQUEST)
+        })?;
+
+    state.deposit_statuses.get(&hash).map(Json).ok_or_else(|| {
+        AppErr::new(anyhow!("Deposit deploy {} not found", deploy_hash))
+            .set_status(StatusCode::NOT_FOUND)
+    })
+}
+
+fn deploy_hash_bytes(deploy_hash: &DeployHash) -> DeployHashBytes {
+    deploy_hash.inner().value()
+}
+
+/// The deposit the contract will emit for `deploy`, read from the session arguments
+/// of the deposit session code. `None` if the deploy does not carry them.
+fn expected_deposit(deploy: &Deploy) -> Option<L1Deposit> {
+    let args = deploy.session().args();
+    let recipient: PublicKey = args.get("recipient")?.clone().into_t().ok()?;
+    let amount: U512 = args.get("amount")?.clone().into_t().ok()?;
+
+    Some(L1Deposit {
+        recipient: recipient.into_bytes().ok()?,
+        amount: u64::try_from(amount).ok()?,
+    })
+}
@@ -4,9 +4,6 @@ use tracing::*;
 
 use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
 
-#[cfg(feature = "database")]
-use kairos_data::transaction as db;
-
 use crate::{state::ServerState, AppErr};
 
 #[derive(TypedPath, Debug, Clone, Copy)]
@@ -22,7 +19,5 @@ pub async fn deposit_mock_handler(
     tracing::info!("parsing transaction data");
 
     let deposit = KairosTransaction::Deposit(deposit);
-    #[cfg(feature = "database")]
-    db::insert(&state.pool, deposit.clone()).await?;
     state.batch_state_manager.enqueue_transaction(deposit).await
 }
@@ -0,0 +1,78 @@
+use std::convert::Infallible;
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use axum_extra::routing::TypedPath;
+use serde::Deserialize;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tracing::*;
+
+use crate::{state::ServerState, AppErr};
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/api/v1/events")]
+pub struct EventsPath;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventsQuery {
+    /// Hex encoded public key, only events involving this account are sent.
+    pub public_key: Option<String>,
+}
+
+/// Streams `ServerEvent`s as server-sent events, the SSE event type is the name of the event.
+///
+/// A client that falls too far behind misses events,
+/// it is then sent a `lagged` event with the number of missed events as data.
+#[instrument(level = "trace", skip(state))]
+pub async fn events_handler(
+    _: EventsPath,
+    Query(query): Query<EventsQuery>,
+    State(state): State<ServerState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppErr> {
+    let public_key = query
+        .public_key
+        .map(|public_key| {
+            hex::decode(&public_key).map_err(|err| {
+                AppErr::new(anyhow!("Invalid public key {}: {}", public_key, err))
+                    .set_status(StatusCode::BAD_REQUEST)
+            })
+        })
+        .transpose()?;
+
+    let events = BroadcastStream::new(state.batch_state_manager.events.subscribe());
+    let stream = events.filter_map(move |event| match event {
+        Ok(event) => {
+            if let Some(public_key) = &public_key {
+                if !event.involves(public_key) {
+                    return None;
+                }
+            }
+
+            match Event::default().event(event.name()).json_data(&event) {
+                Ok(sse_event) => Some(Ok(sse_event)),
+                Err(err) => {
+                    error!("Failed to serialize event {:?}: {}", event, err);
+                    None
+                }
+            }
+        }
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            warn!(
+                "Event stream client lagged behind, {} events missed",
+                missed
+            );
+            Some(Ok(Event::default()
+                .event("lagged")
+                .data(missed.to_string())))
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
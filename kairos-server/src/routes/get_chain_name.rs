@@ -16,19 +16,15 @@ pub async fn get_chain_name_handler(
 ) -> Result<Json<String>, AppErr> {
     // Call RPC to get chain name.
     let rpc_url = &state.server_config.casper_rpc;
-    let chain_name = get_chain_name_from_rpc(rpc_url)
-        .await
-        .expect("RPC request failed");
+    let chain_name = get_chain_name_from_rpc(rpc_url).await?;
 
     Ok(Json(chain_name))
 }
 
-pub async fn get_chain_name_from_rpc(rpc_url: &Url) -> Result<String, ()> {
+pub async fn get_chain_name_from_rpc(rpc_url: &Url) -> Result<String, casper_client::Error> {
     let request_id = casper_client::JsonRpcId::Number(1);
     let verbosity = casper_client::Verbosity::Low;
-    let response = casper_client::get_node_status(request_id, rpc_url.as_str(), verbosity)
-        .await
-        .expect("RPC request failed");
+    let response = casper_client::get_node_status(request_id, rpc_url.as_str(), verbosity).await?;
     let chain_name = response.result.chainspec_name;
 
     Ok(chain_name)
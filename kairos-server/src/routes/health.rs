@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::routing::TypedPath;
+use casper_client_types::ContractHash;
+use serde::{Deserialize, Serialize};
+use tracing::*;
+
+use crate::state::ServerState;
+
+/// How long the database may take to respond to a readiness probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The L1 sync is stale once it missed this many sync intervals.
+const MAX_MISSED_L1_SYNCS: u32 = 3;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/health")]
+pub struct HealthPath;
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/ready")]
+pub struct ReadyPath;
+
+/// The outcome of the checks, the status code is 503 unless every check passed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub ok: bool,
+    pub checks: BTreeMap<String, Check>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Check {
+    fn ok(detail: Option<String>) -> Self {
+        Self { ok: true, detail }
+    }
+
+    fn failed(detail: String) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+impl HealthReport {
+    fn new(checks: BTreeMap<String, Check>) -> (StatusCode, Json<Self>) {
+        let ok = checks.values().all(|check| check.ok);
+        let status = if ok {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        (status, Json(Self { ok, checks }))
+    }
+}
+
+/// Liveness: fails if the trie thread or the batch output handler stopped, e.g. after a panic.
+/// The server can't recover from that and should be restarted.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn health_handler(
+    _: HealthPath,
+    State(state): State<ServerState>,
+) -> (StatusCode, Json<HealthReport>) {
+    HealthReport::new(liveness_checks(&state))
+}
+
+/// Readiness: the liveness checks, and whether the L1 sync, a proving server,
+/// the proof submissions and the database are working, so transactions make it to L1.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn ready_handler(
+    _: ReadyPath,
+    State(state): State<ServerState>,
+) -> (StatusCode, Json<HealthReport>) {
+    let mut checks = liveness_checks(&state);
+    checks.insert("l1_sync".to_string(), check_l1_sync(&state));
+    checks.insert(
+        "proving_server".to_string(),
+        check_proving_server(&state).await,
+    );
+    checks.insert(
+        "proof_submission".to_string(),
+        check_proof_submission(&state),
+    );
+    #[cfg(feature = "database")]
+    checks.insert("database".to_string(), check_database(&state).await);
+
+    HealthReport::new(checks)
+}
+
+fn liveness_checks(state: &ServerState) -> BTreeMap<String, Check> {
+    let batch_state_manager = &state.batch_state_manager;
+    let trie_thread = if batch_state_manager.trie_thread.is_finished() {
+        Check::failed("the trie thread stopped".to_string())
+    } else {
+        Check::ok(None)
+    };
+
+    let batch_output_status = &batch_state_manager.batch_output_status;
+    let batch_output_handler = if batch_state_manager.batch_output_handler.is_finished() {
+        Check::failed("the batch output handler stopped".to_string())
+    } else {
+        // Retries are expected, e.g. while the proving server is down, they are not fatal.
+        let consecutive_failures = batch_output_status
+            .consecutive_failures
+            .load(Ordering::Relaxed);
+        Check::ok((consecutive_failures > 0).then(|| {
+            format!(
+                "{} failed attempts, last error: {}",
+                consecutive_failures,
+                batch_output_status
+                    .last_error
+                    .lock()
+                    .expect("poisoned lock")
+                    .as_deref()
+                    .unwrap_or_default()
+            )
+        }))
+    };
+
+    BTreeMap::from([
+        ("trie_thread".to_string(), trie_thread),
+        ("batch_output_handler".to_string(), batch_output_handler),
+    ])
+}
+
+fn check_l1_sync(state: &ServerState) -> Check {
+    if state.server_config.kairos_demo_contract_hash == ContractHash::default() {
+        return Check::ok(Some("disabled, no contract hash configured".to_string()));
+    }
+
+    let max_age = state.server_config.casper_sync_interval * MAX_MISSED_L1_SYNCS;
+    match state.l1_sync_status.last_sync() {
+        Some(last_sync) if last_sync.elapsed() <= max_age => Check::ok(Some(format!(
+            "synced {}s ago",
+            last_sync.elapsed().as_secs()
+        ))),
+        Some(last_sync) => Check::failed(format!(
+            "last synced {}s ago",
+            last_sync.elapsed().as_secs()
+        )),
+        None => Check::failed("not synced yet".to_string()),
+    }
+}
+
+/// Passes if any proving server is healthy, batches are retried on the healthy ones.
+async fn check_proving_server(state: &ServerState) -> Check {
+    let Some(prover_pool) = &state.batch_state_manager.prover_pool else {
+        return Check::ok(Some(format!(
+            "batches are proven in process by the {:?} prover",
+            state.server_config.batch_config.prover
+        )));
+    };
+    let statuses = prover_pool.check_health().await;
+    let healthy = statuses.iter().filter(|status| status.healthy).count();
+    let unhealthy: Vec<_> = statuses
+        .iter()
+        .filter(|status| !status.healthy)
+        .map(|status| {
+            format!(
+                "{} is {}",
+                status.url,
+                status.last_error.as_deref().unwrap_or("unhealthy")
+            )
+        })
+        .collect();
+
+    let detail = format!("{}/{} healthy", healthy, statuses.len());
+    match (healthy, unhealthy.is_empty()) {
+        (_, true) => Check::ok(None),
+        (0, false) => Check::failed(format!("{}, {}", detail, unhealthy.join(", "))),
+        (_, false) => Check::ok(Some(format!("{}, {}", detail, unhealthy.join(", ")))),
+    }
+}
+
+/// Fails once a proof was rejected for good, the operator has to fix the batch or the contract.
+fn check_proof_submission(state: &ServerState) -> Check {
+    let status = &state.batch_state_manager.batch_output_status;
+    if !status.halted.load(Ordering::Relaxed) {
+        return Check::ok(None);
+    }
+
+    Check::failed(format!(
+        "halted, last error: {}",
+        status
+            .last_error
+            .lock()
+            .expect("poisoned lock")
+            .as_deref()
+            .unwrap_or_default()
+    ))
+}
+
+#[cfg(feature = "database")]
+async fn check_database(state: &ServerState) -> Check {
+    match tokio::time::timeout(PROBE_TIMEOUT, state.pool.get()).await {
+        Ok(Ok(_)) => Check::ok(None),
+        Ok(Err(err)) => Check::failed(format!("no connection available: {}", err)),
+        Err(_) => Check::failed("timed out waiting for a connection".to_string()),
+    }
+}
@@ -0,0 +1,38 @@
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, HeaderValue},
+    response::IntoResponse,
+};
+use axum_extra::routing::TypedPath;
+use tracing::*;
+
+use crate::{state::ServerState, AppErr};
+
+#[derive(TypedPath, Debug, Clone, Copy)]
+#[typed_path("/metrics")]
+pub struct MetricsPath;
+
+/// Serves the `Metrics` in the Prometheus text format.
+#[instrument(level = "trace", skip(state))]
+pub async fn metrics_handler(
+    _: MetricsPath,
+    State(state): State<ServerState>,
+) -> Result<impl IntoResponse, AppErr> {
+    let metrics = &state.batch_state_manager.metrics;
+    metrics
+        .queued_transactions
+        .set(state.batch_state_manager.queued_messages() as i64);
+    metrics
+        .pending_deposit_deploys
+        .set(state.deposit_statuses.pending() as i64);
+
+    let body = metrics.encode().map_err(AppErr::new)?;
+
+    Ok((
+        [(
+            CONTENT_TYPE,
+            HeaderValue::from_static(prometheus::TEXT_FORMAT),
+        )],
+        body,
+    ))
+}
@@ -1,27 +1,47 @@
+pub mod account;
+pub mod admin;
 pub mod contract_hash;
 pub mod deposit;
 #[cfg(feature = "deposit-mock")]
 pub mod deposit_mock;
+pub mod events;
 pub mod get_chain_name;
 pub mod get_nonce;
+pub mod health;
+pub mod metrics;
+pub mod transaction_status;
 pub mod transfer;
 pub mod withdraw;
 
+#[cfg(feature = "database")]
+pub mod batches;
 #[cfg(feature = "database")]
 pub mod fetch;
+pub use account::get_account_handler;
+#[cfg(feature = "database")]
+pub use batches::{get_batch_handler, get_batches_handler};
 pub use contract_hash::contract_hash_handler;
-pub use deposit::deposit_handler;
+pub use deposit::{deposit_handler, get_deposit_status_handler};
 #[cfg(feature = "deposit-mock")]
 pub use deposit_mock::deposit_mock_handler;
+pub use events::events_handler;
 #[cfg(feature = "database")]
 pub use fetch::query_transactions_handler;
 pub use get_chain_name::get_chain_name_handler;
 pub use get_nonce::get_nonce_handler;
+pub use health::{health_handler, ready_handler};
+pub use metrics::metrics_handler;
+pub use transaction_status::get_transaction_status_handler;
 pub use transfer::transfer_handler;
 pub use withdraw::withdraw_handler;
 
+use anyhow::Context;
+use axum::http::StatusCode;
+use kairos_crypto::{implementations::Signer, SignerCore};
+use kairos_tx::asn::SigningPayload;
+
 use crate::utils::{hex_to_vec, vec_to_hex};
-use crate::{PublicKey, Signature};
+use crate::{AppErr, PublicKey, Signature};
 
 use serde::{Deserialize, Serialize};
 
@@ -34,3 +54,27 @@ pub struct PayloadBody {
     #[serde(deserialize_with = "hex_to_vec", serialize_with = "vec_to_hex")]
     pub signature: Signature,
 }
+
+impl PayloadBody {
+    /// Decodes the `SigningPayload` and verifies that `signature` was produced by `public_key`.
+    ///
+    /// The signature is expected over the hash of the DER-encoded payload,
+    /// as produced by `kairos_crypto::SignerTxExtension::sign_tx_payload`.
+    pub fn verify_signature(&self) -> Result<SigningPayload, AppErr> {
+        let signing_payload: SigningPayload = self
+            .payload
+            .as_slice()
+            .try_into()
+            .context("payload err")
+            .map_err(|err| AppErr::new(err).set_status(StatusCode::BAD_REQUEST))?;
+
+        let signer = Signer::from_public_key(&self.public_key)
+            .map_err(|err| AppErr::new(err).set_status(StatusCode::BAD_REQUEST))?;
+        let tx_hash = signing_payload.hash().context("hashing payload")?;
+        signer
+            .verify(tx_hash, &self.signature)
+            .map_err(|err| AppErr::new(err).set_status(StatusCode::UNAUTHORIZED))?;
+
+        Ok(signing_payload)
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::anyhow;
+use axum::{extract::State, http::StatusCode, Json};
+use axum_extra::routing::TypedPath;
+use serde::Deserialize;
+use tracing::*;
+
+use crate::{
+    state::{
+        transaction_status::{TransactionHash, TransactionReceipt},
+        ServerState,
+    },
+    AppErr,
+};
+
+#[derive(TypedPath, Deserialize, Debug, Clone)]
+#[typed_path("/api/v1/transactions/:hash")]
+pub struct TransactionStatusPath {
+    /// Hex encoded hash of the signed payload, as returned when the transaction was submitted.
+    pub hash: String,
+}
+
+/// Returns where a transfer or withdrawal is in the pipeline towards L1.
+/// Only transactions submitted since the server started are known.
+#[instrument(level = "trace", skip(state), ret)]
+pub async fn get_transaction_status_handler(
+    TransactionStatusPath { hash }: TransactionStatusPath,
+    State(state): State<ServerState>,
+) -> Result<Json<TransactionReceipt>, AppErr> {
+    let txn_hash = hex::decode(&hash)
+        .ok()
+        .and_then(|bytes| TransactionHash::try_from(bytes).ok())
+        .ok_or_else(|| {
+            AppErr::new(anyhow!("Invalid transaction hash {}", hash))
+                .set_status(StatusCode::BAD_REQUEST)
+        })?;
+
+    let status = state
+        .batch_state_manager
+        .transaction_statuses
+        .get(&txn_hash)
+        .ok_or_else(|| {
+            AppErr::new(anyhow!("Transaction {} not found", hash)).set_status(StatusCode::NOT_FOUND)
+        })?;
+
+    Ok(Json(TransactionReceipt {
+        hash: hex::encode(txn_hash),
+        status,
+    }))
+}
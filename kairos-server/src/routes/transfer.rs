@@ -4,12 +4,13 @@ use axum_extra::routing::TypedPath;
 use tracing::instrument;
 
 use kairos_circuit_logic::transactions::{KairosTransaction, Signed, Transfer};
-use kairos_tx::asn::{SigningPayload, TransactionBody};
+use kairos_tx::asn::TransactionBody;
 
-#[cfg(feature = "database")]
-use kairos_data::transaction as db;
-
-use crate::{routes::PayloadBody, state::ServerState, AppErr};
+use crate::{
+    routes::PayloadBody,
+    state::{transaction_status::TransactionReceipt, ServerState},
+    AppErr,
+};
 
 #[derive(TypedPath)]
 #[typed_path("/api/v1/transfer")]
@@ -20,10 +21,12 @@ pub async fn transfer_handler(
     _: TransferPath,
     State(state): State<ServerState>,
     Json(body): Json<PayloadBody>,
-) -> Result<(), AppErr> {
+) -> Result<(StatusCode, Json<TransactionReceipt>), AppErr> {
+    tracing::info!("verifying transfer signature");
+    let signing_payload = body.verify_signature()?;
+    let hash = signing_payload.hash().context("hashing payload")?;
+
     tracing::info!("parsing transaction data");
-    let signing_payload: SigningPayload =
-        body.payload.as_slice().try_into().context("payload err")?;
     let transfer: Transfer = match signing_payload.body {
         TransactionBody::Transfer(transfer) => transfer.try_into().context("decoding transfer")?,
         _ => {
@@ -34,8 +37,6 @@ pub async fn transfer_handler(
     let public_key = body.public_key;
     let nonce = signing_payload.nonce.try_into().context("decoding nonce")?;
 
-    tracing::info!("TODO: verifying transfer signature");
-
     tracing::info!("queuing transaction for trie update");
 
     let transfer = KairosTransaction::Transfer(Signed {
@@ -43,10 +44,10 @@ pub async fn transfer_handler(
         nonce,
         transaction: transfer,
     });
-    #[cfg(feature = "database")]
-    db::insert(&state.pool, transfer.clone()).await?;
-    state
+    let (status, receipt) = state
         .batch_state_manager
-        .enqueue_transaction(transfer)
-        .await
+        .submit_signed_transaction(hash, transfer)
+        .await;
+
+    Ok((status, Json(receipt)))
 }
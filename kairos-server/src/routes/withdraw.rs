@@ -4,13 +4,10 @@ use axum_extra::routing::TypedPath;
 use tracing::*;
 
 use kairos_circuit_logic::transactions::{KairosTransaction, Signed, Withdraw};
-use kairos_tx::asn::{SigningPayload, TransactionBody};
-
-#[cfg(feature = "database")]
-use kairos_data::transaction as db;
+use kairos_tx::asn::TransactionBody;
 
 use crate::routes::PayloadBody;
-use crate::state::ServerState;
+use crate::state::{transaction_status::TransactionReceipt, ServerState};
 use crate::AppErr;
 
 #[derive(Debug, TypedPath)]
@@ -22,10 +19,12 @@ pub async fn withdraw_handler(
     _: WithdrawPath,
     State(state): State<ServerState>,
     Json(body): Json<PayloadBody>,
-) -> Result<(), AppErr> {
+) -> Result<(StatusCode, Json<TransactionReceipt>), AppErr> {
+    tracing::info!("verifying withdrawal signature");
+    let signing_payload = body.verify_signature()?;
+    let hash = signing_payload.hash().context("hashing payload")?;
+
     tracing::info!("parsing transaction data");
-    let signing_payload: SigningPayload =
-        body.payload.as_slice().try_into().context("payload err")?;
     let withdrawal = match signing_payload.body {
         TransactionBody::Withdrawal(withdrawal) => {
             Withdraw::try_from(withdrawal).context("decoding withdrawal")?
@@ -45,10 +44,10 @@ pub async fn withdraw_handler(
         nonce,
         transaction: withdrawal,
     });
-    #[cfg(feature = "database")]
-    db::insert(&state.pool, withdrawal.clone()).await?;
-    state
+    let (status, receipt) = state
         .batch_state_manager
-        .enqueue_transaction(withdrawal)
-        .await
+        .submit_signed_transaction(hash, withdrawal)
+        .await;
+
+    Ok((status, Json(receipt)))
 }
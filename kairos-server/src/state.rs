@@ -1,22 +1,47 @@
+pub mod batch_cost;
+pub mod batch_output_handler;
+pub mod batch_tracker;
+pub mod deposit_status;
+pub mod events;
+pub mod mempool;
+pub mod prover;
+pub mod prover_pool;
 pub mod submit_batch;
+pub mod transaction_status;
 pub mod transactions;
 mod trie;
 
-use std::collections::HashSet;
-use std::{sync::Arc, thread};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use kairos_circuit_logic::ProofOutputs;
-use risc0_zkvm::Receipt;
+use axum::http::StatusCode;
 use tokio::{
-    sync::{mpsc, RwLock},
+    sync::{mpsc, oneshot, Semaphore},
     task,
 };
 
-use casper_client::types::DeployHash;
-
-pub use self::trie::TrieStateThreadMsg;
-use crate::{config::ServerConfig, state::submit_batch::submit_proof_to_contract, PublicKey};
-use kairos_circuit_logic::transactions::KairosTransaction;
+use self::batch_output_handler::{BatchOutputHandler, BatchOutputHandlerStatus};
+use self::batch_tracker::BatchTracker;
+use self::deposit_status::DepositStatuses;
+use self::events::EventBus;
+use self::prover::{BatchProver, NativeProver};
+use self::prover_pool::ProverPool;
+use self::submit_batch::{DeployWatcher, ProofSubmitter};
+use self::transaction_status::{
+    TransactionHash, TransactionReceipt, TransactionStatus, TransactionStatuses,
+};
+pub use self::trie::{
+    AccountProof, AccountState, BatchOutput, Database, PendingChange, RecoveryReport,
+    ReplayedTransaction, StoredBatch, TrieStateThreadMsg, TrieThreadStats,
+};
+use crate::{
+    config::{BatchConfig, ProverKind, ServerConfig},
+    metrics::Metrics,
+    PublicKey,
+};
+use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
 use kairos_trie::{stored::memory_db::MemoryDb, NodeHash, TrieRoot};
 
 #[cfg(feature = "database")]
@@ -27,11 +52,52 @@ pub type ServerState = Arc<ServerStateInner>;
 pub struct ServerStateInner {
     pub batch_state_manager: BatchStateManager,
     pub server_config: ServerConfig,
-    pub known_deposit_deploys: RwLock<HashSet<DeployHash>>,
+    /// Deposit deploys forwarded by the deposit endpoint, see `routes::deposit`.
+    pub deposit_statuses: DepositStatuses,
+    pub l1_sync_status: L1SyncStatus,
     #[cfg(feature = "database")]
     pub pool: Pool,
 }
 
+/// When the L1 sync last processed the contract events, read by the readiness endpoint.
+#[derive(Debug, Default)]
+pub struct L1SyncStatus {
+    last_sync: Mutex<Option<Instant>>,
+    /// The trie root accepted by the contract as of the last sync, `None` until it's read.
+    l1_root: Mutex<Option<TrieRoot<NodeHash>>>,
+    /// Contract events in blocks that are not final yet, they are processed once they are.
+    unconfirmed_events: AtomicU32,
+}
+
+impl L1SyncStatus {
+    pub fn record_sync(&self) {
+        *self.last_sync.lock().expect("poisoned lock") = Some(Instant::now());
+    }
+
+    pub fn set_unconfirmed_events(&self, unconfirmed_events: u32) {
+        self.unconfirmed_events
+            .store(unconfirmed_events, Ordering::Relaxed);
+    }
+
+    pub fn unconfirmed_events(&self) -> u32 {
+        self.unconfirmed_events.load(Ordering::Relaxed)
+    }
+
+    /// `None` if the L1 sync never completed since the server started.
+    pub fn last_sync(&self) -> Option<Instant> {
+        *self.last_sync.lock().expect("poisoned lock")
+    }
+
+    pub fn set_l1_root(&self, l1_root: TrieRoot<NodeHash>) {
+        *self.l1_root.lock().expect("poisoned lock") = Some(l1_root);
+    }
+
+    /// Cached so account requests don't query the node, see `routes::account`.
+    pub fn l1_root(&self) -> Option<TrieRoot<NodeHash>> {
+        *self.l1_root.lock().expect("poisoned lock")
+    }
+}
+
 /// The `BatchStateManager` is a piece of Axum state.
 /// It is the entry point for interacting with the trie.
 ///
@@ -42,18 +108,37 @@ pub struct ServerStateInner {
 pub struct BatchStateManager {
     pub trie_thread: thread::JoinHandle<()>,
     pub batch_output_handler: task::JoinHandle<()>,
+    pub batch_output_status: Arc<BatchOutputHandlerStatus>,
+    /// Starts the batch output handler, `None` once it's started.
+    start_batch_output: Mutex<Option<oneshot::Sender<()>>>,
+    /// The proving servers, `None` if batches are proven in process.
+    pub prover_pool: Option<Arc<ProverPool>>,
     pub queued_transactions: mpsc::Sender<TrieStateThreadMsg>,
+    /// Publishes what happens to transactions and batches, see `routes::events`.
+    pub events: EventBus,
+    pub transaction_statuses: TransactionStatuses,
+    /// The proof deploys awaiting execution, woken up by the L1 sync's deploy notifications.
+    pub deploy_watcher: DeployWatcher,
+    pub metrics: Metrics,
 }
 
 impl BatchStateManager {
     /// Create a new `BatchStateManager` with the given `db` and `batch_root`.
     /// `batch_root` and it's descendants must be in the `db`.
+    /// `next_event_index` is the first contract event the trie at `batch_root` has not credited, if known.
+    /// The progress of each committed batch is recorded by `batch_tracker`.
     /// This method spawns the trie state thread, it should be called only once.
-    pub fn new(config: &ServerConfig, db: trie::Database, batch_root: TrieRoot<NodeHash>) -> Self {
-        let batch_config = config.batch_config.clone();
-        let casper_rpc = config.casper_rpc.clone();
-        let contract_hash = config.kairos_demo_contract_hash;
-
+    /// Batches are not proven or submitted until `start_batch_output` is called.
+    ///
+    /// Errors if the configured prover is not available, see `ServerConfig::validate`.
+    pub fn new(
+        config: &ServerConfig,
+        db: Database,
+        batch_root: TrieRoot<NodeHash>,
+        next_event_index: Option<u32>,
+        batch_tracker: BatchTracker,
+    ) -> Result<Self, crate::AppErr> {
+        let (prover, prover_pool) = new_prover(&config.batch_config)?;
         let secret_key = config
             .secret_key_file
             .as_ref()
@@ -63,103 +148,311 @@ impl BatchStateManager {
 
         let (queued_transactions, txn_receiver) = mpsc::channel(1000);
         // This queue provides back pressure to the trie thread.
-        let (batch_sender, mut batch_rec) = mpsc::channel(10);
+        let (batch_sender, batch_rec) = mpsc::channel(10);
+        let transaction_statuses = TransactionStatuses::default();
+        let metrics = Metrics::new();
+        let events = EventBus::new(transaction_statuses.clone(), metrics.clone());
         let trie_thread = trie::spawn_state_thread(
             config.batch_config.clone(),
             txn_receiver,
             batch_sender,
             db,
             batch_root,
+            next_event_index,
+            events.clone(),
         );
 
+        if let Some(tick_interval) = config.batch_config.tick_interval() {
+            tokio::spawn(send_ticks(queued_transactions.downgrade(), tick_interval));
+        }
+
+        let deploy_watcher = DeployWatcher::default();
+        let submitter = secret_key.map(|secret_key| {
+            ProofSubmitter::new(
+                config.casper_rpc.clone(),
+                config.kairos_demo_contract_hash,
+                secret_key,
+                &config.batch_config,
+                deploy_watcher.clone(),
+            )
+        });
+
+        let batch_output_status = Arc::new(BatchOutputHandlerStatus::default());
+        let handler = BatchOutputHandler {
+            prover,
+            casper_rpc: config.casper_rpc.clone(),
+            contract_hash: config.kairos_demo_contract_hash,
+            submitter,
+            tracker: batch_tracker,
+            status: batch_output_status.clone(),
+            events: events.clone(),
+            metrics: metrics.clone(),
+            unsubmitted_roots: Default::default(),
+            // The native and risc0 provers prove on this machine, one batch at a time.
+            proving_permits: Semaphore::new(
+                prover_pool
+                    .as_ref()
+                    .map_or(1, |prover_pool| prover_pool.server_count()),
+            ),
+        };
+        let (start_batch_output, batch_output_started) = oneshot::channel();
         let batch_output_handler = tokio::spawn(async move {
-            while let Some(batch_output) = batch_rec.recv().await {
-                tracing::info!(
-                    "Sending batch output to proving server: {:?}",
-                    batch_output.proof_inputs.transactions
-                );
-
-                let prove_url = batch_config
-                    .proving_server
-                    .join("/api/v1/prove/batch")
-                    .expect("Invalid URL");
-
-                let res = reqwest::Client::new()
-                    .post(prove_url)
-                    .json(&batch_output.proof_inputs)
-                    .send()
-                    .await
-                    .unwrap_or_else(|e| {
-                        tracing::error!("Could not send batch output to proving server: {}", e);
-                        panic!("Could not send batch output to proving server: {}", e);
-                    });
-
-                if res.status().is_success() {
-                    tracing::info!("Proving server returned success");
-                    let (_proof_outputs, receipt): (ProofOutputs, Receipt) =
-                        res.json().await.unwrap_or_else(|e| {
-                            tracing::error!("Could not parse response from proving server: {}", e);
-                            panic!("Could not parse response from proving server: {}", e);
-                        });
-
-                    if let Some(secret_key) = secret_key.as_ref() {
-                        submit_proof_to_contract(
-                            secret_key,
-                            contract_hash,
-                            casper_rpc.clone(),
-                            &receipt,
-                        )
-                        .await
-                    } else {
-                        tracing::warn!("No secret key provided. Not submitting proof to contract.");
-                    }
-                } else {
-                    tracing::error!("Proving server returned an error: {:?}", res);
-                    panic!("Proving server returned an error: {:?}", res);
-                }
+            if batch_output_started.await.is_ok() {
+                handler.run(batch_rec).await;
             }
         });
 
-        Self {
+        Ok(Self {
             trie_thread,
             batch_output_handler,
+            batch_output_status,
+            start_batch_output: Mutex::new(Some(start_batch_output)),
+            prover_pool,
             queued_transactions,
-        }
+            events,
+            transaction_statuses,
+            deploy_watcher,
+            metrics,
+        })
     }
 
     /// Create a new `BatchStateManager` with an empty `MemoryDb` and an empty `TrieRoot`.
     /// This is useful for testing.
-    pub fn new_empty(config: &ServerConfig) -> Self {
-        Self::new(config, MemoryDb::empty(), TrieRoot::default())
+    pub fn new_empty(config: &ServerConfig) -> Result<Self, crate::AppErr> {
+        let batch_state_manager = Self::new(
+            config,
+            Database::Memory(MemoryDb::empty()),
+            TrieRoot::default(),
+            None,
+            BatchTracker::default(),
+        )?;
+        batch_state_manager.start_batch_output();
+
+        Ok(batch_state_manager)
+    }
+
+    /// Create a new `BatchStateManager` backed by the trie store at `config.db_addr`.
+    /// The trie is reopened at the last committed root, so L2 state survives restarts.
+    /// Transactions executed after the last committed batch are replayed into the current batch.
+    /// Committed batches are tracked in the `batches` table through `pool`,
+    /// with `recover_l2_state` they are only proven and submitted once `recover` rebuilt them.
+    #[cfg(feature = "database")]
+    pub fn new_persistent(config: &ServerConfig, pool: Pool) -> Result<Self, crate::AppErr> {
+        let (db, batch_root, next_event_index) = Database::open(&config.db_addr)?;
+        tracing::info!(
+            "Reopening trie at root: {:?}, next contract event: {:?}",
+            batch_root,
+            next_event_index
+        );
+
+        let batch_state_manager = Self::new(
+            config,
+            db,
+            batch_root,
+            next_event_index,
+            BatchTracker::new(pool),
+        )?;
+        if !config.recover_l2_state {
+            batch_state_manager.start_batch_output();
+        }
+
+        Ok(batch_state_manager)
+    }
+
+    /// Starts proving and submitting the committed batches, does nothing if it's already started.
+    pub fn start_batch_output(&self) {
+        if let Some(start) = self
+            .start_batch_output
+            .lock()
+            .expect("poisoned lock")
+            .take()
+        {
+            let _ = start.send(());
+        }
+    }
+
+    /// Credits the deposit emitted by the contract event `event_index`.
+    /// Returns `false` if the trie already credited that event, e.g. before a restart.
+    pub async fn enqueue_deposit(
+        &self,
+        event_index: u32,
+        deposit: L1Deposit,
+    ) -> Result<bool, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::deposit(event_index, deposit);
+        self.request(msg, response, "deposit").await?
+    }
+
+    /// The index of the next contract event the trie will credit,
+    /// `None` if no deposit was credited and no committed root recorded it.
+    pub async fn next_event_index(&self) -> Result<Option<u32>, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::next_event_index();
+        self.request(msg, response, "next-event-index").await
     }
 
     pub async fn enqueue_transaction(&self, txn: KairosTransaction) -> Result<(), crate::AppErr> {
         let (msg, response) = TrieStateThreadMsg::transaction(txn);
+        self.request(msg, response, "transaction").await?
+    }
 
-        self.queued_transactions
-            .send(msg)
-            .await
-            .expect("Could not send transaction to trie thread");
+    /// Enqueues a transfer or withdrawal whose signed payload hashes to `hash`.
+    /// Returns a receipt along with the response status, the status of the transaction
+    /// can then be followed by its hash.
+    ///
+    /// Rejected transactions also get a receipt, with the error status and the rejection reason.
+    /// A transaction held for lower nonces is answered right away with a `Held` status.
+    pub async fn submit_signed_transaction(
+        &self,
+        hash: TransactionHash,
+        txn: KairosTransaction,
+    ) -> (StatusCode, TransactionReceipt) {
+        let hex_hash = hex::encode(hash);
+        if let Err(err) = self.enqueue_transaction(txn).await {
+            let status = err.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let receipt = TransactionReceipt {
+                hash: hex_hash,
+                status: TransactionStatus::Rejected {
+                    reason: err.to_string(),
+                },
+            };
+            return (status, receipt);
+        }
 
-        response
-            .await
-            .expect("Never received response from trie thread")
+        // The trie thread records the status before it responds.
+        let receipt = TransactionReceipt {
+            hash: hex_hash,
+            status: self
+                .transaction_statuses
+                .get(&hash)
+                .unwrap_or(TransactionStatus::Queued),
+        };
+        (StatusCode::OK, receipt)
     }
 
-    pub async fn get_nonce_for(&self, account: PublicKey) -> Result<u64, crate::AppErr> {
-        let (msg, response) = TrieStateThreadMsg::get_nonce_for(account);
+    /// Returns the state of `account` with Merkle proofs, see `TrieState::get_account`.
+    pub async fn get_account(
+        &self,
+        account: PublicKey,
+        l1_root: Option<TrieRoot<NodeHash>>,
+    ) -> Result<AccountState, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::get_account(account, l1_root);
+        self.request(msg, response, "get-account").await?
+    }
+
+    /// Rebuild the trie by replaying the committed `batches` and the `pending` transactions,
+    /// see `TrieState::recover`.
+    /// This should be called before the server starts accepting transactions,
+    /// the batch output handler is started once the trie is rebuilt.
+    pub async fn recover(
+        &self,
+        batches: Vec<StoredBatch>,
+        pending: Vec<impl Into<ReplayedTransaction>>,
+        l1_root: TrieRoot<NodeHash>,
+    ) -> Result<RecoveryReport, crate::AppErr> {
+        let pending = pending.into_iter().map(Into::into).collect();
+        let (msg, response) = TrieStateThreadMsg::recover(batches, pending, l1_root);
+        let report = self.request(msg, response, "recovery").await??;
+        self.start_batch_output();
+
+        Ok(report)
+    }
+
+    /// Commits the current batch, even if it's not full yet, and queues it for proving.
+    pub async fn commit(&self) -> Result<BatchOutput, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::commit();
+        self.request(msg, response, "commit").await?
+    }
+
+    /// Pauses or resumes transaction intake, while paused transactions are rejected.
+    pub async fn set_intake_paused(&self, paused: bool) -> Result<(), crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::set_intake_paused(paused);
+        self.request(msg, response, "pause-intake").await
+    }
+
+    /// Waits until every message queued before this call is processed,
+    /// then rejects the held transactions. Returns the number of rejected transactions.
+    pub async fn drain(&self) -> Result<usize, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::drain();
+        self.request(msg, response, "drain").await
+    }
 
+    /// The number of messages waiting in the `queued_transactions` channel.
+    pub fn queued_messages(&self) -> usize {
+        self.queued_transactions.max_capacity() - self.queued_transactions.capacity()
+    }
+
+    pub async fn stats(&self) -> Result<TrieThreadStats, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::stats();
+        self.request(msg, response, "stats").await
+    }
+
+    async fn request<T>(
+        &self,
+        msg: TrieStateThreadMsg,
+        response: oneshot::Receiver<T>,
+        name: &str,
+    ) -> Result<T, crate::AppErr> {
         self.queued_transactions.send(msg).await.map_err(|err| {
-            tracing::error!("Could not send get-nonce request to trie thread {:?}", err);
+            tracing::error!("Could not send {} request to trie thread {:?}", name, err);
             crate::AppErr::new(err)
         })?;
 
         response.await.map_err(|err| {
             tracing::error!(
-                "Never received response from the trie thread for the get-nonce-request {:?}",
+                "Never received response from the trie thread for the {} request {:?}",
+                name,
                 err
             );
             crate::AppErr::new(err)
-        })?
+        })
+    }
+
+    pub async fn get_nonce_for(&self, account: PublicKey) -> Result<u64, crate::AppErr> {
+        let (msg, response) = TrieStateThreadMsg::get_nonce_for(account);
+        self.request(msg, response, "get-nonce").await?
+    }
+}
+
+/// The prover chosen by `batch_config.prover`, and the pool of proving servers if it uses them.
+///
+/// Errors if the server was built without the chosen prover, or no proving server is configured.
+fn new_prover(
+    batch_config: &BatchConfig,
+) -> Result<(Arc<dyn BatchProver>, Option<Arc<ProverPool>>), crate::AppErr> {
+    match batch_config.prover {
+        ProverKind::Http if batch_config.proving_servers.is_empty() => Err(crate::AppErr::new(
+            anyhow::anyhow!("the http prover needs at least one proving server"),
+        )),
+        ProverKind::Http => {
+            let pool = Arc::new(ProverPool::new(
+                batch_config.proving_servers.clone(),
+                batch_config.proving_timeout,
+            ));
+            tokio::spawn(pool.clone().run_health_checks());
+            Ok((Arc::new(pool.clone()), Some(pool)))
+        }
+        #[cfg(feature = "risc0-prover")]
+        ProverKind::Risc0 => Ok((Arc::new(prover::Risc0Prover), None)),
+        #[cfg(not(feature = "risc0-prover"))]
+        ProverKind::Risc0 => Err(crate::AppErr::new(anyhow::anyhow!(
+            "the risc0 prover needs the server to be built with the `risc0-prover` feature"
+        ))),
+        ProverKind::Native => Ok((Arc::new(NativeProver), None)),
+    }
+}
+
+/// Sends `TrieStateThreadMsg::Tick` every `interval`, so the trie thread acts on time
+/// even when no transactions arrive. Stops once the `BatchStateManager` is dropped.
+async fn send_ticks(queue: mpsc::WeakSender<TrieStateThreadMsg>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+
+        let Some(queue) = queue.upgrade() else {
+            break;
+        };
+        if queue.send(TrieStateThreadMsg::Tick).await.is_err() {
+            break;
+        }
     }
 }
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::config::BatchConfig;
+use kairos_circuit_logic::transactions::{KairosTransaction, PublicKey};
+
+// Rough estimates of the guest's cycles, the budgets should leave some headroom.
+/// Cycles of every batch, e.g. for writing the journal.
+const BATCH_CYCLES: u64 = 200_000;
+/// Cycles of applying a transaction, on top of reading it.
+const TRANSACTION_CYCLES: u64 = 30_000;
+/// Cycles per word of input, the snapshot is read and then hashed before and after the batch.
+const INPUT_WORD_CYCLES: u64 = 50;
+
+/// The estimated proving cost of the current batch, see `BatchConfig::max_batch_cycles`.
+///
+/// Each account touched by the batch adds its path in the trie to the snapshot.
+/// Paths are measured on their own, so nodes shared by paths are counted once per path
+/// and the snapshot size is an upper bound.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchCost {
+    pub cycles: u64,
+    /// The size of the trie snapshot in the guest input.
+    pub snapshot_bytes: u64,
+    touched_accounts: HashSet<PublicKey>,
+}
+
+impl Default for BatchCost {
+    fn default() -> Self {
+        Self {
+            cycles: BATCH_CYCLES,
+            snapshot_bytes: 0,
+            touched_accounts: HashSet::new(),
+        }
+    }
+}
+
+impl BatchCost {
+    /// Adds an executed transaction, `path_bytes` measures the trie path of an account
+    /// that is touched for the first time in this batch.
+    pub fn add(&mut self, txn: &KairosTransaction, mut path_bytes: impl FnMut(&PublicKey) -> u64) {
+        for account in touched_accounts(txn) {
+            if self.touched_accounts.insert(account.clone()) {
+                let bytes = path_bytes(account);
+                self.snapshot_bytes += bytes;
+                self.cycles += bytes / 4 * INPUT_WORD_CYCLES;
+            }
+        }
+
+        self.cycles += TRANSACTION_CYCLES + input_bytes(txn) / 4 * INPUT_WORD_CYCLES;
+    }
+
+    /// Whether the batch reached `max_batch_cycles` or `max_batch_snapshot_bytes`.
+    pub fn reached_budget(&self, config: &BatchConfig) -> bool {
+        config
+            .max_batch_cycles
+            .is_some_and(|max_cycles| self.cycles >= max_cycles)
+            || config
+                .max_batch_snapshot_bytes
+                .is_some_and(|max_bytes| self.snapshot_bytes >= max_bytes)
+    }
+}
+
+/// The size of `value` once written to the guest, see `risc0_zkvm::serde`.
+/// Zero if it can't be serialized, the transaction is then rejected by the prover anyway.
+pub fn input_bytes(value: &impl Serialize) -> u64 {
+    risc0_zkvm::serde::to_vec(value).map_or(0, |words| words.len() as u64 * 4)
+}
+
+fn touched_accounts(txn: &KairosTransaction) -> Vec<&PublicKey> {
+    match txn {
+        KairosTransaction::Deposit(deposit) => vec![&deposit.recipient],
+        KairosTransaction::Transfer(transfer) => {
+            vec![&transfer.public_key, &transfer.transaction.recipient]
+        }
+        KairosTransaction::Withdraw(withdraw) => vec![&withdraw.public_key],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        ProverKind, DEFAULT_MEMPOOL_EXPIRY, DEFAULT_PROVING_TIMEOUT, DEFAULT_SUBMISSION_PAYMENT,
+        DEFAULT_SUBMISSION_TTL,
+    };
+    use kairos_circuit_logic::transactions::L1Deposit;
+
+    fn deposit(recipient: &[u8]) -> KairosTransaction {
+        KairosTransaction::Deposit(L1Deposit {
+            recipient: recipient.to_vec(),
+            amount: 100,
+        })
+    }
+
+    #[test]
+    fn test_each_account_path_is_counted_once() {
+        let mut cost = BatchCost::default();
+        let mut measured = Vec::new();
+
+        for txn in [deposit(b"alice"), deposit(b"bob"), deposit(b"alice")] {
+            cost.add(&txn, |account| {
+                measured.push(account.clone());
+                400
+            });
+        }
+
+        assert_eq!(measured, vec![b"alice".to_vec(), b"bob".to_vec()]);
+        assert_eq!(cost.snapshot_bytes, 800);
+        assert!(cost.cycles > BATCH_CYCLES + 3 * TRANSACTION_CYCLES + 200 * INPUT_WORD_CYCLES);
+    }
+
+    #[test]
+    fn test_budget_is_reached_by_either_limit() {
+        let mut cost = BatchCost::default();
+        cost.add(&deposit(b"alice"), |_| 400);
+
+        let config = |max_batch_cycles, max_batch_snapshot_bytes| BatchConfig {
+            max_batch_size: None,
+            max_batch_duration: None,
+            max_batch_cycles,
+            max_batch_snapshot_bytes,
+            prover: ProverKind::Native,
+            proving_servers: Vec::new(),
+            proving_timeout: DEFAULT_PROVING_TIMEOUT,
+            mempool_expiry: DEFAULT_MEMPOOL_EXPIRY,
+            submission_payment: DEFAULT_SUBMISSION_PAYMENT,
+            submission_ttl: DEFAULT_SUBMISSION_TTL,
+        };
+
+        assert!(!cost.reached_budget(&config(None, None)));
+        assert!(!cost.reached_budget(&config(Some(u64::MAX), Some(401))));
+        assert!(cost.reached_budget(&config(Some(cost.cycles), None)));
+        assert!(cost.reached_budget(&config(None, Some(400))));
+    }
+}
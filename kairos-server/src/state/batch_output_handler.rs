@@ -0,0 +1,408 @@
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use casper_client_types::{ContractHash, U512};
+use reqwest::Url;
+use risc0_zkvm::Receipt;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    task::JoinHandle,
+};
+
+use super::batch_tracker::{BatchId, BatchTracker};
+use super::events::{EventBus, ServerEvent};
+use super::prover::BatchProver;
+use super::submit_batch::{ExecutionFailure, ProofSubmitter, SubmitError};
+use super::trie::BatchOutput;
+use crate::l1_sync::contract_state::get_trie_root;
+use crate::metrics::Metrics;
+use kairos_circuit_logic::ProofInputs;
+
+/// The longest we wait before retrying a batch that failed to be proven or submitted.
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// A proof deploy that runs out of gas is submitted again with twice the payment,
+/// up to this multiple of `BatchConfig::submission_payment`.
+const MAX_PAYMENT_MULTIPLIER: u64 = 8;
+
+/// A committed batch that still has to be proven and submitted to L1.
+#[derive(Debug)]
+pub struct BatchJob {
+    pub id: BatchId,
+    pub new_root: Option<[u8; 32]>,
+    pub proof_inputs: ProofInputs,
+    /// The receipt of a previous attempt, so a proven batch is not proven again.
+    pub receipt: Option<Receipt>,
+}
+
+impl From<BatchOutput> for BatchJob {
+    fn from(batch_output: BatchOutput) -> Self {
+        Self {
+            id: batch_output.id,
+            new_root: batch_output.new_root.into(),
+            proof_inputs: batch_output.proof_inputs,
+            receipt: None,
+        }
+    }
+}
+
+/// The progress of the batch output handler, read by the health and metrics endpoints.
+#[derive(Debug, Default)]
+pub struct BatchOutputHandlerStatus {
+    /// Batches that were proven and, if a secret key is configured, accepted by the contract.
+    pub batches_completed: AtomicU64,
+    /// Failed attempts over the lifetime of the server.
+    pub failed_attempts: AtomicU64,
+    /// Failed attempts of the batch currently being processed, zero if the last attempt succeeded.
+    pub consecutive_failures: AtomicU64,
+    pub last_error: Mutex<Option<String>>,
+    /// Set once a proof was rejected for good, no proofs are submitted after it.
+    pub halted: AtomicBool,
+}
+
+impl BatchOutputHandlerStatus {
+    fn record_success(&self) {
+        self.batches_completed.fetch_add(1, Ordering::Relaxed);
+        self.clear_failures();
+    }
+
+    fn clear_failures(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self, error: &anyhow::Error) {
+        self.failed_attempts.fetch_add(1, Ordering::Relaxed);
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock().expect("poisoned lock") = Some(format!("{error:#}"));
+    }
+
+    fn halt(&self, error: &anyhow::Error) {
+        self.record_failure(error);
+        self.halted.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Proves committed batches and submits the proofs to the contract.
+///
+/// Batches are proven concurrently by the `BatchProver`,
+/// e.g. by the idle servers of a `ProverPool`, at most `proving_permits` at once.
+/// Proofs are submitted one at a time in the order the batches were committed,
+/// since each proof builds on the trie root of the previous batch.
+/// A failed batch is retried with exponential backoff until it succeeds, it's never skipped.
+/// A batch is not submitted if the contract is at its root or the root of a later batch already.
+/// A proof deploy that expires before it's executed is submitted again right away,
+/// one that runs out of gas is submitted again right away with a higher payment.
+/// A proof the contract rejects for good marks its batch failed and halts the submissions,
+/// since every later batch builds on it, see `is_permanent`.
+/// Batches that were not finalized before a restart are resumed from the `batches` table.
+pub struct BatchOutputHandler {
+    pub prover: Arc<dyn BatchProver>,
+    pub casper_rpc: Url,
+    pub contract_hash: ContractHash,
+    /// `None` if no secret key is configured, proofs are then not submitted.
+    pub submitter: Option<ProofSubmitter>,
+    pub tracker: BatchTracker,
+    pub status: Arc<BatchOutputHandlerStatus>,
+    pub events: EventBus,
+    pub metrics: Metrics,
+    /// The new roots of the dispatched batches that were not submitted yet, in the order they were committed.
+    /// The first one is the batch being submitted.
+    pub unsubmitted_roots: Mutex<VecDeque<Option<[u8; 32]>>>,
+    /// Bounds the batches proven at once to what the prover can take,
+    /// the other proving tasks wait for a permit in the order they were spawned.
+    pub proving_permits: Semaphore,
+}
+
+impl BatchOutputHandler {
+    pub async fn run(self, mut batch_rec: mpsc::Receiver<BatchOutput>) {
+        let handler = Arc::new(self);
+        // The proving tasks in the order their batches were committed.
+        let (proving_sender, mut proving_rec) = mpsc::unbounded_channel::<ProvingTask>();
+
+        let dispatch = {
+            let handler = handler.clone();
+            async move {
+                let mut backoff = retry_backoff();
+                let unfinished_jobs = loop {
+                    match handler.tracker.unfinished_jobs().await {
+                        Ok(jobs) => break jobs,
+                        Err(err) => handler.wait_before_retry(&mut backoff, &err).await,
+                    }
+                };
+                handler.status.clear_failures();
+
+                let mut last_batch: BatchId = None;
+                if !unfinished_jobs.is_empty() {
+                    tracing::info!("Resuming {} unfinished batches", unfinished_jobs.len());
+                }
+                for job in unfinished_jobs {
+                    last_batch = job.id;
+                    handler.unsubmitted(job.new_root);
+                    let _ = proving_sender.send(handler.clone().spawn_proving(job));
+                }
+
+                while let Some(batch_output) = batch_rec.recv().await {
+                    // The batch may have been committed while we loaded the unfinished batches.
+                    if batch_output.id.is_some() && batch_output.id <= last_batch {
+                        continue;
+                    }
+
+                    let job = BatchJob::from(batch_output);
+                    handler.unsubmitted(job.new_root);
+                    let _ = proving_sender.send(handler.clone().spawn_proving(job));
+                }
+            }
+        };
+
+        let submit = async {
+            while let Some(proving) = proving_rec.recv().await {
+                let (job, newly_proven) = match proving.await {
+                    Ok(proven) => proven,
+                    Err(err) => std::panic::resume_unwind(err.into_panic()),
+                };
+                if newly_proven {
+                    handler
+                        .events
+                        .publish(ServerEvent::ProofProduced { batch_id: job.id });
+                }
+
+                // Batches keep being proven, so they can be submitted once the server restarts.
+                if handler.status.halted.load(Ordering::Relaxed) {
+                    tracing::warn!("Not submitting batch {:?}, submissions are halted", job.id);
+                } else {
+                    handler.submit_with_retry(job).await;
+                }
+                handler.lock_unsubmitted_roots().pop_front();
+            }
+        };
+
+        // Both run on this task, so the liveness check sees a panic in either.
+        tokio::join!(dispatch, submit);
+    }
+
+    fn unsubmitted(&self, new_root: Option<[u8; 32]>) {
+        self.lock_unsubmitted_roots().push_back(new_root);
+    }
+
+    fn lock_unsubmitted_roots(&self) -> std::sync::MutexGuard<'_, VecDeque<Option<[u8; 32]>>> {
+        self.unsubmitted_roots.lock().expect("poisoned lock")
+    }
+
+    /// Whether the contract accepted the batch being submitted, or a later one,
+    /// i.e. `l1_root` is the new root of one of the unsubmitted batches.
+    fn is_accepted(&self, l1_root: Option<[u8; 32]>) -> bool {
+        l1_root.is_some() && self.lock_unsubmitted_roots().contains(&l1_root)
+    }
+
+    async fn read_l1_root(&self) -> Result<Option<[u8; 32]>, anyhow::Error> {
+        Ok(get_trie_root(&self.casper_rpc, self.contract_hash).await?)
+    }
+
+    fn spawn_proving(self: Arc<Self>, job: BatchJob) -> ProvingTask {
+        tokio::spawn(self.prove_with_retry(job))
+    }
+
+    /// Proves `job` unless a previous attempt did, retrying with exponential backoff.
+    /// Returns the job with its receipt and whether it was proven by this call.
+    async fn prove_with_retry(self: Arc<Self>, mut job: BatchJob) -> (BatchJob, bool) {
+        if job.receipt.is_some() {
+            return (job, false);
+        }
+
+        let mut backoff = retry_backoff();
+        loop {
+            let permit = self
+                .proving_permits
+                .acquire()
+                .await
+                .expect("the proving semaphore is never closed");
+            self.tracker.proving(job.id).await;
+            let started = Instant::now();
+            let receipt = self.prover.prove(&job.proof_inputs).await;
+            drop(permit);
+            self.metrics
+                .proving_duration
+                .observe(started.elapsed().as_secs_f64());
+
+            match receipt {
+                Ok(receipt) => {
+                    self.tracker.proved(job.id, &receipt).await;
+                    job.receipt = Some(receipt);
+                    return (job, true);
+                }
+                Err(err) => {
+                    self.metrics.proving_failures.inc();
+                    self.tracker.failed(job.id, &err).await;
+                    self.wait_before_retry(&mut backoff, &err).await;
+                }
+            }
+        }
+    }
+
+    /// Submits the proof of `job` until it succeeds, retrying with exponential backoff.
+    /// Halts the submissions if the proof is rejected for good.
+    async fn submit_with_retry(&self, mut job: BatchJob) {
+        let mut backoff = retry_backoff();
+        while let Err(err) = self.process(&mut job).await {
+            if is_permanent(&err) {
+                tracing::error!(
+                    "Batch {:?} failed for good, halting proof submissions: {:#}",
+                    job.id,
+                    err
+                );
+                self.status.halt(&err);
+                return;
+            }
+            self.wait_before_retry(&mut backoff, &err).await;
+        }
+
+        tracing::info!("Finished processing batch {:?}", job.id);
+        self.status.record_success();
+    }
+
+    async fn wait_before_retry(&self, backoff: &mut ExponentialBackoff, err: &anyhow::Error) {
+        let delay = backoff.next_backoff().unwrap_or(MAX_RETRY_INTERVAL);
+        tracing::error!(
+            "Batch processing failed, retrying in {:?}: {:#}",
+            delay,
+            err
+        );
+        self.status.record_failure(err);
+
+        tokio::time::sleep(delay).await;
+    }
+
+    async fn process(&self, job: &mut BatchJob) -> Result<(), anyhow::Error> {
+        let result = self.try_process(job).await;
+        if let Err(err) = &result {
+            self.tracker.failed(job.id, err).await;
+        }
+        result
+    }
+
+    async fn try_process(&self, job: &mut BatchJob) -> Result<(), anyhow::Error> {
+        let receipt = job
+            .receipt
+            .as_ref()
+            .context("The batch must be proven before it's submitted")?;
+
+        let Some(submitter) = self.submitter.as_ref() else {
+            tracing::warn!("No secret key provided. Not submitting proof to contract.");
+            return Ok(());
+        };
+
+        // A previous attempt may have been accepted without us observing it, e.g. before a restart,
+        // or a later batch may be accepted already.
+        // Submitting the proof again would be rejected because the root is stale.
+        if self.is_accepted(self.read_l1_root().await?) {
+            tracing::info!("Batch {:?} is already accepted by the contract", job.id);
+            self.finalized(job).await;
+            return Ok(());
+        }
+
+        let started = Instant::now();
+        let gas_used = self
+            .submit_proof(job, submitter, receipt)
+            .await
+            .inspect_err(|_| self.metrics.l1_submission_failures.inc())?;
+        let Some(gas_used) = gas_used else {
+            tracing::info!("Batch {:?} was accepted by an expired deploy", job.id);
+            self.finalized(job).await;
+            return Ok(());
+        };
+        self.metrics
+            .l1_submission_duration
+            .observe(started.elapsed().as_secs_f64());
+        self.metrics
+            .l1_submission_gas_used
+            .observe(u64::try_from(gas_used).unwrap_or(u64::MAX) as f64);
+        self.finalized(job).await;
+
+        Ok(())
+    }
+
+    /// Submits the proof of `job` and waits until the deploy is executed, returns the gas it cost.
+    /// The proof is submitted again each time its deploy expires, unless the batch was accepted anyway,
+    /// the gas it cost is then unknown and `None` is returned.
+    /// A proof that runs out of gas is submitted again with twice the payment, up to `MAX_PAYMENT_MULTIPLIER`.
+    async fn submit_proof(
+        &self,
+        job: &BatchJob,
+        submitter: &ProofSubmitter,
+        receipt: &Receipt,
+    ) -> Result<Option<U512>, anyhow::Error> {
+        let max_payment = submitter.payment().saturating_mul(MAX_PAYMENT_MULTIPLIER);
+        let mut payment = submitter.payment();
+        loop {
+            let deploy = submitter.put(receipt, payment).await?;
+            self.tracker.submitted(job.id, &deploy.hash).await;
+            self.events.publish(ServerEvent::ProofSubmitted {
+                batch_id: job.id,
+                deploy_hash: hex::encode(deploy.hash.inner()),
+            });
+
+            match submitter.wait_for_execution(&deploy).await {
+                Err(err @ SubmitError::Expired { .. }) => {
+                    // The deploy may have been executed without us observing it.
+                    if self.is_accepted(self.read_l1_root().await?) {
+                        return Ok(None);
+                    }
+                    tracing::warn!("Submitting batch {:?} again: {}", job.id, err);
+                }
+                Err(
+                    err @ SubmitError::Failed {
+                        failure: ExecutionFailure::OutOfGas,
+                        ..
+                    },
+                ) if payment < max_payment => {
+                    payment = payment.saturating_mul(2).min(max_payment);
+                    tracing::warn!(
+                        "Submitting batch {:?} again with a payment of {} motes: {}",
+                        job.id,
+                        payment,
+                        err
+                    );
+                }
+                res => return Ok(Some(res?)),
+            }
+        }
+    }
+
+    async fn finalized(&self, job: &BatchJob) {
+        self.tracker.finalized(job.id).await;
+        self.events.publish(ServerEvent::ProofFinalized {
+            batch_id: job.id,
+            new_root: job.new_root.map(hex::encode),
+        });
+    }
+}
+
+/// Whether a failed submission would fail the same way if it was retried:
+/// the contract rejected the proof for good, or the deploy ran out of gas at the highest payment.
+fn is_permanent(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<SubmitError>() {
+        Some(SubmitError::Failed { failure, .. }) => match failure {
+            ExecutionFailure::Reverted(revert) => revert.is_permanent(),
+            ExecutionFailure::OutOfGas => true,
+            ExecutionFailure::Other(_) => false,
+        },
+        _ => false,
+    }
+}
+
+/// Resolves to the proven job, see `BatchOutputHandler::prove_with_retry`.
+type ProvingTask = JoinHandle<(BatchJob, bool)>;
+
+fn retry_backoff() -> ExponentialBackoff {
+    ExponentialBackoff {
+        max_interval: MAX_RETRY_INTERVAL,
+        // Batches must be processed in order, so we never give up on a batch.
+        max_elapsed_time: None,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,120 @@
+use casper_client::types::DeployHash;
+use risc0_zkvm::Receipt;
+
+use super::batch_output_handler::BatchJob;
+
+#[cfg(feature = "database")]
+use kairos_data::{
+    batch::{self, Batch, BatchStatus, BatchUpdate},
+    Pool,
+};
+
+/// Records the progress of each batch through the proving pipeline in the `batches` table.
+///
+/// Tracking is best effort, a failure to record the progress of a batch is logged
+/// but never holds up the batch itself.
+/// Without a database all methods are no-ops.
+#[derive(Clone, Default)]
+pub struct BatchTracker {
+    #[cfg(feature = "database")]
+    pool: Option<Pool>,
+}
+
+/// The sequence number of a tracked batch, `None` if the batch is not tracked.
+pub type BatchId = Option<i64>;
+
+impl BatchTracker {
+    #[cfg(feature = "database")]
+    pub fn new(pool: Pool) -> Self {
+        Self { pool: Some(pool) }
+    }
+
+    /// Load the batches that were committed but not finalized before the last shutdown.
+    ///
+    /// Errors if the batches can't be loaded, or if a stored batch is corrupted.
+    pub async fn unfinished_jobs(&self) -> Result<Vec<BatchJob>, anyhow::Error> {
+        #[cfg(feature = "database")]
+        if let Some(pool) = &self.pool {
+            return batch::get_unfinished(pool)
+                .await?
+                .into_iter()
+                .map(batch_job_from_stored)
+                .collect();
+        }
+
+        Ok(Vec::new())
+    }
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub async fn proving(&self, id: BatchId) {
+        #[cfg(feature = "database")]
+        self.update(id, BatchUpdate::proving()).await;
+    }
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub async fn proved(&self, id: BatchId, receipt: &Receipt) {
+        #[cfg(feature = "database")]
+        match serde_json::to_value(receipt) {
+            Ok(receipt) => self.update(id, BatchUpdate::proved(receipt)).await,
+            Err(err) => tracing::error!("Failed to serialize receipt of batch {:?}: {}", id, err),
+        }
+    }
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub async fn submitted(&self, id: BatchId, deploy_hash: &DeployHash) {
+        #[cfg(feature = "database")]
+        self.update(id, BatchUpdate::submitted(hex::encode(deploy_hash.inner())))
+            .await;
+    }
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub async fn finalized(&self, id: BatchId) {
+        #[cfg(feature = "database")]
+        self.update(id, BatchUpdate::status(BatchStatus::Finalized))
+            .await;
+    }
+
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub async fn failed(&self, id: BatchId, error: &anyhow::Error) {
+        #[cfg(feature = "database")]
+        if let (Some(pool), Some(id)) = (&self.pool, id) {
+            if let Err(err) = batch::record_failure(pool, id, format!("{error:#}")).await {
+                tracing::error!("Failed to record failure of batch {}: {}", id, err);
+            }
+        }
+    }
+
+    #[cfg(feature = "database")]
+    async fn update(&self, id: BatchId, update: BatchUpdate) {
+        let (Some(pool), Some(id)) = (&self.pool, id) else {
+            return;
+        };
+
+        if let Err(err) = batch::update(pool, id, update).await {
+            tracing::error!("Failed to update batch {}: {}", id, err);
+        }
+    }
+}
+
+#[cfg(feature = "database")]
+fn batch_job_from_stored(batch: Batch) -> Result<BatchJob, anyhow::Error> {
+    use anyhow::Context;
+
+    let proof_inputs = batch
+        .proof_inputs
+        .with_context(|| format!("Batch {} has no stored proof inputs", batch.id))?;
+    let new_root = batch
+        .new_root
+        .map(|root| -> Result<[u8; 32], anyhow::Error> {
+            Ok(<[u8; 32]>::try_from(hex::decode(root)?.as_slice())?)
+        })
+        .transpose()
+        .with_context(|| format!("Batch {} has an invalid root", batch.id))?;
+
+    Ok(BatchJob {
+        id: Some(batch.id),
+        new_root,
+        proof_inputs: serde_json::from_value(proof_inputs)?,
+        receipt: batch.receipt.map(serde_json::from_value).transpose()?,
+    })
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use kairos_circuit_logic::transactions::L1Deposit;
+
+/// The most deposit deploys whose status is kept, the oldest are forgotten first.
+const MAX_TRACKED_DEPOSITS: usize = 10_000;
+
+/// The bytes of a deploy hash.
+pub type DeployHashBytes = [u8; 32];
+
+/// Where a deposit deploy forwarded by the deposit endpoint is on its way to L2.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DepositStatus {
+    /// Forwarded to L1, not executed yet.
+    Pending,
+    /// Executed on L1, the contract event was not processed yet, e.g. while its block is not final.
+    Executed,
+    /// The deploy failed on L1, nothing is credited on L2.
+    Failed { error: String },
+    /// The contract event at `event_index` was processed and `credit` was applied to L2,
+    /// the funds can be spent once the transaction is accepted.
+    Processed { event_index: u32, credit: L1Deposit },
+}
+
+impl DepositStatus {
+    fn is_awaiting_credit(&self) -> bool {
+        matches!(self, Self::Pending | Self::Executed)
+    }
+}
+
+/// The status of the deposit deploys forwarded since the server started.
+///
+/// Updated by the deposit endpoint, the deploy notifications and the `EventManager`.
+/// Contract events don't carry the deploy hash, so an event is matched to the oldest
+/// deploy awaiting credit with the same recipient and amount, preferring executed deploys.
+#[derive(Debug, Clone, Default)]
+pub struct DepositStatuses {
+    inner: Arc<Mutex<DepositStore>>,
+}
+
+#[derive(Debug, Default)]
+struct DepositStore {
+    deposits: HashMap<DeployHashBytes, TrackedDeposit>,
+    /// Deploy hashes in the order they were forwarded, used to forget the oldest deposits.
+    order: VecDeque<DeployHashBytes>,
+}
+
+#[derive(Debug)]
+struct TrackedDeposit {
+    /// The deposit expected from the deploy's session arguments, `None` if they could not be read.
+    expected: Option<L1Deposit>,
+    status: DepositStatus,
+}
+
+impl DepositStatuses {
+    pub fn get(&self, deploy_hash: &DeployHashBytes) -> Option<DepositStatus> {
+        self.lock()
+            .deposits
+            .get(deploy_hash)
+            .map(|deposit| deposit.status.clone())
+    }
+
+    pub fn is_tracked(&self, deploy_hash: &DeployHashBytes) -> bool {
+        self.lock().deposits.contains_key(deploy_hash)
+    }
+
+    /// The number of deposit deploys that were not credited or failed yet.
+    pub fn pending(&self) -> usize {
+        self.lock()
+            .deposits
+            .values()
+            .filter(|deposit| deposit.status.is_awaiting_credit())
+            .count()
+    }
+
+    /// Starts tracking a deploy the deposit endpoint put to L1.
+    pub fn forwarded(&self, deploy_hash: DeployHashBytes, expected: Option<L1Deposit>) {
+        let mut store = self.lock();
+        let deposit = TrackedDeposit {
+            expected,
+            status: DepositStatus::Pending,
+        };
+        if store.deposits.insert(deploy_hash, deposit).is_some() {
+            return;
+        }
+
+        store.order.push_back(deploy_hash);
+        if store.order.len() > MAX_TRACKED_DEPOSITS {
+            if let Some(oldest) = store.order.pop_front() {
+                store.deposits.remove(&oldest);
+            }
+        }
+    }
+
+    /// Records the execution result of a tracked deploy, reported by the deploy notifier.
+    pub fn executed(&self, deploy_hash: &DeployHashBytes, error: Option<String>) {
+        let mut store = self.lock();
+        let Some(deposit) = store.deposits.get_mut(deploy_hash) else {
+            return;
+        };
+
+        // The contract event may have been processed before the notification arrived.
+        if deposit.status == DepositStatus::Pending {
+            deposit.status = match error {
+                None => DepositStatus::Executed,
+                Some(error) => DepositStatus::Failed { error },
+            };
+        }
+    }
+
+    /// Records that the contract event `event_index` credited `credit` on L2.
+    /// Returns false if no tracked deploy matches, e.g. if the deposit was not sent through us.
+    pub fn credited(&self, event_index: u32, credit: &L1Deposit) -> bool {
+        let mut store = self.lock();
+        let DepositStore { deposits, order } = &mut *store;
+
+        let find = |status: &DepositStatus| {
+            order.iter().copied().find(|deploy_hash| {
+                deposits.get(deploy_hash).is_some_and(|deposit| {
+                    &deposit.status == status && deposit.expected.as_ref() == Some(credit)
+                })
+            })
+        };
+        // A deploy known to be executed emitted the event, unless its notification is late.
+        let matching = find(&DepositStatus::Executed).or_else(|| find(&DepositStatus::Pending));
+        let Some(deposit) = matching.and_then(|deploy_hash| deposits.get_mut(&deploy_hash)) else {
+            return false;
+        };
+
+        deposit.status = DepositStatus::Processed {
+            event_index,
+            credit: credit.clone(),
+        };
+        true
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DepositStore> {
+        self.inner.lock().expect("poisoned lock")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credit_prefers_executed_deploys() {
+        let statuses = DepositStatuses::default();
+        let deposit = L1Deposit {
+            recipient: vec![1; 32],
+            amount: 100,
+        };
+        let (older, newer) = ([1; 32], [2; 32]);
+        statuses.forwarded(older, Some(deposit.clone()));
+        statuses.forwarded(newer, Some(deposit.clone()));
+
+        // The newer deploy executed first, so its event is the one being processed.
+        statuses.executed(&newer, None);
+        assert!(statuses.credited(0, &deposit));
+        assert_eq!(statuses.get(&older), Some(DepositStatus::Pending));
+        assert_eq!(
+            statuses.get(&newer),
+            Some(DepositStatus::Processed {
+                event_index: 0,
+                credit: deposit.clone()
+            })
+        );
+
+        // Without an executed match, the oldest pending deploy is credited.
+        assert!(statuses.credited(1, &deposit));
+        assert_eq!(
+            statuses.get(&older),
+            Some(DepositStatus::Processed {
+                event_index: 1,
+                credit: deposit.clone()
+            })
+        );
+        assert!(!statuses.credited(2, &deposit));
+    }
+}
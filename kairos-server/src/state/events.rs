@@ -0,0 +1,132 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use super::batch_tracker::BatchId;
+use super::transaction_status::TransactionStatuses;
+use crate::metrics::Metrics;
+use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+
+/// The number of events buffered for each subscriber.
+/// A subscriber that falls further behind misses the oldest events,
+/// so only clients of the event stream subscribe.
+const EVENT_BUFFER_SIZE: usize = 1024;
+
+/// Something that happened on L2, pushed to clients of the event stream.
+///
+/// Roots and deploy hashes are hex encoded, like in the `batches` API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// The trie thread applied the transaction to the current batch.
+    TransactionAccepted { transaction: KairosTransaction },
+    /// The transaction's nonce is ahead of its account's, it's held until the gap is filled.
+    TransactionHeld { transaction: KairosTransaction },
+    /// The trie thread rejected the transaction, e.g. because of a bad nonce or balance.
+    TransactionRejected {
+        transaction: KairosTransaction,
+        reason: String,
+    },
+    /// A deposit observed on L1 was credited on L2.
+    DepositCredited { deposit: L1Deposit },
+    BatchCommitted {
+        batch_id: BatchId,
+        old_root: Option<String>,
+        new_root: Option<String>,
+        transactions: Box<[KairosTransaction]>,
+    },
+    /// The proving server returned a proof of the batch.
+    ProofProduced { batch_id: BatchId },
+    /// The proof of the batch was sent to the contract.
+    ProofSubmitted {
+        batch_id: BatchId,
+        deploy_hash: String,
+    },
+    /// The contract accepted the proof, `new_root` is now the L1 trie root.
+    ProofFinalized {
+        batch_id: BatchId,
+        new_root: Option<String>,
+    },
+}
+
+impl ServerEvent {
+    /// The name of the event, used as the SSE event type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TransactionAccepted { .. } => "transaction_accepted",
+            Self::TransactionHeld { .. } => "transaction_held",
+            Self::TransactionRejected { .. } => "transaction_rejected",
+            Self::DepositCredited { .. } => "deposit_credited",
+            Self::BatchCommitted { .. } => "batch_committed",
+            Self::ProofProduced { .. } => "proof_produced",
+            Self::ProofSubmitted { .. } => "proof_submitted",
+            Self::ProofFinalized { .. } => "proof_finalized",
+        }
+    }
+
+    /// Returns true if a client watching `public_key` should receive this event.
+    ///
+    /// Proof events are not tied to an account, they are always delivered
+    /// so clients can follow a batch they saw committed through to L1.
+    pub fn involves(&self, public_key: &[u8]) -> bool {
+        match self {
+            Self::TransactionAccepted { transaction }
+            | Self::TransactionHeld { transaction }
+            | Self::TransactionRejected { transaction, .. } => {
+                transaction_involves(transaction, public_key)
+            }
+            Self::DepositCredited { deposit } => deposit.recipient == public_key,
+            Self::BatchCommitted { transactions, .. } => transactions
+                .iter()
+                .any(|txn| transaction_involves(txn, public_key)),
+            Self::ProofProduced { .. }
+            | Self::ProofSubmitted { .. }
+            | Self::ProofFinalized { .. } => true,
+        }
+    }
+}
+
+fn transaction_involves(txn: &KairosTransaction, public_key: &[u8]) -> bool {
+    match txn {
+        KairosTransaction::Transfer(transfer) => {
+            transfer.public_key == public_key || transfer.transaction.recipient == public_key
+        }
+        KairosTransaction::Withdraw(withdraw) => withdraw.public_key == public_key,
+        KairosTransaction::Deposit(deposit) => deposit.recipient == public_key,
+    }
+}
+
+/// Records `ServerEvent`s in the transaction statuses and metrics,
+/// then fans them out to every subscriber of the event stream.
+///
+/// The statuses and metrics are updated by the publisher, so unlike subscribers they never miss an event.
+/// Publishing never blocks on subscribers and never fails, so it is safe to call from the trie thread.
+/// Events published while nobody is subscribed are dropped.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ServerEvent>,
+    transaction_statuses: TransactionStatuses,
+    metrics: Metrics,
+}
+
+impl EventBus {
+    pub fn new(transaction_statuses: TransactionStatuses, metrics: Metrics) -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUFFER_SIZE);
+        Self {
+            sender,
+            transaction_statuses,
+            metrics,
+        }
+    }
+
+    pub fn publish(&self, event: ServerEvent) {
+        tracing::trace!("Publishing event: {:?}", event);
+        self.transaction_statuses.apply(&event);
+        self.metrics.apply(&event);
+        // An error only means there are no subscribers.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.sender.subscribe()
+    }
+}
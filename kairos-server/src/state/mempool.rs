@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+
+use crate::{AppErr, PublicKey};
+use kairos_circuit_logic::transactions::KairosTransaction;
+
+/// The furthest a transaction's nonce may be ahead of its account's nonce to be held.
+pub const MAX_NONCE_GAP: u64 = 16;
+/// The most transactions held across all accounts.
+pub const MAX_HELD_TRANSACTIONS: usize = 10_000;
+
+/// A transaction waiting for the transactions with lower nonces from the same account.
+///
+/// The submitter is answered as soon as the transaction is held,
+/// its outcome is only reported through the transaction status.
+#[derive(Debug)]
+pub struct HeldTransaction {
+    pub txn: KairosTransaction,
+    held_at: Instant,
+}
+
+impl HeldTransaction {
+    pub fn new(txn: KairosTransaction) -> Self {
+        Self {
+            txn,
+            held_at: Instant::now(),
+        }
+    }
+}
+
+/// Holds transfers and withdrawals whose nonce is ahead of their account's nonce.
+///
+/// `Account::check_nonce` requires an exact match, so without the mempool a client
+/// that sends nonce N+1 just before N gets a conflict.
+/// The trie thread holds such transactions here and releases them once the nonce gap is filled,
+/// which lets clients pipeline transactions without waiting for each response.
+/// Transactions still held after `expiry` are rejected.
+#[derive(Debug)]
+pub struct Mempool {
+    expiry: Duration,
+    held: HashMap<PublicKey, BTreeMap<u64, HeldTransaction>>,
+    len: usize,
+}
+
+impl Mempool {
+    /// A zero `expiry` disables the mempool, transactions are then never held.
+    pub fn new(expiry: Duration) -> Self {
+        Self {
+            expiry,
+            held: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.expiry.is_zero()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Holds `held` until `account` reaches `nonce`, `account_nonce` is the current nonce of the account.
+    ///
+    /// Errors if the nonce is too far ahead, if a transaction with the same nonce is already held,
+    /// or if the mempool is full. The transaction is then handed back along with the error.
+    pub fn hold(
+        &mut self,
+        account: PublicKey,
+        nonce: u64,
+        account_nonce: u64,
+        held: HeldTransaction,
+    ) -> Result<(), (HeldTransaction, AppErr)> {
+        if nonce > account_nonce.saturating_add(MAX_NONCE_GAP) {
+            let err = AppErr::new(anyhow!(
+                "nonce {} is more than {} ahead of the account nonce {}",
+                nonce,
+                MAX_NONCE_GAP,
+                account_nonce
+            ))
+            .set_status(StatusCode::CONFLICT);
+            return Err((held, err));
+        }
+        if self.len >= MAX_HELD_TRANSACTIONS {
+            let err = AppErr::new(anyhow!("too many transactions are waiting for their nonce"))
+                .set_status(StatusCode::SERVICE_UNAVAILABLE);
+            return Err((held, err));
+        }
+
+        let account_txns = self.held.entry(account).or_default();
+        if account_txns.contains_key(&nonce) {
+            let err = AppErr::new(anyhow!(
+                "a transaction with nonce {} is already waiting",
+                nonce
+            ))
+            .set_status(StatusCode::CONFLICT);
+            return Err((held, err));
+        }
+
+        account_txns.insert(nonce, held);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the transaction of `account` with `nonce`, if one is held.
+    pub fn release(&mut self, account: &PublicKey, nonce: u64) -> Option<HeldTransaction> {
+        let account_txns = self.held.get_mut(account)?;
+        let held = account_txns.remove(&nonce)?;
+        if account_txns.is_empty() {
+            self.held.remove(account);
+        }
+
+        self.len -= 1;
+        Some(held)
+    }
+
+    /// Removes and returns the transactions that were held for longer than `expiry`.
+    pub fn expire(&mut self, now: Instant) -> Vec<HeldTransaction> {
+        let expiry = self.expiry;
+        let mut expired = Vec::new();
+        self.held.retain(|_, account_txns| {
+            let (keep, expire): (BTreeMap<_, _>, BTreeMap<_, _>) = std::mem::take(account_txns)
+                .into_iter()
+                .partition(|(_, held)| now.duration_since(held.held_at) < expiry);
+            *account_txns = keep;
+            expired.extend(expire.into_values());
+
+            !account_txns.is_empty()
+        });
+
+        self.len -= expired.len();
+        expired
+    }
+
+    /// Removes and returns every held transaction.
+    pub fn drain(&mut self) -> Vec<HeldTransaction> {
+        self.len = 0;
+        self.held
+            .drain()
+            .flat_map(|(_, account_txns)| account_txns.into_values())
+            .collect()
+    }
+}
@@ -0,0 +1,226 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use anyhow::Context;
+use reqwest::{StatusCode, Url};
+use risc0_zkvm::{FakeReceipt, InnerReceipt, Receipt, ReceiptClaim};
+use thiserror::Error;
+
+use kairos_circuit_logic::{ProofInputs, ProofOutputs};
+use kairos_verifier_risc0_lib::BATCH_CIRCUIT_PROGRAM_HASH;
+
+pub type ProveFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Receipt, anyhow::Error>> + Send + 'a>>;
+
+/// Proves the committed batches for the `BatchOutputHandler`.
+///
+/// Failed proofs are retried by the caller, so implementations don't retry on their own.
+pub trait BatchProver: Send + Sync {
+    fn prove<'a>(&'a self, proof_inputs: &'a ProofInputs) -> ProveFuture<'a>;
+}
+
+/// A kairos-prover-risc0-server, reached over HTTP.
+#[derive(Debug, Clone)]
+pub struct HttpProver {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl HttpProver {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Sends `proof_inputs` to the proving server and returns the receipt.
+    pub async fn prove_batch(
+        &self,
+        proof_inputs: &ProofInputs,
+    ) -> Result<Receipt, HttpProverError> {
+        tracing::info!(
+            "Sending batch output to proving server {}: {:?}",
+            self.url,
+            proof_inputs.transactions
+        );
+
+        let prove_url = self.url.join("/api/v1/prove/batch").expect("Invalid URL");
+        let unreachable = |source| HttpProverError::Unreachable {
+            url: self.url.clone(),
+            source,
+        };
+
+        let res = self
+            .client
+            .post(prove_url)
+            .json(proof_inputs)
+            .send()
+            .await
+            .map_err(unreachable)?;
+
+        let status = res.status();
+        let body = res.bytes().await.map_err(unreachable)?;
+        if !status.is_success() {
+            return Err(HttpProverError::Rejected {
+                url: self.url.clone(),
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+
+        tracing::info!("Proving server returned success");
+        let (_proof_outputs, receipt): (ProofOutputs, Receipt) = serde_json::from_slice(&body)
+            .map_err(|source| HttpProverError::InvalidResponse {
+                url: self.url.clone(),
+                source,
+            })?;
+
+        Ok(receipt)
+    }
+}
+
+impl BatchProver for HttpProver {
+    fn prove<'a>(&'a self, proof_inputs: &'a ProofInputs) -> ProveFuture<'a> {
+        Box::pin(async move { Ok(self.prove_batch(proof_inputs).await?) })
+    }
+}
+
+/// Why a proving server did not return a receipt.
+#[derive(Debug, Error)]
+pub enum HttpProverError {
+    /// The request or the response was lost, the server may be down.
+    #[error("could not reach proving server {url}: {source}")]
+    Unreachable { url: Url, source: reqwest::Error },
+
+    /// The server responded with an error for this batch.
+    #[error("proving server {url} returned {status}: {body}")]
+    Rejected {
+        url: Url,
+        status: StatusCode,
+        body: String,
+    },
+
+    #[error("could not parse response from proving server {url}: {source}")]
+    InvalidResponse { url: Url, source: serde_json::Error },
+}
+
+impl HttpProverError {
+    /// Whether the server could not be reached, rather than responding with an error.
+    pub fn is_unreachable(&self) -> bool {
+        matches!(self, Self::Unreachable { .. })
+    }
+
+    /// Whether the server refused the batch itself, another server would refuse it too.
+    /// Server errors, e.g. of an overloaded server, may not happen on another server.
+    pub fn is_batch_rejected(&self) -> bool {
+        matches!(self, Self::Rejected { status, .. } if status.is_client_error())
+    }
+}
+
+/// Proves batches with risc0 in the server process.
+///
+/// Uses `risc0_zkvm::default_prover`, so `RISC0_DEV_MODE=1` skips the proof
+/// and returns a fake receipt, like the proving server does.
+#[cfg(feature = "risc0-prover")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Risc0Prover;
+
+#[cfg(feature = "risc0-prover")]
+impl BatchProver for Risc0Prover {
+    fn prove<'a>(&'a self, proof_inputs: &'a ProofInputs) -> ProveFuture<'a> {
+        let proof_inputs = proof_inputs.clone();
+        Box::pin(async move {
+            // Proving takes minutes of CPU time, it must not block the runtime.
+            tokio::task::spawn_blocking(move || {
+                let env = risc0_zkvm::ExecutorEnv::builder()
+                    .write(&proof_inputs)
+                    .context("Error in ExecutorEnv builder write")?
+                    .build()
+                    .context("Error in ExecutorEnv builder build")?;
+
+                let prove_info = risc0_zkvm::default_prover()
+                    .prove(env, methods::PROVE_BATCH_ELF)
+                    .context("Error in risc0_zkvm prove")?;
+                Ok(prove_info.receipt)
+            })
+            .await
+            .context("The risc0 prover panicked")?
+        })
+    }
+}
+
+/// Runs the batch logic natively instead of in the zkVM and returns a fake receipt of its outputs.
+///
+/// Fake receipts only verify in risc0 dev mode, so this is meant for development and tests,
+/// where the contract is deployed with dev mode enabled.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativeProver;
+
+impl BatchProver for NativeProver {
+    fn prove<'a>(&'a self, proof_inputs: &'a ProofInputs) -> ProveFuture<'a> {
+        let proof_inputs = proof_inputs.clone();
+        Box::pin(async move {
+            let proof_outputs =
+                tokio::task::spawn_blocking(move || proof_inputs.run_batch_proof_logic())
+                    .await
+                    .context("The batch logic panicked")?
+                    .map_err(|err| anyhow::anyhow!("Invalid batch: {}", err))?;
+
+            fake_receipt(&proof_outputs)
+        })
+    }
+}
+
+/// A receipt with the journal the batch circuit commits for `proof_outputs`,
+/// see `kairos_verifier_risc0_lib::verifier::verify_execution`.
+fn fake_receipt(proof_outputs: &ProofOutputs) -> Result<Receipt, anyhow::Error> {
+    let output = proof_outputs
+        .borsh_serialize()
+        .map_err(anyhow::Error::msg)?;
+
+    // The circuit writes the length as a word, followed by the output padded to whole words.
+    let mut journal = u32::try_from(output.len())?.to_le_bytes().to_vec();
+    journal.extend_from_slice(&output);
+    journal.resize(journal.len().next_multiple_of(4), 0);
+
+    let claim = ReceiptClaim::ok(BATCH_CIRCUIT_PROGRAM_HASH, journal.clone());
+    Ok(Receipt::new(
+        InnerReceipt::Fake(FakeReceipt::new(claim)),
+        journal,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kairos_circuit_logic::transactions::L1Deposit;
+
+    #[test]
+    fn test_fake_receipt_journal_holds_the_outputs() {
+        let proof_outputs = ProofOutputs {
+            pre_batch_trie_root: None,
+            post_batch_trie_root: Some([1; 32]),
+            deposits: vec![L1Deposit {
+                recipient: vec![1, 2, 3],
+                amount: 100,
+            }]
+            .into(),
+            withdrawals: Vec::new().into(),
+        };
+
+        let receipt = fake_receipt(&proof_outputs).unwrap();
+
+        let journal = &receipt.journal.bytes;
+        assert_eq!(journal.len() % 4, 0);
+        let len = u32::from_le_bytes(journal[..4].try_into().unwrap()) as usize;
+        assert_eq!(
+            ProofOutputs::borsh_deserialize(&journal[4..4 + len]).unwrap(),
+            proof_outputs
+        );
+    }
+}
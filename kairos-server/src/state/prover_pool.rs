@@ -0,0 +1,481 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use reqwest::Url;
+use tokio::{sync::Notify, task::JoinSet};
+
+use super::prover::{BatchProver, HttpProver, ProveFuture};
+use crate::config::ProvingServer;
+use kairos_circuit_logic::ProofInputs;
+
+/// How often the proving servers are probed, see `ProverPool::run_health_checks`.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long a proving server may take to respond to a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The proving servers batches are dispatched to.
+///
+/// A server proves one batch at a time. Batches go to an idle, healthy server,
+/// the one with the fewest batches dispatched relative to its weight.
+/// A server is unhealthy once it's unreachable or times out, until a probe succeeds again.
+/// A server that times out stays reserved until it responds, since it's still proving.
+/// The proof is then retried right away on another healthy server, as it is after a server error.
+/// A server that refuses the batch stays healthy, the batch is failed instead.
+#[derive(Debug)]
+pub struct ProverPool {
+    servers: Vec<ProvingServer>,
+    provers: Vec<HttpProver>,
+    /// A proof that takes longer is abandoned.
+    timeout: Duration,
+    states: Mutex<Vec<ProverState>>,
+    /// Notified when a server becomes idle or healthy.
+    changed: Notify,
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Clone)]
+struct ProverState {
+    busy: bool,
+    healthy: bool,
+    dispatched: u64,
+    last_error: Option<String>,
+}
+
+impl Default for ProverState {
+    fn default() -> Self {
+        Self {
+            busy: false,
+            // Servers are assumed healthy until they fail, so the first batch is not held up.
+            healthy: true,
+            dispatched: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// The state of a proving server, reported by the readiness endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProverStatus {
+    pub url: Url,
+    pub healthy: bool,
+    pub busy: bool,
+    pub last_error: Option<String>,
+}
+
+impl ProverPool {
+    /// Panics if `servers` is empty, `new_prover` rejects that.
+    pub fn new(servers: Vec<ProvingServer>, timeout: Duration) -> Self {
+        assert!(!servers.is_empty(), "No proving server configured");
+        let states = vec![ProverState::default(); servers.len()];
+        let provers = servers
+            .iter()
+            .map(|server| HttpProver::new(server.url.clone()))
+            .collect();
+
+        Self {
+            servers,
+            provers,
+            timeout,
+            states: Mutex::new(states),
+            changed: Notify::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The number of proving servers, i.e. the most batches proven at once.
+    pub fn server_count(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Waits for an idle, healthy server and reserves it until the lease is dropped.
+    ///
+    /// `avoid` is a server that just failed, it's only picked if no other server is healthy.
+    pub async fn acquire(self: &Arc<Self>, avoid: Option<usize>) -> ProverLease {
+        loop {
+            // Register for notifications before checking, so no change is missed in between.
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if let Some(index) = self.try_acquire(avoid) {
+                return ProverLease {
+                    pool: self.clone(),
+                    index,
+                };
+            }
+            changed.await;
+        }
+    }
+
+    fn try_acquire(&self, avoid: Option<usize>) -> Option<usize> {
+        let mut states = self.lock();
+        let others_healthy = states
+            .iter()
+            .enumerate()
+            .any(|(index, state)| state.healthy && Some(index) != avoid);
+
+        let index = states
+            .iter()
+            .enumerate()
+            .filter(|(index, state)| {
+                state.healthy && !state.busy && !(others_healthy && Some(*index) == avoid)
+            })
+            .min_by(|(a, a_state), (b, b_state)| {
+                // Compares dispatched / weight without dividing.
+                let a_load = u128::from(a_state.dispatched) * u128::from(self.servers[*b].weight);
+                let b_load = u128::from(b_state.dispatched) * u128::from(self.servers[*a].weight);
+                a_load.cmp(&b_load)
+            })
+            .map(|(index, _)| index)?;
+
+        let state = &mut states[index];
+        state.busy = true;
+        state.dispatched += 1;
+        Some(index)
+    }
+
+    /// Proves on the leased server, the server is marked unhealthy if it's unreachable or times out.
+    /// The lease is released once the server responds, after a timeout too.
+    async fn prove_on(
+        &self,
+        lease: ProverLease,
+        proof_inputs: &ProofInputs,
+    ) -> Result<risc0_zkvm::Receipt, ProveFailure> {
+        let index = lease.index;
+        let prover = self.provers[index].clone();
+        let mut request = tokio::spawn({
+            let prover = prover.clone();
+            let proof_inputs = proof_inputs.clone();
+            async move {
+                let res = prover.prove_batch(&proof_inputs).await;
+                drop(lease);
+                res
+            }
+        });
+
+        let err = match tokio::time::timeout(self.timeout, &mut request).await {
+            Ok(Ok(Ok(receipt))) => return Ok(receipt),
+            Ok(Ok(Err(err))) if err.is_batch_rejected() => {
+                return Err(ProveFailure::Batch(err.into()))
+            }
+            Ok(Ok(Err(err))) if !err.is_unreachable() => {
+                return Err(ProveFailure::Failed(err.into()))
+            }
+            Ok(Ok(Err(err))) => err.into(),
+            Ok(Err(err)) => return Err(ProveFailure::Failed(err.into())),
+            Err(_) => anyhow!(
+                "Proving server {} timed out after {:?}",
+                prover.url(),
+                self.timeout
+            ),
+        };
+
+        self.set_health(index, Some(format!("{err:#}")));
+        Err(ProveFailure::Server(err))
+    }
+
+    /// Whether a healthy server other than `index` exists, so a failed batch can move on right away.
+    fn has_other_healthy(&self, index: usize) -> bool {
+        self.lock()
+            .iter()
+            .enumerate()
+            .any(|(other, state)| other != index && state.healthy)
+    }
+
+    /// Probes every server, updates their health and returns their status.
+    /// Any HTTP response counts, the proving server has no dedicated health endpoint.
+    pub async fn check_health(&self) -> Vec<ProverStatus> {
+        let mut probes = JoinSet::new();
+        for (index, server) in self.servers.iter().enumerate() {
+            let request = self.client.get(server.url.clone()).timeout(PROBE_TIMEOUT);
+            probes.spawn(async move { (index, request.send().await) });
+        }
+
+        while let Some(probe) = probes.join_next().await {
+            let Ok((index, response)) = probe else {
+                continue;
+            };
+            match response {
+                Ok(_) => self.set_health(index, None),
+                Err(err) => self.set_health(index, Some(format!("unreachable: {}", err))),
+            }
+        }
+
+        self.statuses()
+    }
+
+    /// Probes the servers every `HEALTH_CHECK_INTERVAL`, so failed servers are used again once they recover.
+    pub async fn run_health_checks(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(HEALTH_CHECK_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            for status in self.check_health().await {
+                if let Some(error) = status.last_error.filter(|_| !status.healthy) {
+                    tracing::warn!("Proving server {} is unhealthy: {}", status.url, error);
+                }
+            }
+        }
+    }
+
+    pub fn statuses(&self) -> Vec<ProverStatus> {
+        self.servers
+            .iter()
+            .zip(self.lock().iter())
+            .map(|(server, state)| ProverStatus {
+                url: server.url.clone(),
+                healthy: state.healthy,
+                busy: state.busy,
+                last_error: state.last_error.clone(),
+            })
+            .collect()
+    }
+
+    /// `error` is `None` if the server is healthy.
+    fn set_health(&self, index: usize, error: Option<String>) {
+        let healthy = error.is_none();
+        {
+            let mut states = self.lock();
+            let state = &mut states[index];
+            state.healthy = healthy;
+            if error.is_some() {
+                state.last_error = error;
+            }
+        }
+        if healthy {
+            self.changed.notify_waiters();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<ProverState>> {
+        self.states.lock().expect("poisoned lock")
+    }
+}
+
+/// Why proving on a server failed, see `ProverPool::prove_on`.
+enum ProveFailure {
+    /// The server is unreachable or timed out, another server may prove the batch.
+    Server(anyhow::Error),
+    /// The server failed to prove the batch, e.g. it's overloaded, another server may prove it.
+    Failed(anyhow::Error),
+    /// The server refused the batch.
+    Batch(anyhow::Error),
+}
+
+impl BatchProver for Arc<ProverPool> {
+    /// Proves on an idle server, moving on to another healthy server after each server failure.
+    /// Returns the last error once no other server is healthy,
+    /// or right away if a server refuses the batch.
+    fn prove<'a>(&'a self, proof_inputs: &'a ProofInputs) -> ProveFuture<'a> {
+        Box::pin(async move {
+            let mut failed = None;
+            loop {
+                let lease = self.acquire(failed).await;
+                let index = lease.index;
+                tracing::info!("Proving batch on {}", lease.url());
+
+                let err = match self.prove_on(lease, proof_inputs).await {
+                    Ok(receipt) => return Ok(receipt),
+                    // Other servers would refuse it too, the caller retries later.
+                    Err(ProveFailure::Batch(err)) => return Err(err),
+                    Err(ProveFailure::Server(err) | ProveFailure::Failed(err)) => err,
+                };
+                if !self.has_other_healthy(index) {
+                    return Err(err);
+                }
+
+                tracing::error!("Proving failed, retrying on another server: {:#}", err);
+                failed = Some(index);
+            }
+        })
+    }
+}
+
+/// A proving server reserved for one batch, it's idle again once the lease is dropped.
+#[derive(Debug)]
+pub struct ProverLease {
+    pool: Arc<ProverPool>,
+    index: usize,
+}
+
+impl ProverLease {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.pool.servers[self.index].url
+    }
+}
+
+impl Drop for ProverLease {
+    fn drop(&mut self) {
+        self.pool.lock()[self.index].busy = false;
+        self.pool.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use kairos_circuit_logic::account_trie::{Account, AccountTrie};
+    use kairos_trie::{stored::memory_db::MemoryDb, TrieRoot};
+
+    use super::*;
+
+    fn pool(weights: &[u32]) -> Arc<ProverPool> {
+        let servers = weights
+            .iter()
+            .enumerate()
+            .map(|(index, &weight)| ProvingServer {
+                url: Url::parse(&format!("http://prover-{index}:7894")).unwrap(),
+                weight,
+            })
+            .collect();
+        Arc::new(ProverPool::new(servers, Duration::from_secs(1)))
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_spread_by_weight() {
+        let pool = pool(&[1, 3]);
+
+        let mut dispatched = [0; 2];
+        for _ in 0..8 {
+            let lease = pool.acquire(None).await;
+            dispatched[lease.index()] += 1;
+        }
+
+        assert_eq!(dispatched, [2, 6]);
+    }
+
+    #[tokio::test]
+    async fn test_busy_and_failed_servers_are_skipped() {
+        let pool = pool(&[1, 1]);
+
+        let first = pool.acquire(None).await;
+        let second = pool.acquire(None).await;
+        assert_ne!(first.index(), second.index());
+
+        // Every server is busy until a lease is dropped.
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.acquire(None).await.index() }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        let freed = first.index();
+        drop(first);
+        assert_eq!(waiting.await.unwrap(), freed);
+
+        // A failed server is avoided while another one is healthy.
+        let failed = second.index();
+        drop(second);
+        pool.set_health(failed, Some("proving failed".to_string()));
+        assert_ne!(pool.acquire(Some(failed)).await.index(), failed);
+        assert!(!pool.statuses()[failed].healthy);
+    }
+
+    fn empty_proof_inputs() -> ProofInputs {
+        let empty_trie = AccountTrie::new_try_from_db(
+            Rc::new(MemoryDb::<Account>::empty()),
+            TrieRoot::default(),
+        );
+        ProofInputs {
+            transactions: Vec::new().into(),
+            trie_snapshot: empty_trie.txn.build_initial_snapshot(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proof_fails_once_no_server_is_healthy() {
+        // Nothing listens on these ports, so every proof fails.
+        let servers = ["http://127.0.0.1:1", "http://127.0.0.1:2"]
+            .map(|url| ProvingServer::new(Url::parse(url).unwrap()))
+            .to_vec();
+        let pool = Arc::new(ProverPool::new(servers, Duration::from_secs(1)));
+
+        assert!(pool.prove(&empty_proof_inputs()).await.is_err());
+        assert!(pool.statuses().iter().all(|status| !status.healthy));
+    }
+
+    /// A proving server that responds with `status` and `body` after `delay`, counting the requests.
+    async fn proving_server(
+        delay: Duration,
+        status: axum::http::StatusCode,
+        body: &'static str,
+    ) -> (Url, Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{routing::post, Router};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let requests = Arc::new(AtomicUsize::new(0));
+        let app = Router::new().route(
+            "/api/v1/prove/batch",
+            post({
+                let requests = requests.clone();
+                move || async move {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(delay).await;
+                    (status, body)
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_fail_over_and_refused_batches_fail() {
+        use axum::http::StatusCode;
+        use std::sync::atomic::Ordering;
+
+        let (overloaded, overloaded_requests) = proving_server(
+            Duration::ZERO,
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded",
+        )
+        .await;
+        let (refusing, refusing_requests) =
+            proving_server(Duration::ZERO, StatusCode::BAD_REQUEST, "invalid batch").await;
+        let servers = vec![ProvingServer::new(overloaded), ProvingServer::new(refusing)];
+        let pool = Arc::new(ProverPool::new(servers, Duration::from_secs(1)));
+
+        // The batch moves on from the overloaded server, the other one refuses it for good.
+        let err = pool.prove(&empty_proof_inputs()).await.unwrap_err();
+        assert!(err.to_string().contains("invalid batch"));
+        assert_eq!(overloaded_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(refusing_requests.load(Ordering::SeqCst), 1);
+        // Neither server is marked unhealthy, they responded.
+        assert!(pool.statuses().iter().all(|status| status.healthy));
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_server_stays_reserved_until_it_responds() {
+        use axum::http::StatusCode;
+
+        let (slow, _) = proving_server(
+            Duration::from_millis(500),
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "too slow",
+        )
+        .await;
+        let pool = Arc::new(ProverPool::new(
+            vec![ProvingServer::new(slow)],
+            Duration::from_millis(100),
+        ));
+
+        assert!(pool.prove(&empty_proof_inputs()).await.is_err());
+        let status = &pool.statuses()[0];
+        assert!(!status.healthy);
+        // It's still proving, so it's not handed another batch once a probe succeeds.
+        assert!(status.busy);
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!pool.statuses()[0].busy);
+    }
+}
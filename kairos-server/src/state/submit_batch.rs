@@ -1,103 +1,413 @@
-use std::time::Instant;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use anyhow::anyhow;
-use backoff::{future::retry, ExponentialBackoff};
+use backoff::{backoff::Backoff, ExponentialBackoff};
 use casper_client::{
-    types::{DeployBuilder, ExecutableDeployItem, TimeDiff, Timestamp},
-    Error, JsonRpcId,
+    types::{DeployBuilder, DeployHash, ExecutableDeployItem, TimeDiff, Timestamp},
+    JsonRpcId, Verbosity,
 };
 use casper_client_types::{
-    bytesrepr::Bytes, runtime_args, ContractHash, ExecutionResult, RuntimeArgs, SecretKey,
+    bytesrepr::Bytes, runtime_args, ContractHash, ExecutionResult, RuntimeArgs, SecretKey, U512,
 };
 use rand::random;
 use reqwest::Url;
 use risc0_zkvm::Receipt;
+use thiserror::Error;
+use tokio::{sync::Notify, time::Instant};
 
+use super::deposit_status::DeployHashBytes;
+use crate::config::BatchConfig;
 use crate::routes::get_chain_name::get_chain_name_from_rpc;
 
-pub const MAX_GAS_FEE_PAYMENT_AMOUNT: u64 = 10_000_000_000_000;
-// TODO: retry request on failure, improve error handling
-pub async fn submit_proof_to_contract(
-    signer: &SecretKey,
-    contract_hash: ContractHash,
+/// How long after its TTL a deploy may still show up as executed,
+/// e.g. if it was included in one of the last blocks before it expired.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+/// The longest we wait between polls of a deploy, notifications usually arrive sooner.
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Submits proofs to the contract and tracks their deploys until they are executed.
+///
+/// Executions are polled over RPC, a notification from the `DeployWatcher` polls right away.
+pub struct ProofSubmitter {
     casper_rpc: Url,
-    receipt: &Receipt,
-) {
-    let proof_serialized = Bytes::from(serde_json::to_vec(receipt).expect("could not serialize"));
-
-    tracing::info!("Submitting proof to contract: {:?}", contract_hash);
-    let submit_batch = ExecutableDeployItem::StoredContractByHash {
-        hash: contract_hash,
-        entry_point: "submit_batch".into(),
-        args: runtime_args! {
-            "risc0_receipt" => proof_serialized,
-        },
-    };
-
-    let chain_name = get_chain_name_from_rpc(&casper_rpc)
-        .await
-        .expect("RPC request failed");
-    let deploy = DeployBuilder::new(chain_name, submit_batch, signer)
-        .with_standard_payment(MAX_GAS_FEE_PAYMENT_AMOUNT)
-        .with_timestamp(Timestamp::now())
-        .with_ttl(TimeDiff::from_millis(60_000))
-        .build()
-        .expect("could not build deploy");
-
-    let deploy_hash = *deploy.id();
-
-    let r = casper_client::put_deploy(
-        casper_client::JsonRpcId::Number(random()),
-        casper_rpc.as_str(),
-        casper_client::Verbosity::Low,
-        deploy,
-    )
-    .await
-    .expect("could not put deploy");
-
-    let start = Instant::now();
-    let timed_out = start.elapsed().as_secs() > 60;
-
-    retry(ExponentialBackoff::default(), || async {
+    contract_hash: ContractHash,
+    signer: SecretKey,
+    /// In motes, see `BatchConfig::submission_payment`.
+    payment: u64,
+    ttl: Duration,
+    deploy_watcher: DeployWatcher,
+}
+
+/// A deploy put to the node, it can't be executed after `expires_at`.
+#[derive(Debug, Clone, Copy)]
+pub struct SubmittedDeploy {
+    pub hash: DeployHash,
+    expires_at: Instant,
+}
+
+impl ProofSubmitter {
+    pub fn new(
+        casper_rpc: Url,
+        contract_hash: ContractHash,
+        signer: SecretKey,
+        batch_config: &BatchConfig,
+        deploy_watcher: DeployWatcher,
+    ) -> Self {
+        Self {
+            casper_rpc,
+            contract_hash,
+            signer,
+            payment: batch_config.submission_payment,
+            ttl: batch_config.submission_ttl,
+            deploy_watcher,
+        }
+    }
+
+    /// The configured payment of a proof deploy in motes, see `BatchConfig::submission_payment`.
+    pub fn payment(&self) -> u64 {
+        self.payment
+    }
+
+    /// Puts a deploy submitting `receipt` to the contract, paying `payment` motes for its execution.
+    /// Use `wait_for_execution` to find out whether the contract accepted the proof.
+    pub async fn put(
+        &self,
+        receipt: &Receipt,
+        payment: u64,
+    ) -> Result<SubmittedDeploy, SubmitError> {
+        let proof_serialized = Bytes::from(serde_json::to_vec(receipt)?);
+
+        tracing::info!("Submitting proof to contract: {:?}", self.contract_hash);
+        let submit_batch = ExecutableDeployItem::StoredContractByHash {
+            hash: self.contract_hash,
+            entry_point: "submit_batch".into(),
+            args: runtime_args! {
+                "risc0_receipt" => proof_serialized,
+            },
+        };
+
+        let chain_name = get_chain_name_from_rpc(&self.casper_rpc).await?;
+        let ttl = u64::try_from(self.ttl.as_millis()).unwrap_or(u64::MAX);
+        let deploy = DeployBuilder::new(chain_name, submit_batch, &self.signer)
+            .with_standard_payment(payment)
+            .with_timestamp(Timestamp::now())
+            .with_ttl(TimeDiff::from_millis(ttl))
+            .build()
+            .map_err(|err| SubmitError::InvalidDeploy(err.to_string()))?;
+
+        let hash = *deploy.id();
+        let expires_at = Instant::now() + self.ttl + EXPIRY_MARGIN;
+
+        casper_client::put_deploy(
+            JsonRpcId::Number(random()),
+            self.casper_rpc.as_str(),
+            Verbosity::Low,
+            deploy,
+        )
+        .await?;
+
+        Ok(SubmittedDeploy { hash, expires_at })
+    }
+
+    /// Waits until `deploy` is executed and returns the gas it cost.
+    ///
+    /// Errors with `SubmitError::Expired` if the deploy was not executed before it expired,
+    /// it can then be submitted again.
+    /// Failed queries are retried until the deploy expires.
+    pub async fn wait_for_execution(&self, deploy: &SubmittedDeploy) -> Result<U512, SubmitError> {
+        let deploy_hash = hex::encode(deploy.hash.inner());
+        let watch = self.deploy_watcher.watch(deploy.hash.inner().value());
+        let mut poll = ExponentialBackoff {
+            max_interval: MAX_POLL_INTERVAL,
+            max_elapsed_time: None,
+            ..Default::default()
+        };
+
+        loop {
+            // Checked before querying, so a deploy executed right before it expired is found.
+            let expired = Instant::now() >= deploy.expires_at;
+            match self.execution_result(deploy.hash).await {
+                Ok(Some(Ok(cost))) => {
+                    tracing::info!("Deploy successful: {}", deploy_hash);
+                    return Ok(cost);
+                }
+                Ok(Some(Err(failure))) => {
+                    return Err(SubmitError::Failed {
+                        deploy_hash,
+                        failure,
+                    })
+                }
+                Ok(None) if expired => return Err(SubmitError::Expired { deploy_hash }),
+                Err(err) if expired => return Err(err.into()),
+                Ok(None) => {}
+                Err(err) => tracing::warn!("Could not query deploy {}: {}", deploy_hash, err),
+            }
+
+            let next_poll = Instant::now() + poll.next_backoff().unwrap_or(MAX_POLL_INTERVAL);
+            tokio::select! {
+                _ = watch.processed() => tracing::debug!("Deploy {} was processed", deploy_hash),
+                _ = tokio::time::sleep_until(next_poll.min(deploy.expires_at)) => {}
+            }
+        }
+    }
+
+    /// `None` if the deploy was not executed yet.
+    async fn execution_result(
+        &self,
+        deploy_hash: DeployHash,
+    ) -> Result<Option<Result<U512, ExecutionFailure>>, casper_client::Error> {
         let response = casper_client::get_deploy(
-            JsonRpcId::Number(1),
-            casper_rpc.as_str(),
-            casper_client::Verbosity::Low,
+            JsonRpcId::Number(random()),
+            self.casper_rpc.as_str(),
+            Verbosity::Low,
             deploy_hash,
             false,
         )
-        .await
-        .map_err(|err| {
-            let elapsed = start.elapsed().as_secs();
-            tracing::info!("Running for {elapsed}s, Error: {err:?}");
-            err
-        })
-        .map_err(|err| match &err {
-            e if timed_out => backoff::Error::permanent(anyhow!("Timeout on error: {e:?}")),
-            Error::ResponseIsHttpError { .. } | Error::FailedToGetResponse { .. } => {
-                backoff::Error::transient(anyhow!(err))
-            }
-            _ => backoff::Error::permanent(anyhow!(err)),
-        })
-        .expect("could not get deploy");
+        .await?;
 
-        match response.result.execution_results.first() {
-            Some(result) => match &result.result {
+        Ok(response
+            .result
+            .execution_results
+            .first()
+            .map(|result| match &result.result {
                 ExecutionResult::Failure { error_message, .. } => {
-                    Err(backoff::Error::permanent(anyhow!(error_message.clone())))
+                    Err(ExecutionFailure::from_error_message(error_message))
                 }
-                ExecutionResult::Success { .. } => Ok(()),
-            },
-            None if timed_out => Err(backoff::Error::permanent(anyhow!(
-                "Timeout on error: No execution results"
-            ))),
-            None => Err(backoff::Error::transient(anyhow!(
-                "No execution results there yet"
-            ))),
+                ExecutionResult::Success { cost, .. } => Ok(*cost),
+            }))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SubmitError {
+    #[error("could not serialize the receipt: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("could not build the deploy: {0}")]
+    InvalidDeploy(String),
+
+    /// Casper RPC error.
+    #[error("casper client error: {0}")]
+    CasperClient(#[from] casper_client::Error),
+
+    /// The deploy was not executed before its TTL ran out, it never will be.
+    #[error("deploy {deploy_hash} expired before it was executed")]
+    Expired { deploy_hash: String },
+
+    #[error("deploy {deploy_hash} failed: {failure}")]
+    Failed {
+        deploy_hash: String,
+        failure: ExecutionFailure,
+    },
+}
+
+/// Why the execution of a proof deploy failed.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ExecutionFailure {
+    #[error("the contract rejected the proof, {0}")]
+    Reverted(ContractRevert),
+
+    #[error("out of gas, batch.submission_payment may be too low")]
+    OutOfGas,
+
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ExecutionFailure {
+    /// Decodes the error message of a failed deploy, e.g. `User error: 5`.
+    pub fn from_error_message(error_message: &str) -> Self {
+        let user_error = ["User error: ", "ApiError::User("]
+            .into_iter()
+            .find_map(|prefix| {
+                let code = &error_message[error_message.find(prefix)? + prefix.len()..];
+                let end = code
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(code.len());
+                code[..end].parse::<u16>().ok()
+            });
+
+        match user_error {
+            Some(code) => Self::Reverted(code.into()),
+            None if error_message.contains("Out of gas") => Self::OutOfGas,
+            None => Self::Other(error_message.to_string()),
+        }
+    }
+}
+
+/// The `ApiError::User` codes the `submit_batch` entry point of the demo contract reverts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ContractRevert {
+    #[error("the receipt could not be deserialized (code 0)")]
+    MalformedReceipt,
+
+    #[error("the proof did not verify (code 1000)")]
+    InvalidProof,
+
+    #[error("the journal could not be decoded (code 1001)")]
+    MalformedJournal,
+
+    #[error("the trie root could not be read (code {0})")]
+    MissingTrieRoot(u16),
+
+    /// The batch does not build on the trie root stored in the contract,
+    /// e.g. because an earlier batch was not accepted or this one already was.
+    #[error("the batch does not build on the contract's trie root (code 5)")]
+    StaleTrieRoot,
+
+    #[error("the unprocessed deposits could not be read (code {0})")]
+    UnprocessedDeposits(u16),
+
+    #[error("the batch deposits don't match the unprocessed deposits (code {0})")]
+    DepositMismatch(u16),
+
+    #[error("a withdrawal could not be paid out (code {0})")]
+    WithdrawalFailed(u16),
+
+    #[error("unknown error code {0}")]
+    Unknown(u16),
+}
+
+impl ContractRevert {
+    /// Whether submitting the same proof again is rejected the same way.
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Self::MalformedReceipt
+                | Self::InvalidProof
+                | Self::MalformedJournal
+                | Self::StaleTrieRoot
+        )
+    }
+}
+
+impl From<u16> for ContractRevert {
+    fn from(code: u16) -> Self {
+        match code {
+            0 => Self::MalformedReceipt,
+            1000 => Self::InvalidProof,
+            1001 => Self::MalformedJournal,
+            2..=4 => Self::MissingTrieRoot(code),
+            5 => Self::StaleTrieRoot,
+            101..=199 => Self::UnprocessedDeposits(code),
+            201..=299 => Self::DepositMismatch(code),
+            301..=399 => Self::WithdrawalFailed(code),
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+/// The proof deploys awaiting execution, the L1 sync reports the deploys the node processed.
+#[derive(Debug, Clone, Default)]
+pub struct DeployWatcher {
+    watched: Arc<Mutex<HashMap<DeployHashBytes, Arc<Notify>>>>,
+}
+
+impl DeployWatcher {
+    /// Starts watching `deploy_hash` until the returned `DeployWatch` is dropped.
+    pub fn watch(&self, deploy_hash: DeployHashBytes) -> DeployWatch {
+        let processed = self.lock().entry(deploy_hash).or_default().clone();
+        DeployWatch {
+            watcher: self.clone(),
+            deploy_hash,
+            processed,
+        }
+    }
+
+    pub fn is_watched(&self, deploy_hash: &DeployHashBytes) -> bool {
+        self.lock().contains_key(deploy_hash)
+    }
+
+    /// Records that the node processed `deploy_hash`, whether it succeeded or not.
+    pub fn processed(&self, deploy_hash: &DeployHashBytes) {
+        if let Some(processed) = self.lock().get(deploy_hash) {
+            processed.notify_one();
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<DeployHashBytes, Arc<Notify>>> {
+        self.watched.lock().expect("poisoned lock")
+    }
+}
+
+/// A watched deploy, see `DeployWatcher::watch`.
+#[derive(Debug)]
+pub struct DeployWatch {
+    watcher: DeployWatcher,
+    deploy_hash: DeployHashBytes,
+    processed: Arc<Notify>,
+}
+
+impl DeployWatch {
+    /// Resolves once the node reports the deploy as processed,
+    /// including if it did so since the last call.
+    pub async fn processed(&self) {
+        self.processed.notified().await
+    }
+}
+
+impl Drop for DeployWatch {
+    fn drop(&mut self) {
+        self.watcher.lock().remove(&self.deploy_hash);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_revert_codes_are_decoded() {
+        for (error_message, expected) in [
+            (
+                "User error: 5",
+                ExecutionFailure::Reverted(ContractRevert::StaleTrieRoot),
+            ),
+            (
+                "ApiError::User(1000) [65535]",
+                ExecutionFailure::Reverted(ContractRevert::InvalidProof),
+            ),
+            (
+                "User error: 203",
+                ExecutionFailure::Reverted(ContractRevert::DepositMismatch(203)),
+            ),
+            (
+                "User error: 42",
+                ExecutionFailure::Reverted(ContractRevert::Unknown(42)),
+            ),
+            ("Out of gas error", ExecutionFailure::OutOfGas),
+            (
+                "Interpreter error: trap",
+                ExecutionFailure::Other("Interpreter error: trap".to_string()),
+            ),
+        ] {
+            assert_eq!(
+                ExecutionFailure::from_error_message(error_message),
+                expected
+            );
         }
-    })
-    .await
-    .expect("could not get deploy or deploy failed");
 
-    tracing::info!("Deploy successful: {:?}", r);
+        assert!(ContractRevert::InvalidProof.is_permanent());
+        assert!(ContractRevert::StaleTrieRoot.is_permanent());
+        assert!(!ContractRevert::UnprocessedDeposits(101).is_permanent());
+        assert!(!ContractRevert::Unknown(42).is_permanent());
+    }
+
+    #[tokio::test]
+    async fn test_processed_deploys_wake_up_their_watch() {
+        let watcher = DeployWatcher::default();
+        let watch = watcher.watch([1; 32]);
+        assert!(watcher.is_watched(&[1; 32]));
+
+        // The notification is kept until the watch waits for it.
+        watcher.processed(&[1; 32]);
+        watcher.processed(&[2; 32]);
+        tokio::time::timeout(Duration::from_secs(1), watch.processed())
+            .await
+            .expect("the watch was not notified");
+
+        drop(watch);
+        assert!(!watcher.is_watched(&[1; 32]));
+    }
 }
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::batch_tracker::BatchId;
+use super::events::ServerEvent;
+use kairos_circuit_logic::transactions::KairosTransaction;
+use kairos_tx::asn::{SigningPayload, Transfer, Withdrawal};
+
+/// The most transactions whose status is kept, the oldest are forgotten first.
+const MAX_TRACKED_TRANSACTIONS: usize = 100_000;
+/// The most committed batches kept to map proof events back to their transactions.
+const MAX_TRACKED_BATCHES: usize = 1_000;
+
+/// The hash of a transaction's `SigningPayload`, see `SigningPayload::hash`.
+///
+/// The payload does not include the signer,
+/// two accounts signing the same payload share a hash and the latest status.
+pub type TransactionHash = [u8; 32];
+
+/// Where a transfer or withdrawal is in the pipeline towards L1.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Waiting in the mempool for transactions with lower nonces.
+    Held,
+    /// Executed against the current batch, waiting for the batch to be committed.
+    Queued,
+    Rejected {
+        reason: String,
+    },
+    Included {
+        batch_id: BatchId,
+    },
+    Proved {
+        batch_id: BatchId,
+    },
+    Submitted {
+        batch_id: BatchId,
+        deploy_hash: String,
+    },
+    /// The contract accepted the proof of the batch, the transaction is final.
+    Executed {
+        batch_id: BatchId,
+        deploy_hash: Option<String>,
+    },
+}
+
+/// The status of a transaction along with its hex encoded hash, returned when it's submitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionReceipt {
+    pub hash: String,
+    #[serde(flatten)]
+    pub status: TransactionStatus,
+}
+
+impl TransactionStatus {
+    /// Statuses of submissions, rather than of committed batches.
+    /// They can be replaced by a later submission of the same transaction.
+    fn is_submission(&self) -> bool {
+        matches!(self, Self::Held | Self::Queued | Self::Rejected { .. })
+    }
+
+    /// A held or rejected transaction can still be accepted by a later submission.
+    fn is_open(&self) -> bool {
+        matches!(self, Self::Held | Self::Rejected { .. })
+    }
+}
+
+/// Returns the hash of the payload the transaction was signed over, `None` for deposits.
+///
+/// The payload is rebuilt from the transaction, DER encoding is canonical
+/// so this is the hash of the payload the client submitted.
+pub fn transaction_hash(txn: &KairosTransaction) -> Option<TransactionHash> {
+    let payload = match txn {
+        KairosTransaction::Transfer(transfer) => SigningPayload::new(
+            transfer.nonce,
+            Transfer::new(
+                transfer.transaction.recipient.clone(),
+                transfer.transaction.amount,
+            ),
+        ),
+        KairosTransaction::Withdraw(withdraw) => {
+            SigningPayload::new(withdraw.nonce, Withdrawal::new(withdraw.transaction.amount))
+        }
+        KairosTransaction::Deposit(_) => return None,
+    };
+
+    payload
+        .hash()
+        .map_err(|err| tracing::error!("Failed to hash transaction {:?}: {}", txn, err))
+        .ok()
+}
+
+/// The status of recently submitted transactions, built from the published `ServerEvent`s.
+///
+/// Statuses are kept in memory, they are lost when the server restarts.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionStatuses {
+    inner: Arc<Mutex<StatusStore>>,
+}
+
+#[derive(Debug, Default)]
+struct StatusStore {
+    statuses: HashMap<TransactionHash, TransactionStatus>,
+    /// Hashes in the order they were first seen, used to forget the oldest statuses.
+    order: VecDeque<TransactionHash>,
+    /// Committed batches that are not executed yet, in the order they were committed.
+    batches: VecDeque<CommittedBatch>,
+}
+
+#[derive(Debug)]
+struct CommittedBatch {
+    id: BatchId,
+    hashes: Vec<TransactionHash>,
+    proved: bool,
+}
+
+impl TransactionStatuses {
+    pub fn get(&self, hash: &TransactionHash) -> Option<TransactionStatus> {
+        self.lock().statuses.get(hash).cloned()
+    }
+
+    /// Updates the statuses, it's called by `EventBus::publish` for every event.
+    pub fn apply(&self, event: &ServerEvent) {
+        let mut store = self.lock();
+        match event {
+            ServerEvent::TransactionHeld { transaction } => {
+                store.update_txn(transaction, TransactionStatus::Held);
+            }
+            ServerEvent::TransactionAccepted { transaction } => {
+                store.update_txn(transaction, TransactionStatus::Queued);
+            }
+            ServerEvent::TransactionRejected {
+                transaction,
+                reason,
+            } => {
+                let status = TransactionStatus::Rejected {
+                    reason: reason.clone(),
+                };
+                store.update_txn(transaction, status);
+            }
+            ServerEvent::DepositCredited { .. } => {}
+            ServerEvent::BatchCommitted {
+                batch_id,
+                transactions,
+                ..
+            } => {
+                let hashes: Vec<_> = transactions.iter().filter_map(transaction_hash).collect();
+                store.update_batch(&hashes, |_| TransactionStatus::Included {
+                    batch_id: *batch_id,
+                });
+
+                store.batches.push_back(CommittedBatch {
+                    id: *batch_id,
+                    hashes,
+                    proved: false,
+                });
+                if store.batches.len() > MAX_TRACKED_BATCHES {
+                    store.batches.pop_front();
+                }
+            }
+            ServerEvent::ProofProduced { batch_id } => {
+                store.update_committed_batch(*batch_id, BatchStage::Proved, |_| {
+                    TransactionStatus::Proved {
+                        batch_id: *batch_id,
+                    }
+                });
+            }
+            ServerEvent::ProofSubmitted {
+                batch_id,
+                deploy_hash,
+            } => {
+                store.update_committed_batch(*batch_id, BatchStage::Submitted, |_| {
+                    TransactionStatus::Submitted {
+                        batch_id: *batch_id,
+                        deploy_hash: deploy_hash.clone(),
+                    }
+                });
+            }
+            ServerEvent::ProofFinalized { batch_id, .. } => {
+                store.update_committed_batch(*batch_id, BatchStage::Finalized, |status| {
+                    let deploy_hash = match status {
+                        Some(TransactionStatus::Submitted { deploy_hash, .. }) => {
+                            Some(deploy_hash.clone())
+                        }
+                        _ => None,
+                    };
+                    TransactionStatus::Executed {
+                        batch_id: *batch_id,
+                        deploy_hash,
+                    }
+                });
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, StatusStore> {
+        self.inner.lock().expect("poisoned lock")
+    }
+}
+
+impl StatusStore {
+    fn update_txn(&mut self, txn: &KairosTransaction, status: TransactionStatus) {
+        if let Some(hash) = transaction_hash(txn) {
+            self.update(hash, status);
+        }
+    }
+
+    /// Sets the status of `hash`.
+    /// A submission never overrides the status of a transaction that was already accepted,
+    /// e.g. a duplicate submission is rejected but the original is still queued.
+    fn update(&mut self, hash: TransactionHash, status: TransactionStatus) {
+        match self.statuses.get_mut(&hash) {
+            Some(current) => {
+                if !status.is_submission() || current.is_open() {
+                    *current = status;
+                }
+            }
+            None => {
+                self.statuses.insert(hash, status);
+                self.order.push_back(hash);
+                if self.order.len() > MAX_TRACKED_TRANSACTIONS {
+                    if let Some(oldest) = self.order.pop_front() {
+                        self.statuses.remove(&oldest);
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_batch(
+        &mut self,
+        hashes: &[TransactionHash],
+        status: impl Fn(Option<&TransactionStatus>) -> TransactionStatus,
+    ) {
+        for hash in hashes {
+            let status = status(self.statuses.get(hash));
+            self.update(*hash, status);
+        }
+    }
+
+    /// Updates the transactions of the oldest committed batch with `batch_id` that can reach `stage`.
+    /// Batches are proven in order, so untracked batches, whose id is `None`, are matched in order.
+    fn update_committed_batch(
+        &mut self,
+        batch_id: BatchId,
+        stage: BatchStage,
+        status: impl Fn(Option<&TransactionStatus>) -> TransactionStatus,
+    ) {
+        let proved = stage != BatchStage::Proved;
+        let Some(index) = self
+            .batches
+            .iter()
+            .position(|batch| batch.id == batch_id && batch.proved == proved)
+        else {
+            return;
+        };
+
+        let hashes = match stage {
+            BatchStage::Finalized => self.batches.remove(index).map(|batch| batch.hashes),
+            BatchStage::Proved | BatchStage::Submitted => {
+                let batch = &mut self.batches[index];
+                batch.proved = true;
+                Some(batch.hashes.clone())
+            }
+        };
+        self.update_batch(&hashes.unwrap_or_default(), status);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchStage {
+    Proved,
+    Submitted,
+    Finalized,
+}
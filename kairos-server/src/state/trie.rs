@@ -1,34 +1,210 @@
 use std::{
-    mem,
+    ops::RangeInclusive,
     rc::Rc,
     thread::{self, JoinHandle},
     time::Instant,
 };
 
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use tokio::sync::{mpsc, oneshot};
 
+use super::batch_cost::{input_bytes, BatchCost};
+use super::batch_tracker::BatchId;
+use super::events::{EventBus, ServerEvent};
+use super::mempool::{HeldTransaction, Mempool};
 use super::transactions::batch_state::BatchState;
 use crate::{config::BatchConfig, AppErr};
 use kairos_circuit_logic::{
     account_trie::{Account, AccountTrie},
-    transactions::KairosTransaction,
+    transactions::{KairosTransaction, L1Deposit},
     ProofInputs,
 };
 use kairos_trie::{
-    stored::{memory_db::MemoryDb, merkle::SnapshotBuilder},
-    DigestHasher, NodeHash, TrieRoot,
+    stored::{
+        memory_db::MemoryDb,
+        merkle::{Snapshot, SnapshotBuilder},
+        DatabaseGet, DatabaseSet,
+    },
+    Branch, DigestHasher, Leaf, Node, NodeHash, TrieRoot,
 };
 
 use kairos_circuit_logic::transactions::PublicKey;
 
-pub type Database = MemoryDb<Account>;
+#[cfg(feature = "database")]
+use kairos_data::{batch::NewBatch, trie::TrieDb};
+
+/// The node store backing the account trie.
+///
+/// `Memory` is lost when the server stops, it is useful for testing.
+/// `Postgres` persists every committed node along with the committed root,
+/// which allows the trie to be reopened after a restart.
+pub enum Database {
+    Memory(MemoryDb<Account>),
+    #[cfg(feature = "database")]
+    Postgres(TrieDb<Account>),
+}
+
+impl Database {
+    /// Open the persistent trie store at `db_addr`.
+    /// Returns the store along with the last committed trie root
+    /// and the index of the next contract event it has not credited, if known.
+    #[cfg(feature = "database")]
+    pub fn open(db_addr: &str) -> Result<(Self, TrieRoot<NodeHash>, Option<u32>), AppErr> {
+        let db = TrieDb::connect(db_addr)?;
+        let root = db.last_committed_root()?;
+        let next_event_index = db.next_event_index()?;
+
+        Ok((Self::Postgres(db), root.into(), next_event_index))
+    }
+
+    /// Record the root of `batch_output` as the last committed trie root, along with the batch itself
+    /// and the index of the next contract event the trie has not credited.
+    /// The batch is then durably queued for proving, returns its sequence number.
+    /// `seqs` are the sequence numbers of the recorded transactions in the batch.
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub fn record_committed_batch(
+        &self,
+        batch_output: &BatchOutput,
+        next_event_index: Option<u32>,
+        seqs: Option<RangeInclusive<i64>>,
+    ) -> Result<BatchId, AppErr> {
+        match self {
+            Self::Memory(_) => Ok(None),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => {
+                let new_batch = NewBatch::new(
+                    batch_output.old_root.into(),
+                    batch_output.new_root.into(),
+                    &batch_output.proof_inputs,
+                )?;
+                let id = db.insert_committed_batch(
+                    batch_output.new_root.into(),
+                    next_event_index,
+                    new_batch,
+                    seqs,
+                )?;
+
+                Ok(Some(id))
+            }
+        }
+    }
+
+    /// Record `txn` as executed after every transaction recorded before it,
+    /// `event_index` is the contract event of a deposit.
+    /// Committed batches are rebuilt from the recorded order, see `TrieState::recover`.
+    /// Returns its sequence number, `None` if it's not recorded.
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub fn record_executed_transaction(
+        &self,
+        txn: &KairosTransaction,
+        event_index: Option<u32>,
+    ) -> Result<Option<i64>, AppErr> {
+        match self {
+            Self::Memory(_) => Ok(None),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => db
+                .insert_executed_transaction(txn.clone(), event_index)
+                .map_err(Into::into),
+        }
+    }
+
+    /// The transactions executed after the last committed batch, in the order they were executed.
+    pub fn unbatched_transactions(&self) -> Result<Vec<ReplayedTransaction>, AppErr> {
+        match self {
+            Self::Memory(_) => Ok(Vec::new()),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => db
+                .unbatched_transactions()?
+                .into_iter()
+                .map(|row| {
+                    Ok::<_, AppErr>(ReplayedTransaction {
+                        event_index: row.event_index()?,
+                        seq: row.seq,
+                        txn: KairosTransaction::try_from(row)?,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Record `root` as the last committed trie root, so it can be reopened after a restart.
+    #[cfg_attr(not(feature = "database"), allow(unused_variables))]
+    pub fn record_committed_root(
+        &self,
+        root: TrieRoot<NodeHash>,
+        next_event_index: Option<u32>,
+    ) -> Result<(), AppErr> {
+        match self {
+            Self::Memory(_) => Ok(()),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => db
+                .insert_committed_root(root.into(), next_event_index)
+                .map_err(Into::into),
+        }
+    }
+}
+
+impl DatabaseGet<Account> for Database {
+    type GetError = String;
+
+    fn get(&self, hash: &NodeHash) -> Result<Node<Branch<NodeHash>, Leaf<Account>>, String> {
+        match self {
+            Self::Memory(db) => db.get(hash).map_err(|err| err.to_string()),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => db.get(hash).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+impl DatabaseSet<Account> for Database {
+    type SetError = String;
+
+    fn set(
+        &self,
+        hash: NodeHash,
+        node: Node<Branch<NodeHash>, Leaf<Account>>,
+    ) -> Result<(), Self::GetError> {
+        match self {
+            Self::Memory(db) => db.set(hash, node).map_err(|err| err.to_string()),
+            #[cfg(feature = "database")]
+            Self::Postgres(db) => db.set(hash, node).map_err(|err| err.to_string()),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum TrieStateThreadMsg {
     Transaction(KairosTransaction, oneshot::Sender<Result<(), AppErr>>),
+    /// A deposit emitted by the contract event with the given index.
+    /// Responds with `false` if that event was already credited, see `TrieState::next_event_index`.
+    Deposit(u32, L1Deposit, oneshot::Sender<Result<bool, AppErr>>),
+    /// Responds with `TrieState::next_event_index`.
+    NextEventIndex(oneshot::Sender<Option<u32>>),
     Commit(oneshot::Sender<Result<BatchOutput, AppErr>>),
     GetNonce(PublicKey, oneshot::Sender<Result<u64, AppErr>>),
+    GetAccount(
+        PublicKey,
+        Option<TrieRoot<NodeHash>>,
+        oneshot::Sender<Result<AccountState, AppErr>>,
+    ),
+    /// Sent periodically, see `BatchConfig::tick_interval`.
+    /// Commits the batch once `max_batch_duration` has elapsed, even without new transactions,
+    /// and rejects held transactions that were not released in time, see `Mempool::expire`.
+    Tick,
+    /// While intake is paused, transactions are rejected.
+    SetIntakePaused(bool, oneshot::Sender<()>),
+    /// Rejects every held transaction and responds with their number.
+    /// Messages sent before this one are processed before it's answered.
+    Drain(oneshot::Sender<usize>),
+    Stats(oneshot::Sender<TrieThreadStats>),
+    Recover(
+        Vec<StoredBatch>,
+        Vec<ReplayedTransaction>,
+        TrieRoot<NodeHash>,
+        oneshot::Sender<Result<RecoveryReport, AppErr>>,
+    ),
 }
 
 impl TrieStateThreadMsg {
@@ -37,6 +213,19 @@ impl TrieStateThreadMsg {
         (Self::Transaction(txn, sender), receiver)
     }
 
+    pub fn deposit(
+        event_index: u32,
+        deposit: L1Deposit,
+    ) -> (Self, oneshot::Receiver<Result<bool, AppErr>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::Deposit(event_index, deposit, sender), receiver)
+    }
+
+    pub fn next_event_index() -> (Self, oneshot::Receiver<Option<u32>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::NextEventIndex(sender), receiver)
+    }
+
     pub fn commit() -> (Self, oneshot::Receiver<Result<BatchOutput, AppErr>>) {
         let (sender, receiver) = oneshot::channel();
         (Self::Commit(sender), receiver)
@@ -46,6 +235,39 @@ impl TrieStateThreadMsg {
         let (sender, receiver) = oneshot::channel();
         (Self::GetNonce(account, sender), receiver)
     }
+
+    /// `l1_root` is the trie root accepted on L1, if known.
+    pub fn get_account(
+        account: PublicKey,
+        l1_root: Option<TrieRoot<NodeHash>>,
+    ) -> (Self, oneshot::Receiver<Result<AccountState, AppErr>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::GetAccount(account, l1_root, sender), receiver)
+    }
+
+    pub fn set_intake_paused(paused: bool) -> (Self, oneshot::Receiver<()>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::SetIntakePaused(paused, sender), receiver)
+    }
+
+    pub fn drain() -> (Self, oneshot::Receiver<usize>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::Drain(sender), receiver)
+    }
+
+    pub fn stats() -> (Self, oneshot::Receiver<TrieThreadStats>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::Stats(sender), receiver)
+    }
+
+    pub fn recover(
+        batches: Vec<StoredBatch>,
+        pending: Vec<ReplayedTransaction>,
+        l1_root: TrieRoot<NodeHash>,
+    ) -> (Self, oneshot::Receiver<Result<RecoveryReport, AppErr>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self::Recover(batches, pending, l1_root, sender), receiver)
+    }
 }
 
 pub fn spawn_state_thread(
@@ -54,58 +276,93 @@ pub fn spawn_state_thread(
     batch_outputs_receiver: mpsc::Sender<BatchOutput>,
     db: Database,
     batch_root: TrieRoot<NodeHash>,
+    next_event_index: Option<u32>,
+    events: EventBus,
 ) -> JoinHandle<()> {
     thread::spawn(move || {
-        let mut state = TrieState::new(db, batch_root);
+        let mut state = TrieState::new(db, batch_root, next_event_index);
+        if config.has_cost_budget() {
+            state.track_batch_cost();
+        }
+        let mut mempool = Mempool::new(config.mempool_expiry);
         let mut last_commit_time = Instant::now();
+        let mut intake_paused = false;
+
+        // Transactions executed after the last commit were answered already, they must not be lost.
+        match state.replay_unbatched() {
+            Ok(0) => {}
+            Ok(replayed) => tracing::info!(
+                "Replayed {} transactions executed after the last commit",
+                replayed
+            ),
+            Err(err) => {
+                // Committing now would leave them out of the batch, so transactions are not taken.
+                tracing::error!(
+                    "Failed to replay the transactions executed after the last commit, pausing intake: {}",
+                    err
+                );
+                intake_paused = true;
+            }
+        }
 
         while let Some(msg) = queue.blocking_recv() {
             tracing::trace!("Trie State Thread received message: {:?}", msg);
             match msg {
                 TrieStateThreadMsg::Transaction(txn, responder) => {
-                    let res = state.batch_state.execute_transaction(txn).map_err(|e| {
-                        tracing::warn!("Error executing transaction: {:?}", e);
-                        e
-                    });
-
-                    responder.send(res).unwrap_or_else(|err| {
-                        tracing::warn!(
-                            "Transaction submitter hung up before receiving response: {}",
-                            err.map(|()| "Success".to_string())
-                                .unwrap_or_else(|err| err.to_string())
-                        )
-                    });
+                    if intake_paused {
+                        let err = AppErr::new(anyhow::anyhow!("transaction intake is paused"))
+                            .set_status(StatusCode::SERVICE_UNAVAILABLE);
+                        reject_transaction(&events, txn, Some(responder), err);
+                        continue;
+                    }
 
-                    let should_commit = match config {
-                        BatchConfig {
-                            max_batch_size: Some(batch_size),
-                            ..
-                        } if state.batch_state.batched_txns.len() as u64 >= batch_size => true,
-                        BatchConfig {
-                            max_batch_duration: Some(duration),
-                            ..
-                        } if last_commit_time.elapsed() >= duration => true,
-                        _ => false,
-                    };
-
-                    if should_commit {
-                        let batch_output = state.commit_and_start_new_txn().unwrap_or_else(|err| {
-                            tracing::error!("Failed to commit trie state: {:?}", err);
-                            panic!("Failed to commit trie state: {:?}", err);
+                    let res = submit_transaction(&mut state, &mut mempool, &events, txn, responder)
+                        .and_then(|()| {
+                            commit_if_due(
+                                &config,
+                                &mut state,
+                                &mut last_commit_time,
+                                &events,
+                                &batch_outputs_receiver,
+                            )
                         });
+                    if let Err(err) = res {
+                        pause_intake(&mut intake_paused, &err);
+                    }
+                }
+                TrieStateThreadMsg::Deposit(event_index, deposit, responder) => {
+                    // The L1 sync retries the event, so the deposit is not rejected for good.
+                    if intake_paused {
+                        tracing::debug!("Deferring deposit of event {}", event_index);
+                        let err = AppErr::new(anyhow::anyhow!("transaction intake is paused"))
+                            .set_status(StatusCode::SERVICE_UNAVAILABLE);
+                        let _ = responder.send(Err(err));
+                        continue;
+                    }
 
-                        batch_outputs_receiver
-                            .blocking_send(batch_output)
-                            .unwrap_or_else(|err| {
-                                tracing::error!("Failed to send batch output: {:?}", err);
-                                panic!("Failed to send batch output: {:?}", err);
-                            });
-
-                        last_commit_time = Instant::now();
+                    let res = credit_deposit(&mut state, &events, event_index, deposit, responder)
+                        .and_then(|()| {
+                            commit_if_due(
+                                &config,
+                                &mut state,
+                                &mut last_commit_time,
+                                &events,
+                                &batch_outputs_receiver,
+                            )
+                        });
+                    if let Err(err) = res {
+                        pause_intake(&mut intake_paused, &err);
                     }
                 }
+                TrieStateThreadMsg::NextEventIndex(responder) => {
+                    let _ = responder.send(state.next_event_index);
+                }
                 TrieStateThreadMsg::Commit(sender) => {
-                    let res = state.commit_and_start_new_txn();
+                    let res = commit_batch(&mut state, &events, &batch_outputs_receiver);
+                    match &res {
+                        Ok(_) => last_commit_time = Instant::now(),
+                        Err(err) => pause_intake(&mut intake_paused, err),
+                    }
 
                     if let Err(err) = sender.send(res) {
                         tracing::error!("failed to send commit result: {:?}", err);
@@ -128,19 +385,418 @@ pub fn spawn_state_thread(
                         );
                     }
                 }
+                TrieStateThreadMsg::GetAccount(account, l1_root, responder) => {
+                    let res = state.get_account(&account, l1_root);
+                    if let Err(err) = responder.send(res) {
+                        tracing::error!("Failed to get the account '{:?}': {:?}", account, err);
+                    }
+                }
+                TrieStateThreadMsg::Tick => {
+                    for held in mempool.expire(Instant::now()) {
+                        let err = AppErr::new(anyhow::anyhow!(
+                            "transactions with lower nonces were not received in time"
+                        ))
+                        .set_status(StatusCode::CONFLICT);
+                        reject_transaction(&events, held.txn, None, err);
+                    }
+
+                    let batch_expired = config
+                        .max_batch_duration
+                        .is_some_and(|duration| last_commit_time.elapsed() >= duration);
+                    if batch_expired && !state.batch_state.batched_txns.is_empty() {
+                        match commit_batch(&mut state, &events, &batch_outputs_receiver) {
+                            Ok(_) => last_commit_time = Instant::now(),
+                            Err(err) => pause_intake(&mut intake_paused, &err),
+                        }
+                    }
+                }
+                TrieStateThreadMsg::SetIntakePaused(paused, responder) => {
+                    tracing::info!("Transaction intake paused: {}", paused);
+                    intake_paused = paused;
+                    let _ = responder.send(());
+                }
+                TrieStateThreadMsg::Drain(responder) => {
+                    let held = mempool.drain();
+                    let drained = held.len();
+                    for held in held {
+                        let err = AppErr::new(anyhow::anyhow!(
+                            "the transaction was drained before transactions with lower nonces arrived"
+                        ))
+                        .set_status(StatusCode::SERVICE_UNAVAILABLE);
+                        reject_transaction(&events, held.txn, None, err);
+                    }
+
+                    tracing::info!("Drained {} held transactions", drained);
+                    let _ = responder.send(drained);
+                }
+                TrieStateThreadMsg::Stats(responder) => {
+                    let batch_root: Option<[u8; 32]> = state.batch_root.into();
+                    let _ = responder.send(TrieThreadStats {
+                        intake_paused,
+                        batch_root: batch_root.map(hex::encode),
+                        batched_transactions: state.batch_state.batched_txns.len(),
+                        estimated_batch_cycles: state.batch_cost.as_ref().map(|cost| cost.cycles),
+                        batch_snapshot_bytes: state
+                            .batch_cost
+                            .as_ref()
+                            .map(|cost| cost.snapshot_bytes),
+                        held_transactions: mempool.len(),
+                        queued_messages: queue.len(),
+                        seconds_since_last_commit: last_commit_time.elapsed().as_secs(),
+                    });
+                }
+                TrieStateThreadMsg::Recover(batches, pending, l1_root, responder) => {
+                    let res = state.recover(batches, pending, l1_root);
+                    last_commit_time = Instant::now();
+
+                    if let Err(err) = responder.send(res) {
+                        tracing::error!("Failed to send recovery result: {:?}", err);
+                    }
+                }
             }
         }
     })
 }
 
+/// Commits the current batch once it's full or reached its cost budget,
+/// or once `max_batch_duration` has elapsed.
+/// The transaction that reaches the budget stays in the batch,
+/// so a batch may exceed its budget by one transaction.
+///
+/// Errors if the batch could not be committed, see `commit_batch`.
+fn commit_if_due(
+    config: &BatchConfig,
+    state: &mut TrieState,
+    last_commit_time: &mut Instant,
+    events: &EventBus,
+    batch_outputs: &mpsc::Sender<BatchOutput>,
+) -> Result<(), AppErr> {
+    let should_commit = match config {
+        BatchConfig {
+            max_batch_size: Some(batch_size),
+            ..
+        } if state.batch_state.batched_txns.len() as u64 >= *batch_size => true,
+        // A budget below the cost of an empty batch must not commit empty batches.
+        _ if !state.batch_state.batched_txns.is_empty()
+            && state
+                .batch_cost
+                .as_ref()
+                .is_some_and(|cost| cost.reached_budget(config)) =>
+        {
+            true
+        }
+        BatchConfig {
+            max_batch_duration: Some(duration),
+            ..
+        } if last_commit_time.elapsed() >= *duration => true,
+        _ => false,
+    };
+
+    if should_commit {
+        commit_batch(state, events, batch_outputs)?;
+        *last_commit_time = Instant::now();
+    }
+    Ok(())
+}
+
+/// Commits the current batch and hands it to the batch output handler to be proven.
+///
+/// Errors if the batch output handler is gone, the batch is then committed and recorded
+/// but it's only proven once the server restarts and resumes the unfinished batches.
+fn commit_batch(
+    state: &mut TrieState,
+    events: &EventBus,
+    batch_outputs: &mpsc::Sender<BatchOutput>,
+) -> Result<BatchOutput, AppErr> {
+    let batch_output = state.commit_and_start_new_txn()?;
+    events.publish(batch_output.committed_event());
+
+    batch_outputs
+        .blocking_send(batch_output.clone())
+        .map_err(|_| {
+            AppErr::new(anyhow::anyhow!(
+                "the batch output handler is gone, batch {:?} is not proven",
+                batch_output.id
+            ))
+            .set_status(StatusCode::SERVICE_UNAVAILABLE)
+        })?;
+
+    Ok(batch_output)
+}
+
+/// Stops taking transactions and deposits after `err`, a failure to record or commit the batch,
+/// rather than aborting the trie thread. Intake is resumed through the admin API.
+fn pause_intake(intake_paused: &mut bool, err: &AppErr) {
+    tracing::error!("Pausing transaction intake: {:?}", err);
+    *intake_paused = true;
+}
+
+/// Executes `txn`, or holds it in `mempool` if its nonce is ahead of its account's nonce.
+/// Held transactions that become executable are executed right after.
+///
+/// Errors if an executed transaction could not be recorded, see `execute_transaction`.
+fn submit_transaction(
+    state: &mut TrieState,
+    mempool: &mut Mempool,
+    events: &EventBus,
+    txn: KairosTransaction,
+    responder: oneshot::Sender<Result<(), AppErr>>,
+) -> Result<(), AppErr> {
+    if let Some((account, nonce)) = signed_nonce(&txn).filter(|_| mempool.is_enabled()) {
+        let account_nonce = state
+            .batch_state
+            .account_trie
+            .get_account(&account)
+            .map(|account| account.map_or(0, |account| account.nonce))
+            // If the account can't be read, executing the transaction reports the error.
+            .ok();
+
+        if let Some(account_nonce) = account_nonce.filter(|account_nonce| nonce > *account_nonce) {
+            let held = HeldTransaction::new(txn.clone());
+            match mempool.hold(account, nonce, account_nonce, held) {
+                Ok(()) => {
+                    events.publish(ServerEvent::TransactionHeld { transaction: txn });
+                    responder.send(Ok(())).unwrap_or_else(|_| {
+                        tracing::warn!(
+                            "Transaction submitter hung up before receiving response: Held"
+                        )
+                    });
+                }
+                Err((held, err)) => reject_transaction(events, held.txn, Some(responder), err),
+            }
+            return Ok(());
+        }
+    }
+
+    let mut next = Some((txn, Some(responder)));
+    while let Some((txn, responder)) = next.take() {
+        let signed = signed_nonce(&txn);
+        let executed = execute_transaction(state, events, txn, responder)?;
+
+        if let (true, Some((account, nonce))) = (executed, signed) {
+            next = mempool
+                .release(&account, nonce + 1)
+                .map(|held| (held.txn, None));
+        }
+    }
+    Ok(())
+}
+
+/// Executes `txn` against the current batch and answers `responder`,
+/// released transactions have no responder as their submitter was answered when they were held.
+/// Returns `true` if the transaction was accepted.
+///
+/// Errors if the transaction could not be recorded, it's then rejected.
+fn execute_transaction(
+    state: &mut TrieState,
+    events: &EventBus,
+    txn: KairosTransaction,
+    responder: Option<oneshot::Sender<Result<(), AppErr>>>,
+) -> Result<bool, AppErr> {
+    if let Err(err) = state.execute(txn.clone()) {
+        tracing::warn!("Error executing transaction: {:?}", err);
+        reject_transaction(events, txn, responder, err);
+        return Ok(false);
+    }
+    if let Err(err) = state.record_executed(&txn, None) {
+        reject_transaction(events, txn, responder, unrecorded_err());
+        return Err(err);
+    }
+
+    events.publish(ServerEvent::TransactionAccepted { transaction: txn });
+    if let Some(responder) = responder {
+        responder.send(Ok(())).unwrap_or_else(|_| {
+            tracing::warn!("Transaction submitter hung up before receiving response: Success")
+        });
+    }
+    Ok(true)
+}
+
+/// Credits the deposit emitted by the contract event `event_index` to the current batch
+/// and answers `responder`, with `false` if that event was already credited.
+///
+/// Errors if the deposit could not be recorded, it's then rejected and retried by the L1 sync.
+fn credit_deposit(
+    state: &mut TrieState,
+    events: &EventBus,
+    event_index: u32,
+    deposit: L1Deposit,
+    responder: oneshot::Sender<Result<bool, AppErr>>,
+) -> Result<(), AppErr> {
+    let (res, unrecorded) = match state.credit_deposit(event_index, deposit, events) {
+        Ok(res) => (res, Ok(())),
+        Err(err) => (Err(unrecorded_err()), Err(err)),
+    };
+    if let Err(err) = responder.send(res) {
+        tracing::warn!(
+            "Deposit submitter hung up before receiving response: {:?}",
+            err
+        );
+    }
+    unrecorded
+}
+
+/// The error a transaction is rejected with when it could not be recorded.
+fn unrecorded_err() -> AppErr {
+    AppErr::new(anyhow::anyhow!("the transaction could not be recorded"))
+        .set_status(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+fn reject_transaction(
+    events: &EventBus,
+    txn: KairosTransaction,
+    responder: Option<oneshot::Sender<Result<(), AppErr>>>,
+    err: AppErr,
+) {
+    events.publish(ServerEvent::TransactionRejected {
+        transaction: txn,
+        reason: err.to_string(),
+    });
+    let Some(responder) = responder else {
+        tracing::warn!("Rejected a held transaction: {}", err);
+        return;
+    };
+    responder.send(Err(err)).unwrap_or_else(|err| {
+        tracing::warn!(
+            "Transaction submitter hung up before receiving response: {}",
+            err.map(|()| "Success".to_string())
+                .unwrap_or_else(|err| err.to_string())
+        )
+    });
+}
+
+/// Returns the account and nonce of transfers and withdrawals, deposits have no nonce.
+fn signed_nonce(txn: &KairosTransaction) -> Option<(PublicKey, u64)> {
+    match txn {
+        KairosTransaction::Transfer(transfer) => {
+            Some((transfer.public_key.clone(), transfer.nonce))
+        }
+        KairosTransaction::Withdraw(withdraw) => {
+            Some((withdraw.public_key.clone(), withdraw.nonce))
+        }
+        KairosTransaction::Deposit(_) => None,
+    }
+}
+
 /// Proof input data that is sent to the L1 contract.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BatchOutput {
+    /// The sequence number of the batch, `None` if the batch is not persisted.
+    pub id: BatchId,
     pub new_root: TrieRoot<NodeHash>,
     pub old_root: TrieRoot<NodeHash>,
     pub proof_inputs: ProofInputs,
 }
 
+impl BatchOutput {
+    fn committed_event(&self) -> ServerEvent {
+        let old_root: Option<[u8; 32]> = self.old_root.into();
+        let new_root: Option<[u8; 32]> = self.new_root.into();
+
+        ServerEvent::BatchCommitted {
+            batch_id: self.id,
+            old_root: old_root.map(hex::encode),
+            new_root: new_root.map(hex::encode),
+            transactions: self.proof_inputs.transactions.clone(),
+        }
+    }
+}
+
+/// The state of an account as of the last committed batch,
+/// along with Merkle proofs that clients can check against the trie roots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    /// The balance as of the last committed batch, as proven by `proof`.
+    pub balance: u64,
+    /// The nonce as of the last committed batch, as proven by `proof`.
+    pub nonce: u64,
+    /// A proof against the root of the last committed batch, which may not be on L1 yet.
+    pub proof: AccountProof,
+    /// A proof against the trie root accepted on L1, `None` if the L1 root is unknown.
+    pub l1_proof: Option<AccountProof>,
+    /// What the transactions that are not committed to a batch yet change, they are not proven.
+    pub pending: PendingChange,
+}
+
+/// How the transactions of the current batch change an account.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingChange {
+    /// Negative if the account spends more than it receives.
+    pub balance: i128,
+    pub nonce: u64,
+}
+
+/// A Merkle proof of an account, or of its absence, against `root`.
+///
+/// `snapshot` holds the trie nodes on the path to the account,
+/// hashing it must produce `root` and looking up the account in it must produce `account`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub root: Option<[u8; 32]>,
+    pub account: Option<Account>,
+    pub snapshot: Snapshot<Account>,
+}
+
+/// A snapshot of the trie thread, for operators.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrieThreadStats {
+    pub intake_paused: bool,
+    /// Hex encoded root of the last committed batch, `None` if the trie is empty.
+    pub batch_root: Option<String>,
+    /// Transactions in the current batch.
+    pub batched_transactions: usize,
+    /// The estimated proving cost of the current batch, `None` without a cost budget.
+    pub estimated_batch_cycles: Option<u64>,
+    pub batch_snapshot_bytes: Option<u64>,
+    /// Transactions waiting in the mempool for lower nonces.
+    pub held_transactions: usize,
+    /// Messages waiting to be processed by the trie thread, behind the stats request.
+    pub queued_messages: usize,
+    pub seconds_since_last_commit: u64,
+}
+
+/// Summary of replaying the transaction history, see `TrieState::recover`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Committed batches that were rebuilt.
+    pub batches: usize,
+    /// Transactions of the committed batches that were applied to the trie.
+    pub replayed: usize,
+    /// Transactions executed after the last committed batch, they are left in the current batch.
+    pub pending: usize,
+}
+
+/// A committed batch to rebuild, see `TrieState::recover`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBatch {
+    /// The sequence number of the batch.
+    pub id: i64,
+    /// The trie root the batch committed.
+    pub new_root: TrieRoot<NodeHash>,
+    /// The transactions of the batch, in the order they were executed.
+    pub transactions: Vec<ReplayedTransaction>,
+}
+
+/// An executed transaction to replay, see `TrieState::recover`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayedTransaction {
+    pub txn: KairosTransaction,
+    /// The index of the contract event that emitted a deposit, `None` if it's not a deposit.
+    pub event_index: Option<u32>,
+    /// The order the transaction was executed in, `None` if it's not recorded.
+    pub seq: Option<i64>,
+}
+
+impl From<KairosTransaction> for ReplayedTransaction {
+    fn from(txn: KairosTransaction) -> Self {
+        Self {
+            txn,
+            event_index: None,
+            seq: None,
+        }
+    }
+}
+
 /// A struct for tracking the state of the trie between batches.
 ///
 /// The `TrieStateThread` responds to messages by applying transactions against this struct.
@@ -151,42 +807,355 @@ pub struct TrieState {
     /// The root hash of the trie at the start of the current batch.
     batch_root: TrieRoot<NodeHash>,
     batch_state: BatchState<SnapshotBuilder<Rc<Database>, Account>>,
+    /// The index of the next contract event to credit, deposits from earlier events are ignored.
+    /// It's recorded along with each committed root, so deposits are credited once across restarts.
+    /// `None` until a deposit is credited, if no committed root recorded it.
+    next_event_index: Option<u32>,
+    /// The estimated proving cost of the current batch, `None` unless it's tracked.
+    batch_cost: Option<BatchCost>,
+    /// The sequence numbers of the recorded transactions in the current batch,
+    /// they are assigned to the batch when it's committed.
+    batch_seqs: Option<RangeInclusive<i64>>,
 }
 
 impl TrieState {
-    pub fn new(db: Database, batch_root: TrieRoot<NodeHash>) -> Self {
+    pub fn new(
+        db: Database,
+        batch_root: TrieRoot<NodeHash>,
+        next_event_index: Option<u32>,
+    ) -> Self {
         let db = Rc::new(db);
 
         Self {
             db: db.clone(),
             batch_root,
             batch_state: BatchState::new(AccountTrie::new_try_from_db(db, batch_root)),
+            next_event_index,
+            batch_cost: None,
+            batch_seqs: None,
+        }
+    }
+
+    /// Estimates the proving cost of each batch from now on, see `BatchConfig::has_cost_budget`.
+    pub fn track_batch_cost(&mut self) {
+        self.batch_cost = Some(BatchCost::default());
+    }
+
+    /// Executes `txn` against the current batch and adds it to the batch cost.
+    fn execute(&mut self, txn: KairosTransaction) -> Result<(), AppErr> {
+        let Some(mut batch_cost) = self.batch_cost.take() else {
+            return self.batch_state.execute_transaction(txn);
+        };
+
+        let res = self.batch_state.execute_transaction(txn.clone());
+        if res.is_ok() {
+            batch_cost.add(&txn, |account| self.path_bytes(account));
+        }
+        self.batch_cost = Some(batch_cost);
+        res
+    }
+
+    fn reset_batch_cost(&mut self) {
+        if let Some(batch_cost) = &mut self.batch_cost {
+            *batch_cost = BatchCost::default();
         }
     }
 
+    /// The size of the snapshot of `account`'s path in the trie at the start of the batch.
+    fn path_bytes(&self, account: &PublicKey) -> u64 {
+        match self.prove_account(account, self.batch_root) {
+            Ok(proof) => input_bytes(&proof.snapshot),
+            Err(err) => {
+                tracing::warn!("Failed to measure the trie path of an account: {}", err);
+                0
+            }
+        }
+    }
+
+    /// Credits the deposit emitted by the contract event `event_index` to the current batch.
+    /// Returns `false` without touching the trie if that event was already credited,
+    /// or the error the deposit was rejected with.
+    ///
+    /// Errors if the deposit could not be recorded, see `record_executed`.
+    pub fn credit_deposit(
+        &mut self,
+        event_index: u32,
+        deposit: L1Deposit,
+        events: &EventBus,
+    ) -> Result<Result<bool, AppErr>, AppErr> {
+        if self
+            .next_event_index
+            .is_some_and(|next_event_index| event_index < next_event_index)
+        {
+            tracing::debug!("Deposit of event {} was already credited", event_index);
+            return Ok(Ok(false));
+        }
+
+        let txn = KairosTransaction::Deposit(deposit);
+        if let Err(err) = self.execute(txn.clone()) {
+            tracing::warn!(
+                "Error executing deposit of event {}: {:?}",
+                event_index,
+                err
+            );
+            events.publish(ServerEvent::TransactionRejected {
+                transaction: txn,
+                reason: err.to_string(),
+            });
+            return Ok(Err(err));
+        }
+
+        if let Err(err) = self.record_executed(&txn, Some(event_index)) {
+            events.publish(ServerEvent::TransactionRejected {
+                transaction: txn,
+                reason: unrecorded_err().to_string(),
+            });
+            return Err(err);
+        }
+        self.next_event_index = Some(event_index + 1);
+        events.publish(ServerEvent::TransactionAccepted { transaction: txn });
+        Ok(Ok(true))
+    }
+
+    /// Records the executed `txn`, so the batch can be rebuilt after a restart.
+    ///
+    /// Errors if it can't be recorded. The trie already applied it, so the current batch
+    /// is rebuilt from the recorded transactions rather than diverge from the recorded history.
+    fn record_executed(
+        &mut self,
+        txn: &KairosTransaction,
+        event_index: Option<u32>,
+    ) -> Result<(), AppErr> {
+        let seq = match self.db.record_executed_transaction(txn, event_index) {
+            Ok(seq) => seq,
+            Err(err) => {
+                tracing::error!("Failed to record executed transaction: {:?}", err);
+                self.reset_batch();
+                if let Err(replay_err) = self.replay_unbatched() {
+                    tracing::error!(
+                        "Failed to rebuild the batch from the recorded transactions: {}",
+                        replay_err
+                    );
+                }
+                return Err(err);
+            }
+        };
+        self.add_batch_seq(seq);
+        Ok(())
+    }
+
+    fn add_batch_seq(&mut self, seq: Option<i64>) {
+        if let Some(seq) = seq {
+            self.batch_seqs = Some(match self.batch_seqs.take() {
+                Some(seqs) => *seqs.start()..=seq,
+                None => seq..=seq,
+            });
+        }
+    }
+
+    /// Executes the transactions recorded after the last committed batch against the current batch,
+    /// so they are committed with the next batch. Returns their number.
+    ///
+    /// Errors if a recorded transaction is rejected, the current batch is then left empty.
+    pub fn replay_unbatched(&mut self) -> Result<usize, AppErr> {
+        let unbatched = self.db.unbatched_transactions()?;
+        let next_event_index = self.next_event_index;
+
+        let res = self.replay(unbatched);
+        if res.is_err() {
+            self.reset_batch();
+            self.next_event_index = next_event_index;
+        }
+        res
+    }
+
+    /// Drops the transactions of the current batch, the trie is back at `batch_root`.
+    fn reset_batch(&mut self) {
+        self.batch_state = BatchState::new(AccountTrie::new_try_from_db(
+            self.db.clone(),
+            self.batch_root,
+        ));
+        self.reset_batch_cost();
+        self.batch_seqs = None;
+    }
+
+    /// Returns the state of `account` as of the last committed batch with proofs against its root,
+    /// and against `l1_root` if it's known.
+    /// The changes made by the current batch are reported separately.
+    ///
+    /// Errors if `l1_root` is not in the database.
+    pub fn get_account(
+        &self,
+        account: &PublicKey,
+        l1_root: Option<TrieRoot<NodeHash>>,
+    ) -> Result<AccountState, AppErr> {
+        let current = self
+            .batch_state
+            .account_trie
+            .get_account(account)
+            .map_err(|err| AppErr::new(anyhow::anyhow!(err)))?
+            .unwrap_or_else(|| Account::new(0, 0));
+        let proof = self.prove_account(account, self.batch_root)?;
+        let committed = proof.account.clone().unwrap_or_else(|| Account::new(0, 0));
+
+        Ok(AccountState {
+            balance: committed.balance,
+            nonce: committed.nonce,
+            pending: PendingChange {
+                balance: i128::from(current.balance) - i128::from(committed.balance),
+                nonce: current.nonce - committed.nonce,
+            },
+            proof,
+            l1_proof: l1_root
+                .map(|l1_root| self.prove_account(account, l1_root))
+                .transpose()?,
+        })
+    }
+
+    fn prove_account(
+        &self,
+        account: &PublicKey,
+        root: TrieRoot<NodeHash>,
+    ) -> Result<AccountProof, AppErr> {
+        let (account, snapshot) = AccountTrie::new_try_from_db(self.db.clone(), root)
+            .prove_account(account)
+            .map_err(|err| AppErr::new(anyhow::anyhow!(err)))?;
+
+        Ok(AccountProof {
+            root: root.into(),
+            account,
+            snapshot,
+        })
+    }
+
+    /// Rebuild the trie from the empty root by replaying the committed `batches`
+    /// and then the `pending` transactions, in the order they were executed.
+    ///
+    /// Each batch must reproduce the root it committed, the batches themselves are already stored
+    /// and queued for proving, so they are committed without being recorded again.
+    /// The pending transactions stay in the current batch.
+    /// Replayed deposits move `next_event_index` past their event.
+    ///
+    /// Errors if a stored transaction is rejected, a batch doesn't reproduce its root,
+    /// or `l1_root` is not the root of any batch.
+    pub fn recover(
+        &mut self,
+        batches: Vec<StoredBatch>,
+        pending: Vec<ReplayedTransaction>,
+        l1_root: TrieRoot<NodeHash>,
+    ) -> Result<RecoveryReport, AppErr> {
+        self.batch_root = TrieRoot::Empty;
+        self.next_event_index = None;
+        self.batch_state = BatchState::new(AccountTrie::new_try_from_db(
+            self.db.clone(),
+            TrieRoot::Empty,
+        ));
+        self.reset_batch_cost();
+        self.batch_seqs = None;
+
+        let mut report = RecoveryReport::default();
+        let mut l1_root_reached = l1_root == TrieRoot::Empty;
+
+        for batch in batches {
+            report.replayed += self.replay(batch.transactions)?;
+
+            let root = self
+                .batch_state
+                .account_trie
+                .txn
+                .calc_root_hash(&mut DigestHasher::<Sha256>::default())?;
+            if root != batch.new_root {
+                return Err(AppErr::new(anyhow::anyhow!(
+                    "Replaying batch {} produced the root {:?} instead of {:?}",
+                    batch.id,
+                    root,
+                    batch.new_root
+                )));
+            }
+
+            self.commit(false)?;
+            report.batches += 1;
+            l1_root_reached |= root == l1_root;
+        }
+
+        if !l1_root_reached {
+            return Err(AppErr::new(anyhow::anyhow!(
+                "No committed batch has the L1 trie root: {:?}",
+                l1_root
+            )));
+        }
+
+        report.pending = self.replay(pending)?;
+        Ok(report)
+    }
+
+    /// Executes stored transactions against the current batch, returns their number.
+    fn replay(&mut self, txns: Vec<ReplayedTransaction>) -> Result<usize, AppErr> {
+        let replayed = txns.len();
+        for ReplayedTransaction {
+            txn,
+            event_index,
+            seq,
+        } in txns
+        {
+            self.execute(txn).map_err(|err| {
+                AppErr::new(anyhow::anyhow!(
+                    "A stored transaction was rejected during replay: {}",
+                    err
+                ))
+            })?;
+            if let Some(event_index) = event_index {
+                self.next_event_index = Some(event_index + 1);
+            }
+            self.add_batch_seq(seq);
+        }
+
+        Ok(replayed)
+    }
+
     /// Calculate the new root hash of the trie and sync changes to the database.
+    /// The batch is persisted along with the new root, so it is proven even if the server restarts.
     ///
     /// Errors if underlying trie commit fails due to data database connection or consistency issues.
     pub fn commit_and_start_new_txn(&mut self) -> Result<BatchOutput, AppErr> {
+        self.commit(true)
+    }
+
+    /// Commits the current batch, `record_batch` is `false` for batches that are already on L1.
+    fn commit(&mut self, record_batch: bool) -> Result<BatchOutput, AppErr> {
         let old_trie_txn = &self.batch_state.account_trie;
         let old_root = self.batch_root;
         let new_root = old_trie_txn
             .txn
             .commit(&mut DigestHasher::<Sha256>::default())?;
 
-        let snapshot = old_trie_txn.txn.build_initial_snapshot();
-        let new_trie_txn = AccountTrie::new_try_from_db(self.db.clone(), new_root);
-
-        let old_batch_state = mem::replace(&mut self.batch_state, BatchState::new(new_trie_txn));
-        self.batch_root = new_root;
-
-        Ok(BatchOutput {
+        let mut batch_output = BatchOutput {
+            id: None,
             new_root,
             old_root,
             proof_inputs: ProofInputs {
-                transactions: old_batch_state.batched_txns.into(),
-                trie_snapshot: snapshot,
+                transactions: self.batch_state.batched_txns.clone().into(),
+                trie_snapshot: old_trie_txn.txn.build_initial_snapshot(),
             },
-        })
+        };
+
+        if record_batch {
+            batch_output.id = self.db.record_committed_batch(
+                &batch_output,
+                self.next_event_index,
+                self.batch_seqs.clone(),
+            )?;
+        } else {
+            self.db
+                .record_committed_root(new_root, self.next_event_index)?;
+        }
+
+        let new_trie_txn = AccountTrie::new_try_from_db(self.db.clone(), new_root);
+        self.batch_state = BatchState::new(new_trie_txn);
+        self.batch_root = new_root;
+        self.reset_batch_cost();
+        self.batch_seqs = None;
+
+        Ok(batch_output)
     }
 }
@@ -1,10 +1,8 @@
 use axum_extra::routing::TypedPath;
 use axum_test::{TestServer, TestServerConfig};
 use reqwest::Url;
-use std::collections::HashSet;
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
-use tokio::sync::RwLock;
 use tracing_subscriber::{prelude::*, EnvFilter};
 
 use casper_client::{
@@ -19,13 +17,21 @@ use cctl::CCTLNetwork;
 #[cfg(feature = "database")]
 use kairos_data::new as new_pool;
 use kairos_server::{
-    config::{BatchConfig, ServerConfig},
+    config::{
+        BatchConfig, ProverKind, ProvingServer, ServerConfig, DEFAULT_MEMPOOL_EXPIRY,
+        DEFAULT_PROVING_TIMEOUT, DEFAULT_SUBMISSION_PAYMENT, DEFAULT_SUBMISSION_TTL,
+    },
     routes::deposit::DepositPath,
-    state::{BatchStateManager, ServerStateInner},
+    state::{
+        deposit_status::DepositStatuses, BatchStateManager, L1SyncStatus, ServerState,
+        ServerStateInner,
+    },
 };
 #[cfg(feature = "database")]
 use kairos_test_utils::postgres::PostgresDB;
 
+#[cfg(feature = "deposit-mock")]
+use kairos_crypto::{implementations::Signer, SignerCore, SignerFsExtension, SignerTxExtension};
 #[cfg(feature = "deposit-mock")]
 use kairos_server::routes::{
     deposit_mock::MockDepositPath, transfer::TransferPath, withdraw::WithdrawPath, PayloadBody,
@@ -35,6 +41,26 @@ use kairos_tx::asn::{SigningPayload, Transfer, Withdrawal};
 
 static TEST_ENVIRONMENT: OnceLock<()> = OnceLock::new();
 
+#[cfg(feature = "deposit-mock")]
+fn load_signer(relative_path: &str) -> Signer {
+    let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.extend(["..", relative_path, "secret_key.pem"].iter());
+    Signer::from_private_key_file(path).expect("Failed to load secret key")
+}
+
+#[cfg(feature = "deposit-mock")]
+fn sign_payload(signer: &Signer, payload: SigningPayload) -> PayloadBody {
+    let tx = signer
+        .sign_tx_payload(payload)
+        .expect("Failed to sign payload");
+
+    PayloadBody {
+        public_key: tx.public_key.into(),
+        payload: tx.payload.der_encode().unwrap(),
+        signature: tx.signature.into(),
+    }
+}
+
 #[cfg(feature = "deposit-mock")]
 async fn new_test_app(#[cfg(feature = "database")] postgres_url: &Url) -> TestServer {
     let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
@@ -48,52 +74,102 @@ async fn new_test_app(#[cfg(feature = "database")] postgres_url: &Url) -> TestSe
     .await
 }
 
-async fn new_test_app_with_casper_node(
+fn test_server_config(
     casper_rpc_url: &Url,
     casper_sse_url: &Url,
     #[cfg(feature = "database")] postgres_url: &Url,
-) -> TestServer {
-    TEST_ENVIRONMENT.get_or_init(|| {
-        tracing_subscriber::registry()
-            .with(
-                EnvFilter::try_from_default_env()
-                    .unwrap_or_else(|_| "info,kairos_server=trace".into()),
-            )
-            .with(tracing_subscriber::fmt::layer())
-            .init();
-    });
-    let config = TestServerConfig::builder().mock_transport().build();
-    let server_config = ServerConfig {
+) -> ServerConfig {
+    ServerConfig {
         secret_key_file: None,
         socket_addr: "0.0.0.0:0".parse().unwrap(),
         casper_rpc: casper_rpc_url.clone(),
         casper_sse: casper_sse_url.clone(),
         // For testing purposes, we set the sync interval to be fast
         casper_sync_interval: Duration::from_secs(5),
+        // Credit deposits as soon as they are executed on the test node
+        casper_confirmations: 0,
         kairos_demo_contract_hash: ContractHash::default(),
         batch_config: BatchConfig {
             max_batch_size: None,
             max_batch_duration: None,
+            max_batch_cycles: None,
+            max_batch_snapshot_bytes: None,
+            prover: ProverKind::Http,
             // dummy proving server will never be called because of max_batch_size and max_batch_duration
-            proving_server: Url::parse("http://127.0.0.1:7894").unwrap(),
+            proving_servers: vec![ProvingServer::new(
+                Url::parse("http://127.0.0.1:7894").unwrap(),
+            )],
+            proving_timeout: DEFAULT_PROVING_TIMEOUT,
+            mempool_expiry: DEFAULT_MEMPOOL_EXPIRY,
+            submission_payment: DEFAULT_SUBMISSION_PAYMENT,
+            submission_ttl: DEFAULT_SUBMISSION_TTL,
         },
+        admin_config: None,
         #[cfg(feature = "database")]
         db_addr: postgres_url.to_string(),
-    };
+        #[cfg(feature = "database")]
+        recover_l2_state: false,
+    }
+}
 
-    let state = Arc::new(ServerStateInner {
-        batch_state_manager: BatchStateManager::new_empty(&server_config),
-        server_config,
-        known_deposit_deploys: RwLock::new(HashSet::new()),
+async fn new_test_app_with_casper_node(
+    casper_rpc_url: &Url,
+    casper_sse_url: &Url,
+    #[cfg(feature = "database")] postgres_url: &Url,
+) -> TestServer {
+    let state = new_test_state(
+        casper_rpc_url,
+        casper_sse_url,
         #[cfg(feature = "database")]
-        pool: new_pool(postgres_url.as_ref())
-            .await
-            .expect("Failed to connect to database"),
-    });
+        postgres_url,
+    )
+    .await;
 
+    new_test_server(state)
+}
+
+fn new_test_server(state: ServerState) -> TestServer {
+    let config = TestServerConfig::builder().mock_transport().build();
     TestServer::new_with_config(kairos_server::app_router(state), config).unwrap()
 }
 
+async fn new_test_state(
+    casper_rpc_url: &Url,
+    casper_sse_url: &Url,
+    #[cfg(feature = "database")] postgres_url: &Url,
+) -> ServerState {
+    TEST_ENVIRONMENT.get_or_init(|| {
+        tracing_subscriber::registry()
+            .with(
+                EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| "info,kairos_server=trace".into()),
+            )
+            .with(tracing_subscriber::fmt::layer())
+            .init();
+    });
+    let server_config = test_server_config(
+        casper_rpc_url,
+        casper_sse_url,
+        #[cfg(feature = "database")]
+        postgres_url,
+    );
+
+    new_test_state_from_config(server_config).await
+}
+
+async fn new_test_state_from_config(server_config: ServerConfig) -> ServerState {
+    Arc::new(ServerStateInner {
+        batch_state_manager: BatchStateManager::new_empty(&server_config).unwrap(),
+        #[cfg(feature = "database")]
+        pool: new_pool(&server_config.db_addr)
+            .await
+            .expect("Failed to connect to database"),
+        server_config,
+        deposit_statuses: DepositStatuses::default(),
+        l1_sync_status: L1SyncStatus::default(),
+    })
+}
+
 #[tokio::test]
 #[cfg_attr(not(feature = "cctl-tests"), ignore)]
 async fn test_signed_deploy_is_forwarded_if_sender_in_approvals() {
@@ -170,8 +246,11 @@ async fn test_deposit_withdraw() {
     )
     .await;
 
+    let alice = load_signer("testdata/users/user-2");
+    let alice_public_key = alice.to_public_key().unwrap();
+
     let deposit = L1Deposit {
-        recipient: "alice_key".into(),
+        recipient: alice_public_key.clone(),
         amount: 100,
     };
 
@@ -200,51 +279,39 @@ async fn test_deposit_withdraw() {
     // first withdrawal
     server
         .post(WithdrawPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(0, Withdrawal::new(50))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Withdrawal::new(50)),
+        ))
         .await
         .assert_status_success();
 
     // withdrawal with insufficient funds
     server
         .post(WithdrawPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(1, Withdrawal::new(51))
-                .der_encode()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(1, Withdrawal::new(51)),
+        ))
         .await
         .assert_status_failure();
 
     // second withdrawal
     server
         .post(WithdrawPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(1, Withdrawal::new(50))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(1, Withdrawal::new(50)),
+        ))
         .await
         .assert_status_success();
 
     server
         .post(WithdrawPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(2, Withdrawal::new(50))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(2, Withdrawal::new(50)),
+        ))
         .await
         .assert_status_failure();
 }
@@ -263,11 +330,15 @@ async fn test_deposit_transfer_withdraw() {
     )
     .await;
 
+    let alice = load_signer("testdata/users/user-2");
+    // secp256k1 key, the testdata users are all ed25519
+    let bob = load_signer("kairos-cli/tests/fixtures/secp256k1");
+
     // deposit
     server
         .post(MockDepositPath.to_uri().path())
         .json(&L1Deposit {
-            recipient: "alice_key".into(),
+            recipient: alice.to_public_key().unwrap(),
             amount: 100,
         })
         .await
@@ -276,26 +347,20 @@ async fn test_deposit_transfer_withdraw() {
     // transfer
     server
         .post(TransferPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(0, Transfer::new("bob_key".as_bytes(), 50))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Transfer::new(bob.to_public_key().unwrap(), 50)),
+        ))
         .await
         .assert_status_success();
 
     // withdraw
     server
         .post(WithdrawPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "bob_key".into(),
-            payload: SigningPayload::new(0, Withdrawal::new(50))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &bob,
+            SigningPayload::new(0, Withdrawal::new(50)),
+        ))
         .await
         .assert_status_success();
 }
@@ -314,11 +379,14 @@ async fn test_deposit_transfer_to_self_withdraw() {
     )
     .await;
 
+    let alice = load_signer("testdata/users/user-2");
+    let alice_public_key = alice.to_public_key().unwrap();
+
     // deposit
     server
         .post(MockDepositPath.to_uri().path())
         .json(&L1Deposit {
-            recipient: "alice_key".into(),
+            recipient: alice_public_key.clone(),
             amount: 1000,
         })
         .await
@@ -327,26 +395,1463 @@ async fn test_deposit_transfer_to_self_withdraw() {
     // transfer
     server
         .post(TransferPath.to_uri().path())
-        .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(0, Transfer::new("alice_key".as_bytes(), 1000))
-                .try_into()
-                .unwrap(),
-            signature: vec![],
-        })
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Transfer::new(alice_public_key, 1000)),
+        ))
         .await
         .assert_status_failure();
 
     // withdraw
+    server
+        .post(WithdrawPath.to_uri().path())
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Withdrawal::new(1000)),
+        ))
+        .await
+        .assert_status_success();
+}
+
+#[tokio::test]
+#[cfg(feature = "deposit-mock")]
+async fn test_invalid_signature_is_rejected() {
+    use axum::http::StatusCode;
+    use kairos_circuit_logic::transactions::L1Deposit;
+
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+
+    let server = new_test_app(
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+
+    let alice = load_signer("testdata/users/user-2");
+    let mallory = load_signer("testdata/users/user-3");
+
+    server
+        .post(MockDepositPath.to_uri().path())
+        .json(&L1Deposit {
+            recipient: alice.to_public_key().unwrap(),
+            amount: 100,
+        })
+        .await
+        .assert_status_success();
+
+    // missing signature
     server
         .post(WithdrawPath.to_uri().path())
         .json(&PayloadBody {
-            public_key: "alice_key".into(),
-            payload: SigningPayload::new(0, Withdrawal::new(1000))
+            public_key: alice.to_public_key().unwrap(),
+            payload: SigningPayload::new(0, Withdrawal::new(50))
                 .try_into()
                 .unwrap(),
             signature: vec![],
         })
         .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // signed by someone else on behalf of alice
+    let forged = PayloadBody {
+        public_key: alice.to_public_key().unwrap(),
+        ..sign_payload(
+            &mallory,
+            SigningPayload::new(0, Transfer::new(mallory.to_public_key().unwrap(), 50)),
+        )
+    };
+    server
+        .post(TransferPath.to_uri().path())
+        .json(&forged)
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    // alice's funds are untouched
+    server
+        .post(WithdrawPath.to_uri().path())
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Withdrawal::new(100)),
+        ))
+        .await
         .assert_status_success();
 }
+
+#[tokio::test]
+#[cfg(feature = "database")]
+async fn test_trie_survives_restart() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+    use kairos_server::state::TrieStateThreadMsg;
+
+    let postgres = PostgresDB::run(None).unwrap();
+    let postgres_url: Url = postgres.connection.clone().into();
+    // Creating the pool runs the migrations.
+    let pool = new_pool(postgres_url.as_ref())
+        .await
+        .expect("Failed to connect to database");
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let server_config = test_server_config(&dummy_url, &dummy_url, &postgres_url);
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+
+    {
+        let batch_state_manager =
+            BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+        batch_state_manager
+            .enqueue_transaction(KairosTransaction::Deposit(L1Deposit {
+                recipient: alice_public_key.clone(),
+                amount: 100,
+            }))
+            .await
+            .unwrap();
+
+        let (msg, response) = TrieStateThreadMsg::commit();
+        batch_state_manager
+            .queued_transactions
+            .send(msg)
+            .await
+            .unwrap();
+        response.await.unwrap().unwrap();
+    }
+
+    // The account is only known if the trie was reopened at the committed root.
+    let batch_state_manager =
+        BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+    let nonce = batch_state_manager
+        .get_nonce_for(alice_public_key)
+        .await
+        .unwrap();
+    assert_eq!(nonce, 0);
+}
+
+#[tokio::test]
+#[cfg(feature = "database")]
+async fn test_unbatched_transactions_survive_restart() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit, Signed, Withdraw};
+    use kairos_data::transaction as db;
+
+    let postgres = PostgresDB::run(None).unwrap();
+    let postgres_url: Url = postgres.connection.clone().into();
+    let pool = new_pool(postgres_url.as_ref())
+        .await
+        .expect("Failed to connect to database");
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let server_config = test_server_config(&dummy_url, &dummy_url, &postgres_url);
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let deposit = L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    };
+    let withdraw = |nonce| {
+        KairosTransaction::Withdraw(Signed {
+            public_key: alice_public_key.clone(),
+            nonce,
+            transaction: Withdraw { amount: 10 },
+        })
+    };
+
+    {
+        let batch_state_manager =
+            BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+        assert!(batch_state_manager
+            .enqueue_deposit(0, deposit.clone())
+            .await
+            .unwrap());
+        batch_state_manager.commit().await.unwrap();
+
+        // Executed but not committed before the restart.
+        batch_state_manager
+            .enqueue_transaction(withdraw(0))
+            .await
+            .unwrap();
+        assert!(batch_state_manager
+            .enqueue_deposit(1, deposit.clone())
+            .await
+            .unwrap());
+    }
+
+    // The unbatched transactions are back in the current batch.
+    let batch_state_manager =
+        BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+    let stats = batch_state_manager.stats().await.unwrap();
+    assert_eq!(stats.batched_transactions, 2);
+    assert_eq!(
+        batch_state_manager
+            .get_nonce_for(alice_public_key.clone())
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(!batch_state_manager
+        .enqueue_deposit(1, deposit.clone())
+        .await
+        .unwrap());
+
+    batch_state_manager
+        .enqueue_transaction(withdraw(1))
+        .await
+        .unwrap();
+    batch_state_manager.commit().await.unwrap();
+    batch_state_manager
+        .enqueue_transaction(withdraw(2))
+        .await
+        .unwrap();
+
+    // Each batch holds the transactions it executed, the last one is still unbatched.
+    let executed = db::get_executed(&pool).await.unwrap();
+    let batch_ids = executed.iter().map(|row| row.batch_id).collect::<Vec<_>>();
+    assert!(batch_ids[0].is_some());
+    assert!(batch_ids[1].is_some() && batch_ids[1] != batch_ids[0]);
+    assert_eq!(batch_ids[1..4], [batch_ids[1]; 3]);
+    assert_eq!(batch_ids[4], None);
+    let executed = executed
+        .into_iter()
+        .map(|row| KairosTransaction::try_from(row).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        executed,
+        vec![
+            KairosTransaction::Deposit(deposit.clone()),
+            withdraw(0),
+            KairosTransaction::Deposit(deposit),
+            withdraw(1),
+            withdraw(2),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_recover_replays_transactions() {
+    use kairos_circuit_logic::transactions::{
+        KairosTransaction, L1Deposit, Signed, Transfer as L2Transfer,
+    };
+    use kairos_server::state::StoredBatch;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let server_config = test_server_config(&dummy_url, &dummy_url);
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let bob_public_key = "bob_key".as_bytes().to_vec();
+    let deposit = KairosTransaction::Deposit(L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    });
+    let transfer = KairosTransaction::Transfer(Signed {
+        public_key: alice_public_key.clone(),
+        nonce: 0,
+        transaction: L2Transfer {
+            recipient: bob_public_key,
+            amount: 50,
+        },
+    });
+
+    // The batch that is rebuilt.
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    batch_state_manager
+        .enqueue_transaction(deposit.clone())
+        .await
+        .unwrap();
+    let batch_root = batch_state_manager.commit().await.unwrap().new_root;
+    let batch = |new_root| StoredBatch {
+        id: 1,
+        new_root,
+        transactions: vec![deposit.clone().into()],
+    };
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    let report = batch_state_manager
+        .recover(vec![batch(batch_root)], vec![transfer.clone()], batch_root)
+        .await
+        .unwrap();
+    assert_eq!(report.batches, 1);
+    assert_eq!(report.replayed, 1);
+    assert_eq!(report.pending, 1);
+
+    let nonce = batch_state_manager
+        .get_nonce_for(alice_public_key)
+        .await
+        .unwrap();
+    assert_eq!(nonce, 1);
+    let stats = batch_state_manager.stats().await.unwrap();
+    let batch_root_hex = Option::<[u8; 32]>::from(batch_root).map(hex::encode);
+    assert_eq!(stats.batch_root, batch_root_hex);
+    assert_eq!(stats.batched_transactions, 1);
+
+    // Only executed transactions are stored, so a rejected one means the store is corrupt.
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    assert!(batch_state_manager
+        .recover(
+            vec![batch(batch_root)],
+            vec![transfer.clone(), transfer.clone()],
+            batch_root
+        )
+        .await
+        .is_err());
+
+    // The batch must reproduce the root it committed.
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    assert!(batch_state_manager
+        .recover(
+            vec![batch(Some([1u8; 32]).into())],
+            Vec::<KairosTransaction>::new(),
+            batch_root
+        )
+        .await
+        .is_err());
+
+    // No batch committed an unrelated L1 root.
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    assert!(batch_state_manager
+        .recover(
+            vec![batch(batch_root)],
+            vec![transfer],
+            Some([1u8; 32]).into()
+        )
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+#[cfg(feature = "database")]
+async fn test_recover_replays_the_execution_order() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit, Signed, Withdraw};
+    use kairos_data::transaction as db;
+
+    let postgres = PostgresDB::run(None).unwrap();
+    let postgres_url: Url = postgres.connection.clone().into();
+    let pool = new_pool(postgres_url.as_ref())
+        .await
+        .expect("Failed to connect to database");
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let server_config = test_server_config(&dummy_url, &dummy_url, &postgres_url);
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let deposit = KairosTransaction::Deposit(L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    });
+    let withdraw = |nonce| {
+        KairosTransaction::Withdraw(Signed {
+            public_key: alice_public_key.clone(),
+            nonce,
+            transaction: Withdraw { amount: 10 },
+        })
+    };
+
+    let batch_root = {
+        let batch_state_manager =
+            BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+        batch_state_manager
+            .enqueue_transaction(deposit.clone())
+            .await
+            .unwrap();
+        batch_state_manager.commit().await.unwrap();
+
+        // Nonce 1 arrives first and is held, it's executed after nonce 0.
+        let (second, first) = tokio::join!(
+            batch_state_manager.enqueue_transaction(withdraw(1)),
+            batch_state_manager.enqueue_transaction(withdraw(0)),
+        );
+        first.unwrap();
+        second.unwrap();
+        let batch_root = batch_state_manager.commit().await.unwrap().new_root;
+
+        // Rejected transactions are not stored.
+        batch_state_manager
+            .enqueue_transaction(withdraw(1))
+            .await
+            .unwrap_err();
+        batch_state_manager
+            .enqueue_transaction(withdraw(2))
+            .await
+            .unwrap();
+        batch_root
+    };
+
+    let executed = db::get_executed(&pool).await.unwrap();
+    let batch_ids = executed.iter().map(|row| row.batch_id).collect::<Vec<_>>();
+    assert_eq!(batch_ids[2], batch_ids[1]);
+    assert_eq!(batch_ids[3], None);
+    let executed = executed
+        .into_iter()
+        .map(|row| KairosTransaction::try_from(row).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        executed,
+        vec![deposit, withdraw(0), withdraw(1), withdraw(2)]
+    );
+
+    let state = ServerStateInner {
+        batch_state_manager: BatchStateManager::new_persistent(&server_config, pool.clone())
+            .unwrap(),
+        server_config,
+        deposit_statuses: DepositStatuses::default(),
+        l1_sync_status: L1SyncStatus::default(),
+        pool,
+    };
+    kairos_server::recover_l2_state(&state).await;
+
+    let nonce = state
+        .batch_state_manager
+        .get_nonce_for(alice_public_key)
+        .await
+        .unwrap();
+    assert_eq!(nonce, 3);
+    let stats = state.batch_state_manager.stats().await.unwrap();
+    let batch_root_hex = Option::<[u8; 32]>::from(batch_root).map(hex::encode);
+    assert_eq!(stats.batch_root, batch_root_hex);
+    assert_eq!(stats.batched_transactions, 1);
+}
+
+#[tokio::test]
+#[cfg(feature = "database")]
+async fn test_committed_batch_is_tracked() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+    use kairos_data::batch::{Batch, BatchStatus};
+    use kairos_server::{
+        routes::batches::{BatchPath, BatchesPath},
+        state::TrieStateThreadMsg,
+    };
+
+    let postgres = PostgresDB::run(None).unwrap();
+    let postgres_url: Url = postgres.connection.clone().into();
+    let pool = new_pool(postgres_url.as_ref())
+        .await
+        .expect("Failed to connect to database");
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let server_config = test_server_config(&dummy_url, &dummy_url, &postgres_url);
+    let batch_state_manager =
+        BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+
+    batch_state_manager
+        .enqueue_transaction(KairosTransaction::Deposit(L1Deposit {
+            recipient: "alice_key".as_bytes().to_vec(),
+            amount: 100,
+        }))
+        .await
+        .unwrap();
+    let (msg, response) = TrieStateThreadMsg::commit();
+    batch_state_manager
+        .queued_transactions
+        .send(msg)
+        .await
+        .unwrap();
+    response.await.unwrap().unwrap();
+
+    let state = Arc::new(ServerStateInner {
+        batch_state_manager,
+        server_config,
+        deposit_statuses: DepositStatuses::default(),
+        l1_sync_status: L1SyncStatus::default(),
+        pool,
+    });
+    let server = TestServer::new_with_config(
+        kairos_server::app_router(state),
+        TestServerConfig::builder().mock_transport().build(),
+    )
+    .unwrap();
+
+    // The batch is recorded asynchronously by the batch output handler.
+    let mut batches = Vec::new();
+    for _ in 0..50 {
+        batches = server
+            .get(&BatchesPath.to_uri().path())
+            .await
+            .json::<Vec<Batch>>();
+        if !batches.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(batches.len(), 1);
+    assert_eq!(batches[0].old_root, None);
+    assert!(batches[0].new_root.is_some());
+    assert_eq!(batches[0].transactions.as_array().unwrap().len(), 1);
+
+    // The dummy proving server is unreachable, the failure is recorded and the batch retried.
+    let mut batch = None;
+    for _ in 0..50 {
+        let response = server
+            .get(&BatchPath { id: batches[0].id }.to_uri().path())
+            .await
+            .json::<Batch>();
+        if response.attempts > 0 {
+            batch = Some(response);
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let batch = batch.expect("Batch failure was not recorded");
+    assert_eq!(batch.id, batches[0].id);
+    assert_eq!(batch.status, BatchStatus::Failed);
+    assert!(batch.error.is_some());
+
+    server
+        .get(&BatchPath { id: batch.id + 1 }.to_uri().path())
+        .await
+        .assert_status(axum::http::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[cfg(feature = "deposit-mock")]
+async fn test_get_account_with_proof() {
+    use kairos_circuit_logic::{
+        account_trie::{Account, AccountTrie},
+        transactions::L1Deposit,
+    };
+    use kairos_server::{
+        routes::account::AccountPath,
+        state::{AccountProof, AccountState, PendingChange, TrieStateThreadMsg},
+    };
+    use kairos_trie::DigestHasher;
+    use sha2::Sha256;
+
+    fn check_proof(proof: &AccountProof) {
+        let trie = AccountTrie::new_try_from_snapshot(&proof.snapshot).unwrap();
+        let root = trie
+            .txn
+            .calc_root_hash(&mut DigestHasher::<Sha256>::default())
+            .unwrap();
+        assert_eq!(Option::<[u8; 32]>::from(root), proof.root);
+    }
+
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let state = new_test_state(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+    let server = new_test_server(state.clone());
+
+    let alice_public_key = load_signer("testdata/users/user-2")
+        .to_public_key()
+        .unwrap();
+    let alice_path = AccountPath {
+        public_key: hex::encode(&alice_public_key),
+    };
+
+    server
+        .post(MockDepositPath.to_uri().path())
+        .json(&L1Deposit {
+            recipient: alice_public_key.clone(),
+            amount: 100,
+        })
+        .await
+        .assert_status_success();
+
+    // The deposit is not committed yet, so it's only part of the pending change.
+    let account = server
+        .get(&alice_path.to_uri().path())
+        .await
+        .json::<AccountState>();
+    assert_eq!((account.balance, account.nonce), (0, 0));
+    assert_eq!(
+        account.pending,
+        PendingChange {
+            balance: 100,
+            nonce: 0
+        }
+    );
+    assert_eq!(account.proof.root, None);
+    assert_eq!(account.proof.account, None);
+    assert_eq!(account.l1_proof, None);
+    check_proof(&account.proof);
+
+    let (msg, response) = TrieStateThreadMsg::commit();
+    state
+        .batch_state_manager
+        .queued_transactions
+        .send(msg)
+        .await
+        .unwrap();
+    response.await.unwrap().unwrap();
+
+    let account = server
+        .get(&alice_path.to_uri().path())
+        .await
+        .json::<AccountState>();
+    assert_eq!((account.balance, account.nonce), (100, 0));
+    assert_eq!(account.pending, PendingChange::default());
+    assert!(account.proof.root.is_some());
+    assert_eq!(account.proof.account, Some(Account::new(100, 0)));
+    check_proof(&account.proof);
+
+    // The proof against the L1 root comes from the root cached by the L1 sync.
+    let committed_root = account.proof.root;
+    state.l1_sync_status.set_l1_root(committed_root.into());
+    let account = server
+        .get(&alice_path.to_uri().path())
+        .await
+        .json::<AccountState>();
+    let l1_proof = account.l1_proof.unwrap();
+    assert_eq!(l1_proof.root, committed_root);
+    assert_eq!(l1_proof.account, Some(Account::new(100, 0)));
+    check_proof(&l1_proof);
+
+    // Unknown accounts come with a proof of absence.
+    let unknown = server
+        .get(
+            &AccountPath {
+                public_key: hex::encode("unknown"),
+            }
+            .to_uri()
+            .path(),
+        )
+        .await
+        .json::<AccountState>();
+    assert_eq!((unknown.balance, unknown.nonce), (0, 0));
+    assert_eq!(unknown.pending, PendingChange::default());
+    assert_eq!(unknown.proof.root, account.proof.root);
+    assert_eq!(unknown.proof.account, None);
+    check_proof(&unknown.proof);
+
+    server
+        .get("/api/v1/accounts/not-hex")
+        .await
+        .assert_status(axum::http::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_events_are_published() {
+    use kairos_circuit_logic::transactions::{
+        KairosTransaction, L1Deposit, Signed, Transfer as L2Transfer,
+    };
+    use kairos_server::state::{events::ServerEvent, TrieStateThreadMsg};
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let server_config = test_server_config(&dummy_url, &dummy_url);
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    let mut events = batch_state_manager.events.subscribe();
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let bob_public_key = "bob_key".as_bytes().to_vec();
+    let deposit = KairosTransaction::Deposit(L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    });
+    let transfer = KairosTransaction::Transfer(Signed {
+        public_key: alice_public_key.clone(),
+        nonce: 0,
+        transaction: L2Transfer {
+            recipient: bob_public_key.clone(),
+            amount: 500,
+        },
+    });
+
+    batch_state_manager
+        .enqueue_transaction(deposit.clone())
+        .await
+        .unwrap();
+    // Alice can't afford the transfer.
+    batch_state_manager
+        .enqueue_transaction(transfer.clone())
+        .await
+        .unwrap_err();
+    let (msg, response) = TrieStateThreadMsg::commit();
+    batch_state_manager
+        .queued_transactions
+        .send(msg)
+        .await
+        .unwrap();
+    let batch_output = response.await.unwrap().unwrap();
+
+    assert_eq!(
+        events.recv().await.unwrap(),
+        ServerEvent::TransactionAccepted {
+            transaction: deposit.clone()
+        }
+    );
+    let rejected = events.recv().await.unwrap();
+    assert!(matches!(
+        &rejected,
+        ServerEvent::TransactionRejected { transaction, .. } if *transaction == transfer
+    ));
+    let committed = events.recv().await.unwrap();
+    assert_eq!(
+        committed,
+        ServerEvent::BatchCommitted {
+            batch_id: None,
+            old_root: None,
+            new_root: Option::<[u8; 32]>::from(batch_output.new_root).map(hex::encode),
+            transactions: vec![deposit].into(),
+        }
+    );
+
+    // The rejected transfer involves Bob, the committed batch does not.
+    assert!(rejected.involves(&bob_public_key));
+    assert!(!committed.involves(&bob_public_key));
+    assert!(committed.involves(&alice_public_key));
+    assert!(!committed.involves(b"carol_key"));
+}
+
+#[tokio::test]
+async fn test_future_nonces_are_held_until_the_gap_fills() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit, Signed, Withdraw};
+    use kairos_server::state::transaction_status::{transaction_hash, TransactionStatus};
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url);
+    server_config.batch_config.mempool_expiry = Duration::from_millis(200);
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let withdraw = |nonce| {
+        KairosTransaction::Withdraw(Signed {
+            public_key: alice_public_key.clone(),
+            nonce,
+            transaction: Withdraw { amount: 10 },
+        })
+    };
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    batch_state_manager
+        .enqueue_transaction(KairosTransaction::Deposit(L1Deposit {
+            recipient: alice_public_key.clone(),
+            amount: 100,
+        }))
+        .await
+        .unwrap();
+
+    // Nonce 1 reaches the trie thread first, it's held until nonce 0 is executed.
+    let (second, first) = tokio::join!(
+        batch_state_manager.enqueue_transaction(withdraw(1)),
+        batch_state_manager.enqueue_transaction(withdraw(0)),
+    );
+    first.unwrap();
+    second.unwrap();
+    let nonce = batch_state_manager
+        .get_nonce_for(alice_public_key.clone())
+        .await
+        .unwrap();
+    assert_eq!(nonce, 2);
+
+    // Nonces too far ahead are rejected right away.
+    batch_state_manager
+        .enqueue_transaction(withdraw(1000))
+        .await
+        .unwrap_err();
+
+    // Nonce 2 never arrives, so nonce 3 expires.
+    // The submitter is answered as soon as the transaction is held.
+    batch_state_manager
+        .enqueue_transaction(withdraw(3))
+        .await
+        .unwrap();
+    let hash = transaction_hash(&withdraw(3)).unwrap();
+    assert_eq!(
+        batch_state_manager.transaction_statuses.get(&hash),
+        Some(TransactionStatus::Held)
+    );
+    let mut status = None;
+    for _ in 0..50 {
+        status = batch_state_manager.transaction_statuses.get(&hash);
+        if status != Some(TransactionStatus::Held) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(
+        matches!(status, Some(TransactionStatus::Rejected { .. })),
+        "Held transaction never expired: {:?}",
+        status
+    );
+
+    batch_state_manager
+        .enqueue_transaction(withdraw(2))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[cfg(feature = "deposit-mock")]
+async fn test_transaction_status() {
+    use kairos_circuit_logic::transactions::L1Deposit;
+    use kairos_server::{
+        routes::transaction_status::TransactionStatusPath,
+        state::{
+            transaction_status::{TransactionReceipt, TransactionStatus},
+            TrieStateThreadMsg,
+        },
+    };
+
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let state = new_test_state(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+    let server = new_test_server(state.clone());
+
+    let alice = load_signer("testdata/users/user-2");
+    let alice_public_key = alice.to_public_key().unwrap();
+    server
+        .post(MockDepositPath.to_uri().path())
+        .json(&L1Deposit {
+            recipient: alice_public_key.clone(),
+            amount: 100,
+        })
+        .await
+        .assert_status_success();
+
+    let payload = SigningPayload::new(0, Withdrawal::new(50));
+    let hash = hex::encode(payload.hash().unwrap());
+    let receipt = server
+        .post(WithdrawPath.to_uri().path())
+        .json(&sign_payload(&alice, payload))
+        .await
+        .json::<TransactionReceipt>();
+    assert_eq!(
+        receipt,
+        TransactionReceipt {
+            hash: hash.clone(),
+            status: TransactionStatus::Queued,
+        }
+    );
+
+    let status_path = TransactionStatusPath { hash };
+    let receipt = server
+        .get(&status_path.to_uri().path())
+        .await
+        .json::<TransactionReceipt>();
+    assert_eq!(receipt.status, TransactionStatus::Queued);
+
+    // Submitting the same withdrawal again is rejected, but the original stays queued.
+    let response = server
+        .post(WithdrawPath.to_uri().path())
+        .json(&sign_payload(
+            &alice,
+            SigningPayload::new(0, Withdrawal::new(50)),
+        ))
+        .await;
+    response.assert_status_failure();
+    let rejection = response.json::<TransactionReceipt>();
+    assert_eq!(rejection.hash, hash);
+    assert!(matches!(
+        rejection.status,
+        TransactionStatus::Rejected { .. }
+    ));
+    let receipt = server
+        .get(&status_path.to_uri().path())
+        .await
+        .json::<TransactionReceipt>();
+    assert_eq!(receipt.status, TransactionStatus::Queued);
+
+    let (msg, response) = TrieStateThreadMsg::commit();
+    state
+        .batch_state_manager
+        .queued_transactions
+        .send(msg)
+        .await
+        .unwrap();
+    response.await.unwrap().unwrap();
+
+    // Statuses are updated asynchronously from the event stream.
+    let mut status = TransactionStatus::Queued;
+    for _ in 0..50 {
+        status = server
+            .get(&status_path.to_uri().path())
+            .await
+            .json::<TransactionReceipt>()
+            .status;
+        if status != TransactionStatus::Queued {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(matches!(status, TransactionStatus::Included { .. }));
+
+    server
+        .get(
+            &TransactionStatusPath {
+                hash: hex::encode([0u8; 32]),
+            }
+            .to_uri()
+            .path(),
+        )
+        .await
+        .assert_status(axum::http::StatusCode::NOT_FOUND);
+    server
+        .get("/api/v1/transactions/not-hex")
+        .await
+        .assert_status(axum::http::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_admin_api() {
+    use axum::http::{header::AUTHORIZATION, HeaderValue};
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit, Signed, Withdraw};
+    use kairos_server::{
+        config::AdminConfig,
+        routes::admin::{
+            AdminCommitPath, AdminDrainPath, AdminPauseIntakePath, AdminResumeIntakePath,
+            AdminStatsPath, CommitResponse, DrainResponse,
+        },
+        state::{
+            transaction_status::{transaction_hash, TransactionStatus},
+            TrieThreadStats,
+        },
+    };
+
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let mut server_config = test_server_config(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    );
+    server_config.admin_config = Some(AdminConfig {
+        socket_addr: "0.0.0.0:0".parse().unwrap(),
+        token: "secret".to_string(),
+    });
+    let state = new_test_state_from_config(server_config).await;
+    let admin = TestServer::new_with_config(
+        kairos_server::admin_router(state.clone()),
+        TestServerConfig::builder().mock_transport().build(),
+    )
+    .unwrap();
+    let token = HeaderValue::from_static("Bearer secret");
+
+    admin
+        .post(AdminCommitPath.to_uri().path())
+        .await
+        .assert_status(axum::http::StatusCode::UNAUTHORIZED);
+    admin
+        .post(AdminCommitPath.to_uri().path())
+        .add_header(AUTHORIZATION, HeaderValue::from_static("Bearer wrong"))
+        .await
+        .assert_status(axum::http::StatusCode::UNAUTHORIZED);
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let l1_deposit = L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    };
+    let deposit = KairosTransaction::Deposit(l1_deposit.clone());
+
+    admin
+        .post(AdminPauseIntakePath.to_uri().path())
+        .add_header(AUTHORIZATION, token.clone())
+        .await
+        .assert_status_ok();
+    state
+        .batch_state_manager
+        .enqueue_transaction(deposit.clone())
+        .await
+        .unwrap_err();
+    // Deposits are deferred, the L1 sync retries their event once intake resumes.
+    let deferred = state
+        .batch_state_manager
+        .enqueue_deposit(0, l1_deposit.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        deferred.status(),
+        Some(axum::http::StatusCode::SERVICE_UNAVAILABLE)
+    );
+    assert_eq!(
+        state.batch_state_manager.next_event_index().await.unwrap(),
+        None
+    );
+    let stats = admin
+        .get(AdminStatsPath.to_uri().path())
+        .add_header(AUTHORIZATION, token.clone())
+        .await
+        .json::<TrieThreadStats>();
+    assert!(stats.intake_paused);
+    assert_eq!(stats.batched_transactions, 0);
+
+    admin
+        .post(AdminResumeIntakePath.to_uri().path())
+        .add_header(AUTHORIZATION, token.clone())
+        .await
+        .assert_status_ok();
+    state
+        .batch_state_manager
+        .enqueue_transaction(deposit)
+        .await
+        .unwrap();
+    assert!(state
+        .batch_state_manager
+        .enqueue_deposit(0, l1_deposit)
+        .await
+        .unwrap());
+
+    // A withdrawal waiting for lower nonces is rejected by the drain.
+    let held = KairosTransaction::Withdraw(Signed {
+        public_key: alice_public_key,
+        nonce: 3,
+        transaction: Withdraw { amount: 10 },
+    });
+    state
+        .batch_state_manager
+        .enqueue_transaction(held.clone())
+        .await
+        .unwrap();
+    let drain = admin
+        .post(AdminDrainPath.to_uri().path())
+        .add_header(AUTHORIZATION, token.clone())
+        .await
+        .json::<DrainResponse>();
+    assert_eq!(drain.drained, 1);
+    assert!(matches!(
+        state
+            .batch_state_manager
+            .transaction_statuses
+            .get(&transaction_hash(&held).unwrap()),
+        Some(TransactionStatus::Rejected { .. })
+    ));
+
+    let commit = admin
+        .post(AdminCommitPath.to_uri().path())
+        .add_header(AUTHORIZATION, token.clone())
+        .await
+        .json::<CommitResponse>();
+    assert_eq!(commit.transactions, 2);
+    assert!(commit.new_root.is_some());
+
+    let stats = admin
+        .get(AdminStatsPath.to_uri().path())
+        .add_header(AUTHORIZATION, token)
+        .await
+        .json::<TrieThreadStats>();
+    assert!(!stats.intake_paused);
+    assert_eq!(stats.batched_transactions, 0);
+    assert_eq!(stats.held_transactions, 0);
+    assert_eq!(stats.batch_root, commit.new_root);
+}
+
+#[tokio::test]
+async fn test_batch_is_committed_after_max_batch_duration_without_traffic() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+    use kairos_server::state::events::ServerEvent;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url);
+    server_config.batch_config.max_batch_duration = Some(Duration::from_millis(200));
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    let mut events = batch_state_manager.events.subscribe();
+
+    let deposit = KairosTransaction::Deposit(L1Deposit {
+        recipient: "alice_key".as_bytes().to_vec(),
+        amount: 100,
+    });
+    batch_state_manager
+        .enqueue_transaction(deposit.clone())
+        .await
+        .unwrap();
+
+    // No further transactions arrive, the batch is committed by the timer alone.
+    let committed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let event @ ServerEvent::BatchCommitted { .. } = events.recv().await.unwrap() {
+                break event;
+            }
+        }
+    })
+    .await
+    .expect("batch was not committed after max_batch_duration");
+    assert!(matches!(
+        committed,
+        ServerEvent::BatchCommitted { transactions, .. } if *transactions == [deposit]
+    ));
+
+    let stats = batch_state_manager.stats().await.unwrap();
+    assert_eq!(stats.batched_transactions, 0);
+}
+
+#[tokio::test]
+async fn test_batches_are_proven_by_the_native_prover() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+    use kairos_server::state::events::ServerEvent;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url);
+    server_config.batch_config.prover = ProverKind::Native;
+    server_config.batch_config.proving_servers = Vec::new();
+    server_config.batch_config.max_batch_size = Some(1);
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    assert!(batch_state_manager.prover_pool.is_none());
+    let mut events = batch_state_manager.events.subscribe();
+
+    batch_state_manager
+        .enqueue_transaction(KairosTransaction::Deposit(L1Deposit {
+            recipient: "alice_key".as_bytes().to_vec(),
+            amount: 100,
+        }))
+        .await
+        .unwrap();
+
+    // No proving server is running, the batch is proven in process.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let ServerEvent::ProofProduced { .. } = events.recv().await.unwrap() {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("batch was not proven by the native prover");
+}
+
+#[tokio::test]
+async fn test_batch_is_committed_once_it_reaches_its_cost_budget() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit};
+    use kairos_server::state::events::ServerEvent;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let mut server_config = test_server_config(&dummy_url, &dummy_url);
+    // Any account path exceeds this, so every transaction fills the batch.
+    server_config.batch_config.max_batch_snapshot_bytes = Some(1);
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    let mut events = batch_state_manager.events.subscribe();
+
+    let deposit = KairosTransaction::Deposit(L1Deposit {
+        recipient: "alice_key".as_bytes().to_vec(),
+        amount: 100,
+    });
+    batch_state_manager
+        .enqueue_transaction(deposit.clone())
+        .await
+        .unwrap();
+
+    let committed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let event @ ServerEvent::BatchCommitted { .. } = events.recv().await.unwrap() {
+                break event;
+            }
+        }
+    })
+    .await
+    .expect("batch was not committed after reaching its cost budget");
+    assert!(matches!(
+        committed,
+        ServerEvent::BatchCommitted { transactions, .. } if *transactions == [deposit]
+    ));
+
+    let stats = batch_state_manager.stats().await.unwrap();
+    assert_eq!(stats.batched_transactions, 0);
+    assert_eq!(stats.batch_snapshot_bytes, Some(0));
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    use kairos_circuit_logic::transactions::{KairosTransaction, L1Deposit, Signed, Withdraw};
+    use kairos_server::routes::metrics::MetricsPath;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let state = new_test_state(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+    let server = new_test_server(state.clone());
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    state
+        .batch_state_manager
+        .enqueue_transaction(KairosTransaction::Deposit(L1Deposit {
+            recipient: alice_public_key.clone(),
+            amount: 100,
+        }))
+        .await
+        .unwrap();
+    // Alice can't afford the withdrawal.
+    state
+        .batch_state_manager
+        .enqueue_transaction(KairosTransaction::Withdraw(Signed {
+            public_key: alice_public_key,
+            nonce: 0,
+            transaction: Withdraw { amount: 500 },
+        }))
+        .await
+        .unwrap_err();
+    state.batch_state_manager.commit().await.unwrap();
+
+    // The metrics are updated from the event bus, so they may lag behind.
+    let expected = [
+        "kairos_server_transactions_accepted_total{type=\"deposit\"} 1",
+        "kairos_server_transactions_rejected_total{type=\"withdraw\"} 1",
+        "kairos_server_batch_size_count 1",
+        "kairos_server_queued_transactions 0",
+        "kairos_server_pending_deposit_deploys 0",
+    ];
+    let mut metrics = String::new();
+    for _ in 0..100 {
+        let response = server.get(MetricsPath.to_uri().path()).await;
+        response.assert_status_ok();
+        metrics = response.text();
+        if expected.iter().all(|line| metrics.contains(line)) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    for line in expected {
+        assert!(metrics.contains(line), "missing `{line}` in:\n{metrics}");
+    }
+}
+
+#[tokio::test]
+async fn test_health_and_readiness() {
+    use kairos_server::routes::health::{HealthPath, HealthReport, ReadyPath};
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let state = new_test_state(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+    let server = new_test_server(state);
+
+    let health = server.get(HealthPath.to_uri().path()).await;
+    health.assert_status_ok();
+    let health = health.json::<HealthReport>();
+    assert!(health.ok);
+    assert!(health.checks["trie_thread"].ok);
+    assert!(health.checks["batch_output_handler"].ok);
+
+    // The test config points to a proving server that is not running.
+    let ready = server.get(ReadyPath.to_uri().path()).await;
+    ready.assert_status(axum::http::StatusCode::SERVICE_UNAVAILABLE);
+    let ready = ready.json::<HealthReport>();
+    assert!(!ready.ok);
+    assert!(!ready.checks["proving_server"].ok);
+    // Without a contract hash the L1 sync is disabled, which is not a failure.
+    assert!(ready.checks["l1_sync"].ok);
+    assert!(ready.checks["proof_submission"].ok);
+    #[cfg(feature = "database")]
+    assert!(ready.checks["database"].ok);
+}
+
+#[tokio::test]
+async fn test_deposit_status() {
+    use kairos_circuit_logic::transactions::L1Deposit;
+    use kairos_server::routes::deposit::DepositStatusPath;
+    use kairos_server::state::deposit_status::DepositStatus;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let postgres = PostgresDB::run(None).unwrap();
+    let state = new_test_state(
+        &dummy_url,
+        &dummy_url,
+        #[cfg(feature = "database")]
+        &postgres.connection.clone().into(),
+    )
+    .await;
+    let server = new_test_server(state.clone());
+    let status_path = |deploy_hash: [u8; 32]| {
+        DepositStatusPath {
+            deploy_hash: hex::encode(deploy_hash),
+        }
+        .to_uri()
+        .path()
+        .to_string()
+    };
+
+    let deposit = L1Deposit {
+        recipient: "alice_key".as_bytes().to_vec(),
+        amount: 100,
+    };
+    let deposits = &state.deposit_statuses;
+    deposits.forwarded([1; 32], Some(deposit.clone()));
+    deposits.forwarded([2; 32], Some(deposit.clone()));
+    deposits.forwarded([3; 32], Some(deposit.clone()));
+
+    server
+        .get(&status_path([0; 32]))
+        .await
+        .assert_status(axum::http::StatusCode::NOT_FOUND);
+    server
+        .get("/api/v1/deposits/not-hex")
+        .await
+        .assert_status(axum::http::StatusCode::BAD_REQUEST);
+    assert_eq!(
+        server
+            .get(&status_path([1; 32]))
+            .await
+            .json::<DepositStatus>(),
+        DepositStatus::Pending
+    );
+
+    deposits.executed(&[1; 32], None);
+    deposits.executed(&[2; 32], Some("User error: 1".to_string()));
+    assert_eq!(
+        server
+            .get(&status_path([1; 32]))
+            .await
+            .json::<DepositStatus>(),
+        DepositStatus::Executed
+    );
+    assert_eq!(
+        server
+            .get(&status_path([2; 32]))
+            .await
+            .json::<DepositStatus>(),
+        DepositStatus::Failed {
+            error: "User error: 1".to_string()
+        }
+    );
+
+    // Events are matched to the oldest deploy awaiting credit, failed deploys are skipped.
+    assert!(deposits.credited(7, &deposit));
+    assert!(deposits.credited(8, &deposit));
+    assert!(!deposits.credited(9, &deposit));
+    assert_eq!(
+        server
+            .get(&status_path([1; 32]))
+            .await
+            .json::<DepositStatus>(),
+        DepositStatus::Processed {
+            event_index: 7,
+            credit: deposit.clone()
+        }
+    );
+    assert_eq!(
+        server
+            .get(&status_path([3; 32]))
+            .await
+            .json::<DepositStatus>(),
+        DepositStatus::Processed {
+            event_index: 8,
+            credit: deposit
+        }
+    );
+    assert_eq!(deposits.pending(), 0);
+}
+
+#[tokio::test]
+async fn test_deposits_are_credited_once_per_event() {
+    use kairos_circuit_logic::transactions::L1Deposit;
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    #[cfg(feature = "database")]
+    let server_config = test_server_config(&dummy_url, &dummy_url, &dummy_url);
+    #[cfg(not(feature = "database"))]
+    let server_config = test_server_config(&dummy_url, &dummy_url);
+
+    let alice_public_key = "alice_key".as_bytes().to_vec();
+    let deposit = L1Deposit {
+        recipient: alice_public_key.clone(),
+        amount: 100,
+    };
+
+    let batch_state_manager = BatchStateManager::new_empty(&server_config).unwrap();
+    assert_eq!(batch_state_manager.next_event_index().await.unwrap(), None);
+
+    assert!(batch_state_manager
+        .enqueue_deposit(3, deposit.clone())
+        .await
+        .unwrap());
+    // The same event, e.g. processed again after a restart.
+    assert!(!batch_state_manager
+        .enqueue_deposit(3, deposit.clone())
+        .await
+        .unwrap());
+    assert!(!batch_state_manager
+        .enqueue_deposit(2, deposit.clone())
+        .await
+        .unwrap());
+    assert!(batch_state_manager
+        .enqueue_deposit(4, deposit)
+        .await
+        .unwrap());
+    assert_eq!(
+        batch_state_manager.next_event_index().await.unwrap(),
+        Some(5)
+    );
+
+    let account = batch_state_manager
+        .get_account(alice_public_key, None)
+        .await
+        .unwrap();
+    assert_eq!(account.balance, 200);
+}
+
+#[tokio::test]
+#[cfg(feature = "database")]
+async fn test_event_cursor_survives_restart() {
+    use kairos_circuit_logic::transactions::L1Deposit;
+    use kairos_data::transaction as db;
+
+    let postgres = PostgresDB::run(None).unwrap();
+    let postgres_url: Url = postgres.connection.clone().into();
+    let pool = new_pool(postgres_url.as_ref())
+        .await
+        .expect("Failed to connect to database");
+
+    let dummy_url = Url::parse("http://0.0.0.0:0").unwrap();
+    let server_config = test_server_config(&dummy_url, &dummy_url, &postgres_url);
+    let deposit = L1Deposit {
+        recipient: "alice_key".as_bytes().to_vec(),
+        amount: 100,
+    };
+
+    {
+        let batch_state_manager =
+            BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+        assert!(batch_state_manager
+            .enqueue_deposit(0, deposit.clone())
+            .await
+            .unwrap());
+        batch_state_manager.commit().await.unwrap();
+    }
+
+    // The cursor was committed along with the trie root.
+    let batch_state_manager =
+        BatchStateManager::new_persistent(&server_config, pool.clone()).unwrap();
+    assert_eq!(
+        batch_state_manager.next_event_index().await.unwrap(),
+        Some(1)
+    );
+    assert!(!batch_state_manager
+        .enqueue_deposit(0, deposit)
+        .await
+        .unwrap());
+
+    // The deposit was stored once, with its event.
+    let stored = db::get_executed(&pool).await.unwrap();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].event_index().unwrap(), Some(0));
+}
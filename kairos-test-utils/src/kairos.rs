@@ -7,7 +7,10 @@ use std::net::{SocketAddr, TcpListener};
 use std::time::Duration;
 use tokio::net::TcpStream;
 
-use kairos_server::config::{BatchConfig, ServerConfig};
+use kairos_server::config::{
+    BatchConfig, ProverKind, ProvingServer, ServerConfig, DEFAULT_MEMPOOL_EXPIRY,
+    DEFAULT_PROVING_TIMEOUT, DEFAULT_SUBMISSION_PAYMENT, DEFAULT_SUBMISSION_TTL,
+};
 
 async fn wait_for_port(address: &SocketAddr) -> Result<(), io::Error> {
     retry(ExponentialBackoff::default(), || async {
@@ -23,8 +26,9 @@ pub struct Kairos {
 }
 
 impl Kairos {
-    /// If no proving server is running, we will start the one at `BatchConfig.proving_server`.
-    /// The caller should ensure that `BatchConfig.proving_server == KAIROS_PROVER_SERVER_URL`.
+    /// If the `http` prover is configured and no proving server is running,
+    /// we will start the first one in `BatchConfig.proving_servers`.
+    /// The caller should ensure that it's the `KAIROS_PROVER_SERVER_URL`.
     pub async fn run(
         casper_rpc: &Url,
         casper_sse: &Url,
@@ -41,7 +45,16 @@ impl Kairos {
             .unwrap_or_else(|| BatchConfig {
                 max_batch_size: None,
                 max_batch_duration: None,
-                proving_server: Url::parse("http://127.0.0.1:7894").unwrap(),
+                max_batch_cycles: None,
+                max_batch_snapshot_bytes: None,
+                prover: ProverKind::Http,
+                proving_servers: vec![ProvingServer::new(
+                    Url::parse("http://127.0.0.1:7894").unwrap(),
+                )],
+                proving_timeout: DEFAULT_PROVING_TIMEOUT,
+                mempool_expiry: DEFAULT_MEMPOOL_EXPIRY,
+                submission_payment: DEFAULT_SUBMISSION_PAYMENT,
+                submission_ttl: DEFAULT_SUBMISSION_TTL,
             });
 
         let config = ServerConfig {
@@ -51,17 +64,23 @@ impl Kairos {
             casper_sse: casper_sse.clone(),
             // We want a short sync interval for tests.
             casper_sync_interval: Duration::from_secs(5),
+            // The local network doesn't reorganize, deposits can be credited right away.
+            casper_confirmations: 0,
             kairos_demo_contract_hash: kairos_demo_contract_hash.unwrap_or_default(),
             batch_config,
+            admin_config: None,
             #[cfg(feature = "database")]
             db_addr: db_addr.to_string(),
+            #[cfg(feature = "database")]
+            recover_l2_state: false,
         };
 
         let kairos_prover_server = match proving_server_batch_config {
             Some(batch_config)
-                if reqwest::get(batch_config.proving_server.clone())
-                    .await
-                    .is_err() =>
+                if batch_config.prover == ProverKind::Http
+                    && reqwest::get(batch_config.proving_servers[0].url.clone())
+                        .await
+                        .is_err() =>
             {
                 // Start the proving server if it's not providing any response.
                 // We don't care what the response is, we just want to know it's reachable.
@@ -71,6 +71,15 @@ in
       '';
     };
 
+    casperConfirmations = mkOption {
+      type = types.int;
+      default = 3;
+      example = 3;
+      description = ''
+        The number of blocks that must be added on top of the block of a deposit before it is credited on L2.
+      '';
+    };
+
     prover = mkOption {
       description = "Prover server related options";
       default = { };
@@ -189,6 +198,7 @@ in
           KAIROS_SERVER_CASPER_RPC = cfg.casperRpcUrl;
           KAIROS_SERVER_CASPER_SSE = cfg.casperSseUrl;
           KAIROS_SERVER_CASPER_SYNC_INTERVAL = builtins.toString cfg.casperSyncInterval;
+          KAIROS_SERVER_CASPER_CONFIRMATIONS = builtins.toString cfg.casperConfirmations;
           KAIROS_SERVER_DEMO_CONTRACT_HASH = cfg.demoContractHash;
           KAIROS_PROVER_SERVER_URL = "${cfg.prover.protocol}://${cfg.prover.bindAddress}:${builtins.toString cfg.prover.port}";
           KAIROS_SERVER_DB_ADDR = "postgresql://${cfg.database.userName}@localhost:${builtins.toString cfg.database.port}/${cfg.database.databaseName}?host=${cfg.database.host}";
@@ -129,7 +129,7 @@ nixosTest {
       transfer_amount = 1000
       beneficiary = client.succeed("cat ${clientUsersDirectory}/user-3/public_key_hex")
       transfer_output = client.succeed("kairos-cli --kairos-server-address http://kairos transfer --amount {} --recipient {} --private-key {}".format(transfer_amount, beneficiary, depositor_private_key))
-      assert "Transfer successfully sent to L2\n" in transfer_output, "The transfer command was not successful: {}".format(transfer_output)
+      assert "Transfer successfully sent to L2" in transfer_output, "The transfer command was not successful: {}".format(transfer_output)
 
       # data availability
       transactions_result = client.succeed("kairos-cli --kairos-server-address http://kairos fetch --recipient {}".format(beneficiary))
@@ -141,7 +141,7 @@ nixosTest {
       withdrawer = client.succeed("cat ${clientUsersDirectory}/user-3/public_key_hex")
       withdrawer_private_key = "${clientUsersDirectory}/user-3/secret_key.pem"
       withdraw_output = client.succeed("kairos-cli --kairos-server-address http://kairos withdraw --amount {} --private-key {}".format(withdrawal_amount, withdrawer_private_key))
-      assert "Withdrawal successfully sent to L2\n" in withdraw_output, "The withdraw command was not successful: {}".format(withdraw_output)
+      assert "Withdrawal successfully sent to L2" in withdraw_output, "The withdraw command was not successful: {}".format(withdraw_output)
 
       wait_for_transaction(withdrawer, "withdrawal", withdrawal_amount)
 